@@ -0,0 +1,37 @@
+use criterion::{criterion_group, criterion_main, BenchmarkId, Criterion, Throughput};
+use std::hint::black_box;
+
+use pngme::checksum;
+
+/// How chunk CRCs used to be calculated: a new `Crc` with a single lookup table for every chunk,
+/// over the chunk type and data concatenated into a new buffer
+fn concatenated_crc(chunk_type: &[u8; 4], chunk_data: &[u8]) -> u32 {
+    let mut bytes_to_calc: Vec<u8> = Vec::new();
+    bytes_to_calc.extend(chunk_type);
+    bytes_to_calc.extend(chunk_data);
+
+    let crc: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
+    crc.checksum(&bytes_to_calc)
+}
+
+fn bench_chunk_crc(c: &mut Criterion) {
+    let mut group = c.benchmark_group("chunk_crc");
+
+    for size in [1 << 10, 1 << 20, 8 << 20] {
+        let data: Vec<u8> = (0..size).map(|i| (i * 31 % 251) as u8).collect();
+        group.throughput(Throughput::Bytes(size as u64));
+
+        group.bench_with_input(BenchmarkId::new("concatenated", size), &data, |b, data| {
+            b.iter(|| concatenated_crc(black_box(b"IDAT"), black_box(data)))
+        });
+
+        group.bench_with_input(BenchmarkId::new("incremental", size), &data, |b, data| {
+            b.iter(|| checksum::chunk_crc(black_box(b"IDAT"), black_box(data)))
+        });
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, bench_chunk_crc);
+criterion_main!(benches);
@@ -0,0 +1,103 @@
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use std::{hint::black_box, str::FromStr};
+
+use pngme::{chunk::Chunk, chunk_type::ChunkType, png::Png, png_ref::PngRef, selector::Selector};
+
+fn chunk(chunk_type: &str, data: Vec<u8>) -> Chunk {
+    Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data)
+}
+
+/// Builds a PNG with a valid header, image data split in `idat_count` chunks of `idat_size`
+/// bytes, and `extra_count` extra chunks of `extra_size` bytes
+fn generate_png(
+    idat_count: usize,
+    idat_size: usize,
+    extra_type: &str,
+    extra_count: usize,
+    extra_size: usize,
+) -> Png {
+    let mut ihdr = Vec::new();
+    ihdr.extend(1024u32.to_be_bytes());
+    ihdr.extend(1024u32.to_be_bytes());
+    ihdr.extend([8, 6, 0, 0, 0]);
+
+    let mut chunks = vec![chunk("IHDR", ihdr)];
+    for i in 0..extra_count {
+        chunks.push(chunk(extra_type, vec![i as u8; extra_size]));
+    }
+    for i in 0..idat_count {
+        let data = (0..idat_size).map(|j| ((i + j) * 31 % 251) as u8).collect();
+        chunks.push(chunk("IDAT", data));
+    }
+    chunks.push(chunk("IEND", Vec::new()));
+
+    Png::from_chunks(chunks)
+}
+
+/// Images with different shapes: many tiny chunks, one huge IDAT chunk and many private chunks
+fn scenarios() -> Vec<(&'static str, Png)> {
+    vec![
+        (
+            "many_tiny_chunks",
+            generate_png(10_000, 16, "tEXt", 10_000, 4),
+        ),
+        ("huge_idat", generate_png(1, 16 << 20, "tEXt", 0, 0)),
+        (
+            "many_private_chunks",
+            generate_png(8, 64 << 10, "ruSt", 1_000, 64),
+        ),
+    ]
+}
+
+fn bench_png(c: &mut Criterion) {
+    let selector = Selector::from_str("ruSt").unwrap();
+
+    for (name, png) in scenarios() {
+        let bytes = png.as_bytes();
+
+        let mut group = c.benchmark_group(name);
+        group.sample_size(20);
+        group.throughput(Throughput::Bytes(bytes.len() as u64));
+
+        group.bench_function("parse", |b| {
+            b.iter(|| Png::try_from(black_box(&bytes[..])).unwrap())
+        });
+
+        group.bench_function("parse_ref", |b| {
+            b.iter(|| {
+                let png = PngRef::try_from(black_box(&bytes[..])).unwrap();
+                png.chunks()
+                    .map(|chunk| chunk.unwrap().length())
+                    .sum::<u32>()
+            })
+        });
+
+        group.bench_function("serialize", |b| b.iter(|| black_box(&png).as_bytes()));
+
+        group.bench_function("encode", |b| {
+            b.iter_batched(
+                || png.clone(),
+                |mut png| {
+                    let index = png.chunks().len() - 1;
+                    png.insert_chunk(index, chunk("ruSt", b"Hidden message".to_vec()));
+                    png.as_bytes()
+                },
+                BatchSize::LargeInput,
+            )
+        });
+
+        group.bench_function("decode", |b| {
+            b.iter(|| {
+                let png = Png::try_from(black_box(&bytes[..])).unwrap();
+                png.select(&selector)
+                    .filter_map(|(_, chunk)| chunk.data_as_string().ok())
+                    .collect::<Vec<_>>()
+            })
+        });
+
+        group.finish();
+    }
+}
+
+criterion_group!(benches, bench_png);
+criterion_main!(benches);
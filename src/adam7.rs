@@ -0,0 +1,208 @@
+use crate::ihdr::Ihdr;
+
+/// One of the seven passes of Adam7 interlacing, made of the pixels starting at
+/// (`x_start`, `y_start`) and repeating every `x_step` columns and `y_step` rows
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Pass {
+    pub x_start: u32,
+    pub y_start: u32,
+    pub x_step: u32,
+    pub y_step: u32,
+}
+
+impl Pass {
+    /// Number of pixels in each row of the pass
+    pub fn width(&self, image_width: u32) -> u32 {
+        image_width
+            .saturating_sub(self.x_start)
+            .div_ceil(self.x_step)
+    }
+
+    /// Number of rows in the pass. A pass without any pixel in its rows has no rows.
+    pub fn height(&self, image_width: u32, image_height: u32) -> u32 {
+        if self.width(image_width) == 0 {
+            return 0;
+        }
+
+        image_height
+            .saturating_sub(self.y_start)
+            .div_ceil(self.y_step)
+    }
+}
+
+pub const PASSES: [Pass; 7] = [
+    Pass {
+        x_start: 0,
+        y_start: 0,
+        x_step: 8,
+        y_step: 8,
+    },
+    Pass {
+        x_start: 4,
+        y_start: 0,
+        x_step: 8,
+        y_step: 8,
+    },
+    Pass {
+        x_start: 0,
+        y_start: 4,
+        x_step: 4,
+        y_step: 8,
+    },
+    Pass {
+        x_start: 2,
+        y_start: 0,
+        x_step: 4,
+        y_step: 4,
+    },
+    Pass {
+        x_start: 0,
+        y_start: 2,
+        x_step: 2,
+        y_step: 4,
+    },
+    Pass {
+        x_start: 1,
+        y_start: 0,
+        x_step: 2,
+        y_step: 2,
+    },
+    Pass {
+        x_start: 0,
+        y_start: 1,
+        x_step: 1,
+        y_step: 2,
+    },
+];
+
+/// Copies pixel `from_x` of the row `from` into pixel `to_x` of the row `to`
+fn copy_pixel(from: &[u8], from_x: usize, to: &mut [u8], to_x: usize, bits_per_pixel: usize) {
+    if bits_per_pixel >= 8 {
+        let bytes = bits_per_pixel / 8;
+        to[to_x * bytes..(to_x + 1) * bytes]
+            .copy_from_slice(&from[from_x * bytes..(from_x + 1) * bytes]);
+        return;
+    }
+
+    // Pixels with less than 8 bits are packed from the most significant bit
+    let mask = (1u8 << bits_per_pixel) - 1;
+    let from_bit = from_x * bits_per_pixel;
+    let value = (from[from_bit / 8] >> (8 - bits_per_pixel - from_bit % 8)) & mask;
+
+    let to_bit = to_x * bits_per_pixel;
+    let shift = 8 - bits_per_pixel - to_bit % 8;
+    to[to_bit / 8] = (to[to_bit / 8] & !(mask << shift)) | (value << shift);
+}
+
+/// Splits the rows of a full image into the rows of each of the seven passes
+pub fn interlace(ihdr: &Ihdr, rows: &[Vec<u8>]) -> Vec<Vec<Vec<u8>>> {
+    let bits_per_pixel = ihdr.bits_per_pixel();
+
+    PASSES
+        .iter()
+        .map(|pass| {
+            let width = pass.width(ihdr.width());
+            let height = pass.height(ihdr.width(), ihdr.height());
+
+            (0..height)
+                .map(|pass_y| {
+                    let row = &rows[(pass.y_start + pass_y * pass.y_step) as usize];
+                    let mut pass_row = vec![0u8; ihdr.row_length(width)];
+                    for pass_x in 0..width {
+                        let x = pass.x_start + pass_x * pass.x_step;
+                        copy_pixel(
+                            row,
+                            x as usize,
+                            &mut pass_row,
+                            pass_x as usize,
+                            bits_per_pixel,
+                        );
+                    }
+                    pass_row
+                })
+                .collect()
+        })
+        .collect()
+}
+
+/// Merges the rows of the seven passes into the rows of the full image
+pub fn deinterlace(ihdr: &Ihdr, passes: &[Vec<Vec<u8>>]) -> Vec<Vec<u8>> {
+    let bits_per_pixel = ihdr.bits_per_pixel();
+    let mut rows = vec![vec![0u8; ihdr.row_length(ihdr.width())]; ihdr.height() as usize];
+
+    for (pass, pass_rows) in PASSES.iter().zip(passes) {
+        let width = pass.width(ihdr.width());
+
+        for (pass_y, pass_row) in pass_rows.iter().enumerate() {
+            let row = &mut rows[(pass.y_start + pass_y as u32 * pass.y_step) as usize];
+            for pass_x in 0..width {
+                let x = pass.x_start + pass_x * pass.x_step;
+                copy_pixel(pass_row, pass_x as usize, row, x as usize, bits_per_pixel);
+            }
+        }
+    }
+
+    rows
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    #[test]
+    fn test_pass_sizes() {
+        let sizes: Vec<(u32, u32)> = PASSES
+            .iter()
+            .map(|pass| (pass.width(8), pass.height(8, 8)))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![(1, 1), (1, 1), (2, 1), (2, 2), (4, 2), (4, 4), (8, 4)]
+        );
+
+        // Passes 2, 4 and 6 have no pixels in an image 1 pixel wide
+        let sizes: Vec<(u32, u32)> = PASSES
+            .iter()
+            .map(|pass| (pass.width(1), pass.height(1, 3)))
+            .collect();
+        assert_eq!(
+            sizes,
+            vec![(1, 1), (0, 0), (1, 0), (0, 0), (1, 1), (0, 0), (1, 1)]
+        );
+    }
+
+    #[test]
+    fn test_interlace_round_trip() {
+        let ihdr = Ihdr::new(11, 9, 8, ColorType::GrayscaleAlpha, true).unwrap();
+        let rows: Vec<Vec<u8>> = (0..9u8)
+            .map(|y| (0..22u8).map(|x| y * 22 + x).collect())
+            .collect();
+
+        let passes = interlace(&ihdr, &rows);
+        assert_eq!(
+            passes[0],
+            vec![vec![0, 1, 16, 17], vec![176, 177, 192, 193]]
+        );
+        assert_eq!(deinterlace(&ihdr, &passes), rows);
+    }
+
+    #[test]
+    fn test_interlace_round_trip_packed_pixels() {
+        let ihdr = Ihdr::new(13, 5, 2, ColorType::Grayscale, true).unwrap();
+        let rows: Vec<Vec<u8>> = (0..5u8)
+            .map(|y| {
+                vec![
+                    y.wrapping_mul(37),
+                    0b00_01_10_11,
+                    0b11_10_01_00,
+                    0b01_00_00_00,
+                ]
+            })
+            .collect();
+
+        let passes = interlace(&ihdr, &rows);
+        assert_eq!(passes[1][0], vec![0b00_01_00_00]);
+        assert_eq!(deinterlace(&ihdr, &passes), rows);
+    }
+}
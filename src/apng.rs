@@ -0,0 +1,866 @@
+use std::fmt::Display;
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, ihdr::Ihdr, png::Png};
+
+fn read_u32(data: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        data[offset],
+        data[offset + 1],
+        data[offset + 2],
+        data[offset + 3],
+    ])
+}
+
+fn read_u16(data: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([data[offset], data[offset + 1]])
+}
+
+/// Checks that `chunk` is of the chunk type `expected`
+fn check_chunk_type(chunk: &Chunk, expected: &[u8; 4]) -> Result<()> {
+    ensure!(
+        chunk.chunk_type().bytes() == *expected,
+        "Expected a {} chunk, found \"{}\"",
+        String::from_utf8_lossy(expected),
+        chunk.chunk_type()
+    );
+
+    Ok(())
+}
+
+fn new_chunk(chunk_type: &[u8; 4], data: Vec<u8>) -> Chunk {
+    // The APNG chunk types are valid, so this should not fail
+    let chunk_type = ChunkType::try_from(*chunk_type).expect("APNG chunk types are valid");
+    Chunk::new(chunk_type, data)
+}
+
+/// The animation control chunk (acTL), marking the image as an APNG
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct AnimationControl {
+    pub num_frames: u32,
+
+    /// Number of times the animation is played, 0 to loop forever
+    pub num_plays: u32,
+}
+
+impl AnimationControl {
+    pub const LENGTH: usize = 8;
+
+    pub fn as_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(Self::LENGTH);
+        data.extend(self.num_frames.to_be_bytes());
+        data.extend(self.num_plays.to_be_bytes());
+
+        new_chunk(b"acTL", data)
+    }
+}
+
+impl TryFrom<&Chunk> for AnimationControl {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        check_chunk_type(chunk, b"acTL")?;
+        let data = chunk.data();
+        ensure!(
+            data.len() == Self::LENGTH,
+            "Invalid acTL chunk, its data should have {} bytes",
+            Self::LENGTH
+        );
+
+        let control = Self {
+            num_frames: read_u32(data, 0),
+            num_plays: read_u32(data, 4),
+        };
+        ensure!(
+            control.num_frames > 0,
+            "Invalid acTL chunk, an animation should have at least one frame"
+        );
+
+        Ok(control)
+    }
+}
+
+/// How the area of a frame is cleared before rendering the next frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum DisposeOp {
+    /// The area is left as it is
+    None,
+
+    /// The area is cleared to fully transparent black
+    Background,
+
+    /// The area is reverted to what it was before rendering the frame
+    Previous,
+}
+
+impl TryFrom<u8> for DisposeOp {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(DisposeOp::None),
+            1 => Ok(DisposeOp::Background),
+            2 => Ok(DisposeOp::Previous),
+            _ => bail!("Invalid dispose operation {}", value),
+        }
+    }
+}
+
+impl Display for DisposeOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            DisposeOp::None => "none",
+            DisposeOp::Background => "background",
+            DisposeOp::Previous => "previous",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// How a frame is rendered over the output buffer
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum BlendOp {
+    /// The frame replaces the area it covers, including its alpha
+    Source,
+
+    /// The frame is alpha-composited over the area it covers
+    Over,
+}
+
+impl TryFrom<u8> for BlendOp {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(BlendOp::Source),
+            1 => Ok(BlendOp::Over),
+            _ => bail!("Invalid blend operation {}", value),
+        }
+    }
+}
+
+impl Display for BlendOp {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            BlendOp::Source => "source",
+            BlendOp::Over => "over",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The frame control chunk (fcTL), describing the geometry and timing of a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct FrameControl {
+    pub sequence_number: u32,
+    pub width: u32,
+    pub height: u32,
+    pub x_offset: u32,
+    pub y_offset: u32,
+
+    /// Numerator of the frame delay, in seconds
+    pub delay_num: u16,
+
+    /// Denominator of the frame delay, 0 meaning 100 (the delay is in hundredths of a second)
+    pub delay_den: u16,
+
+    pub dispose_op: DisposeOp,
+    pub blend_op: BlendOp,
+}
+
+impl FrameControl {
+    pub const LENGTH: usize = 26;
+
+    /// Converts a delay in milliseconds to the numerator and denominator of a fcTL chunk, using
+    /// the largest denominator that fits
+    pub fn delay_from_ms(ms: u32) -> Result<(u16, u16)> {
+        [(1000, 1), (10, 100), (1, 1000)]
+            .into_iter()
+            .filter(|(ms_per_unit, _)| ms.is_multiple_of(*ms_per_unit))
+            .find_map(|(ms_per_unit, den)| {
+                u16::try_from(ms / ms_per_unit).ok().map(|num| (num, den))
+            })
+            .context(format!("Invalid frame delay {} ms, it's too long", ms))
+    }
+
+    /// How long the frame is displayed, in milliseconds
+    pub fn delay_ms(&self) -> f64 {
+        let den = if self.delay_den == 0 {
+            100
+        } else {
+            self.delay_den
+        };
+        self.delay_num as f64 * 1000.0 / den as f64
+    }
+
+    /// Checks that the frame is not empty and fits within the image
+    pub fn validate(&self, ihdr: &Ihdr) -> Result<()> {
+        ensure!(
+            self.width > 0 && self.height > 0,
+            "Invalid frame {}, its size should not be zero",
+            self.sequence_number
+        );
+        ensure!(
+            self.x_offset as u64 + self.width as u64 <= ihdr.width() as u64
+                && self.y_offset as u64 + self.height as u64 <= ihdr.height() as u64,
+            "Invalid frame {}, its {}x{} area at ({}, {}) doesn't fit in the {}x{} image",
+            self.sequence_number,
+            self.width,
+            self.height,
+            self.x_offset,
+            self.y_offset,
+            ihdr.width(),
+            ihdr.height()
+        );
+
+        Ok(())
+    }
+
+    pub fn as_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(Self::LENGTH);
+        data.extend(self.sequence_number.to_be_bytes());
+        data.extend(self.width.to_be_bytes());
+        data.extend(self.height.to_be_bytes());
+        data.extend(self.x_offset.to_be_bytes());
+        data.extend(self.y_offset.to_be_bytes());
+        data.extend(self.delay_num.to_be_bytes());
+        data.extend(self.delay_den.to_be_bytes());
+        data.push(self.dispose_op as u8);
+        data.push(self.blend_op as u8);
+
+        new_chunk(b"fcTL", data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameControl {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        check_chunk_type(chunk, b"fcTL")?;
+        let data = chunk.data();
+        ensure!(
+            data.len() == Self::LENGTH,
+            "Invalid fcTL chunk, its data should have {} bytes",
+            Self::LENGTH
+        );
+
+        Ok(Self {
+            sequence_number: read_u32(data, 0),
+            width: read_u32(data, 4),
+            height: read_u32(data, 8),
+            x_offset: read_u32(data, 12),
+            y_offset: read_u32(data, 16),
+            delay_num: read_u16(data, 20),
+            delay_den: read_u16(data, 22),
+            dispose_op: DisposeOp::try_from(data[24])?,
+            blend_op: BlendOp::try_from(data[25])?,
+        })
+    }
+}
+
+/// A frame data chunk (fdAT), holding part of the compressed image data of a frame like IDAT
+#[derive(Debug, Clone, PartialEq)]
+pub struct FrameData {
+    pub sequence_number: u32,
+    pub data: Vec<u8>,
+}
+
+impl FrameData {
+    pub fn as_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(self.data.len() + 4);
+        data.extend(self.sequence_number.to_be_bytes());
+        data.extend_from_slice(&self.data);
+
+        new_chunk(b"fdAT", data)
+    }
+}
+
+impl TryFrom<&Chunk> for FrameData {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        check_chunk_type(chunk, b"fdAT")?;
+        let data = chunk.data();
+        ensure!(
+            data.len() >= 4,
+            "Invalid fdAT chunk, it should start with a sequence number"
+        );
+
+        Ok(Self {
+            sequence_number: read_u32(data, 0),
+            data: data[4..].to_vec(),
+        })
+    }
+}
+
+/// A frame of an animation, with the indices of its chunks in the chunk list
+#[derive(Debug, Clone, PartialEq)]
+pub struct Frame {
+    pub control: FrameControl,
+
+    /// Index of the fcTL chunk
+    pub control_index: usize,
+
+    /// Indices of the IDAT or fdAT chunks holding the frame data, right after the fcTL chunk
+    pub data_indices: Vec<usize>,
+}
+
+impl Frame {
+    /// Checks if the frame data is stored in IDAT chunks, making the frame the default image
+    /// shown by viewers without APNG support
+    pub fn is_default_image(&self, chunks: &[Chunk]) -> bool {
+        self.data_indices
+            .first()
+            .is_some_and(|index| chunks[*index].chunk_type().bytes() == *b"IDAT")
+    }
+}
+
+/// The animation of an APNG, parsed from its acTL, fcTL and fdAT chunks
+#[derive(Debug, Clone, PartialEq)]
+pub struct Animation {
+    pub control: AnimationControl,
+    pub frames: Vec<Frame>,
+
+    /// Whether the IDAT chunks are the first frame, or a default image outside the animation
+    pub default_image_is_first_frame: bool,
+}
+
+impl Animation {
+    /// Parses the animation chunks of an image, returning `None` if it has no acTL chunk.
+    ///
+    /// The chunks are checked against the APNG spec: acTL comes before the first IDAT, frame
+    /// data follows its fcTL, the sequence numbers of the fcTL and fdAT chunks count up from 0
+    /// and every frame fits within the image.
+    pub fn parse(chunks: &[Chunk], ihdr: &Ihdr) -> Result<Option<Self>> {
+        let Some(actl_index) = chunks
+            .iter()
+            .position(|c| c.chunk_type().bytes() == *b"acTL")
+        else {
+            return Ok(None);
+        };
+
+        let control = AnimationControl::try_from(&chunks[actl_index])?;
+
+        let first_idat = chunks
+            .iter()
+            .position(|c| c.chunk_type().bytes() == *b"IDAT")
+            .context("Invalid APNG, the image has no IDAT chunk")?;
+        ensure!(
+            actl_index < first_idat,
+            "Invalid APNG, the acTL chunk should come before the first IDAT chunk"
+        );
+
+        let mut frames: Vec<Frame> = Vec::new();
+        let mut next_sequence_number = 0;
+        // Whether the chunk before the current one was part of the frame being read
+        let mut in_frame = false;
+
+        for (index, chunk) in chunks.iter().enumerate() {
+            match &chunk.chunk_type().bytes() {
+                b"fcTL" => {
+                    let frame_control = FrameControl::try_from(chunk)?;
+                    ensure!(
+                        frame_control.sequence_number == next_sequence_number,
+                        "Invalid APNG, expected sequence number {} in chunk {}, found {}",
+                        next_sequence_number,
+                        index,
+                        frame_control.sequence_number
+                    );
+                    next_sequence_number += 1;
+                    frame_control.validate(ihdr)?;
+
+                    if let Some(frame) = frames.last() {
+                        ensure!(
+                            !frame.data_indices.is_empty(),
+                            "Invalid APNG, frame {} has no data",
+                            frame.control.sequence_number
+                        );
+                    }
+
+                    frames.push(Frame {
+                        control: frame_control,
+                        control_index: index,
+                        data_indices: Vec::new(),
+                    });
+                    in_frame = true;
+                }
+                b"fdAT" => {
+                    let frame_data = FrameData::try_from(chunk)?;
+                    ensure!(
+                        frame_data.sequence_number == next_sequence_number,
+                        "Invalid APNG, expected sequence number {} in chunk {}, found {}",
+                        next_sequence_number,
+                        index,
+                        frame_data.sequence_number
+                    );
+                    next_sequence_number += 1;
+
+                    let frame = frames
+                        .last_mut()
+                        .filter(|frame| in_frame && !frame.is_default_image(chunks))
+                        .context(format!(
+                            "Invalid APNG, the fdAT chunk {} doesn't follow a fcTL chunk",
+                            index
+                        ))?;
+                    frame.data_indices.push(index);
+                }
+                b"IDAT" => {
+                    // Only a fcTL chunk right before the IDAT chunks makes them the first frame
+                    let is_first_frame = in_frame
+                        && frames.len() == 1
+                        && (frames[0].data_indices.is_empty()
+                            || frames[0].is_default_image(chunks));
+
+                    if is_first_frame {
+                        frames[0].data_indices.push(index);
+                    } else {
+                        ensure!(
+                            frames.is_empty(),
+                            "Invalid APNG, the IDAT chunk {} follows a frame",
+                            index
+                        );
+                        in_frame = false;
+                    }
+                }
+                _ => in_frame = false,
+            }
+        }
+
+        if let Some(frame) = frames.last() {
+            ensure!(
+                !frame.data_indices.is_empty(),
+                "Invalid APNG, frame {} has no data",
+                frame.control.sequence_number
+            );
+        }
+
+        ensure!(
+            frames.len() == control.num_frames as usize,
+            "Invalid APNG, the acTL chunk declares {} frames, but the image has {}",
+            control.num_frames,
+            frames.len()
+        );
+
+        let default_image_is_first_frame = frames[0].is_default_image(chunks);
+        if default_image_is_first_frame {
+            let first = frames[0].control;
+            ensure!(
+                first.x_offset == 0
+                    && first.y_offset == 0
+                    && first.width == ihdr.width()
+                    && first.height == ihdr.height(),
+                "Invalid APNG, the first frame should cover the whole image"
+            );
+        }
+
+        Ok(Some(Self {
+            control,
+            frames,
+            default_image_is_first_frame,
+        }))
+    }
+}
+
+/// Chunks before the first IDAT chunk that describe the whole image, like the palette or the
+/// color space, which each frame needs to be displayed on its own
+fn shared_chunks(png: &Png) -> Vec<Chunk> {
+    png.chunks()
+        .iter()
+        .skip(1)
+        .take_while(|chunk| chunk.chunk_type().bytes() != *b"IDAT")
+        .filter(|chunk| !matches!(&chunk.chunk_type().bytes(), b"acTL" | b"fcTL" | b"fdAT"))
+        .cloned()
+        .collect()
+}
+
+/// Splits an APNG into a standalone PNG for each frame of its animation. Each image has the
+/// size of its frame and the frame data moved from fdAT to IDAT chunks. Frames are not composed
+/// over the previous ones, so frames smaller than the animation only hold the area they update.
+pub fn split(png: &Png) -> Result<Vec<Png>> {
+    let ihdr = png.ihdr()?;
+    let animation = png.animation()?.context("The image is not an APNG")?;
+    let shared = shared_chunks(png);
+    let chunks = png.chunks();
+
+    animation
+        .frames
+        .iter()
+        .map(|frame| {
+            let control = frame.control;
+            let frame_ihdr = Ihdr::new(
+                control.width,
+                control.height,
+                ihdr.bit_depth(),
+                ihdr.color_type(),
+                ihdr.is_interlaced(),
+            )?;
+
+            let mut frame_chunks = vec![frame_ihdr.as_chunk()];
+            frame_chunks.extend(shared.iter().cloned());
+
+            for &index in frame.data_indices.iter() {
+                let chunk = &chunks[index];
+                let data = match &chunk.chunk_type().bytes() {
+                    b"fdAT" => FrameData::try_from(chunk)?.data,
+                    _ => chunk.data().clone(),
+                };
+                frame_chunks.push(new_chunk(b"IDAT", data));
+            }
+
+            frame_chunks.push(new_chunk(b"IEND", vec![]));
+            Ok(Png::from_chunks(frame_chunks))
+        })
+        .collect()
+}
+
+/// Assembles PNG images into an APNG, with each image as a frame displayed for
+/// `delay_num / delay_den` seconds. The first image is the default image, and its chunks before
+/// the image data (like the palette) apply to the whole animation. The other images should
+/// have the same bit depth, color type, palette and transparency, and may be smaller than the
+/// first one. None of the images can be an APNG already.
+pub fn build(frames: &[Png], delay_num: u16, delay_den: u16, num_plays: u32) -> Result<Png> {
+    let first = frames
+        .first()
+        .context("An animation needs at least one frame")?;
+    let ihdr = first.ihdr()?;
+
+    let mut chunks = vec![ihdr.as_chunk()];
+    chunks.extend(shared_chunks(first));
+    chunks.push(
+        AnimationControl {
+            num_frames: frames.len() as u32,
+            num_plays,
+        }
+        .as_chunk(),
+    );
+
+    let mut sequence_number = 0;
+    for (index, frame) in frames.iter().enumerate() {
+        // Only the IDAT chunks of each image are copied, so the frames of an APNG would be lost
+        ensure!(
+            frame.animation()?.is_none(),
+            "Frame {} is already an APNG",
+            index
+        );
+
+        let frame_ihdr = frame.ihdr()?;
+        ensure!(
+            frame_ihdr.bit_depth() == ihdr.bit_depth()
+                && frame_ihdr.color_type() == ihdr.color_type()
+                && frame_ihdr.is_interlaced() == ihdr.is_interlaced(),
+            "Frame {} should have the same bit depth, color type and interlacing as the first \
+             frame",
+            index
+        );
+        ensure!(
+            frame.palette()? == first.palette()?,
+            "Frame {} should have the same palette as the first frame",
+            index
+        );
+        ensure!(
+            frame.transparency()? == first.transparency()?,
+            "Frame {} should have the same transparency (tRNS chunk) as the first frame",
+            index
+        );
+
+        let control = FrameControl {
+            sequence_number,
+            width: frame_ihdr.width(),
+            height: frame_ihdr.height(),
+            x_offset: 0,
+            y_offset: 0,
+            delay_num,
+            delay_den,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        };
+        control
+            .validate(&ihdr)
+            .context(format!("Frame {} is larger than the first frame", index))?;
+        chunks.push(control.as_chunk());
+        sequence_number += 1;
+
+        for idat in frame.iter_by_type("IDAT") {
+            if index == 0 {
+                chunks.push(idat.clone());
+            } else {
+                chunks.push(
+                    FrameData {
+                        sequence_number,
+                        data: idat.data().clone(),
+                    }
+                    .as_chunk(),
+                );
+                sequence_number += 1;
+            }
+        }
+    }
+
+    chunks.push(new_chunk(b"IEND", vec![]));
+    Ok(Png::from_chunks(chunks))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn frame_control(sequence_number: u32, width: u32, height: u32) -> FrameControl {
+        FrameControl {
+            sequence_number,
+            width,
+            height,
+            x_offset: 0,
+            y_offset: 0,
+            delay_num: 1,
+            delay_den: 10,
+            dispose_op: DisposeOp::None,
+            blend_op: BlendOp::Source,
+        }
+    }
+
+    fn idat() -> Chunk {
+        new_chunk(b"IDAT", vec![1, 2, 3])
+    }
+
+    fn fdat(sequence_number: u32) -> Chunk {
+        FrameData {
+            sequence_number,
+            data: vec![4, 5, 6],
+        }
+        .as_chunk()
+    }
+
+    fn ihdr() -> Ihdr {
+        Ihdr::new(10, 10, 8, ColorType::Rgba, false).unwrap()
+    }
+
+    fn apng_chunks() -> Vec<Chunk> {
+        vec![
+            ihdr().as_chunk(),
+            AnimationControl {
+                num_frames: 2,
+                num_plays: 0,
+            }
+            .as_chunk(),
+            frame_control(0, 10, 10).as_chunk(),
+            idat(),
+            idat(),
+            frame_control(1, 5, 5).as_chunk(),
+            fdat(2),
+            fdat(3),
+            new_chunk(b"IEND", vec![]),
+        ]
+    }
+
+    #[test]
+    fn test_chunk_round_trips() {
+        let control = frame_control(3, 4, 5);
+        assert_eq!(
+            FrameControl::try_from(&control.as_chunk()).unwrap(),
+            control
+        );
+        assert_eq!(control.delay_ms(), 100.0);
+
+        let data = FrameData {
+            sequence_number: 7,
+            data: vec![1, 2],
+        };
+        assert_eq!(FrameData::try_from(&data.as_chunk()).unwrap(), data);
+
+        let control = AnimationControl {
+            num_frames: 3,
+            num_plays: 2,
+        };
+        assert_eq!(
+            AnimationControl::try_from(&control.as_chunk()).unwrap(),
+            control
+        );
+    }
+
+    #[test]
+    fn test_parse_animation() {
+        let chunks = apng_chunks();
+        let animation = Animation::parse(&chunks, &ihdr()).unwrap().unwrap();
+
+        assert_eq!(animation.control.num_frames, 2);
+        assert!(animation.default_image_is_first_frame);
+        assert_eq!(animation.frames[0].control_index, 2);
+        assert_eq!(animation.frames[0].data_indices, vec![3, 4]);
+        assert_eq!(animation.frames[1].data_indices, vec![6, 7]);
+
+        assert!(Animation::parse(&chunks[..1], &ihdr()).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_default_image_outside_animation() {
+        let mut chunks = apng_chunks();
+        // Without the first fcTL, the IDAT chunks are not part of the animation
+        chunks.remove(2);
+        chunks[1] = AnimationControl {
+            num_frames: 1,
+            num_plays: 0,
+        }
+        .as_chunk();
+        chunks[4] = frame_control(0, 5, 5).as_chunk();
+        chunks[5] = fdat(1);
+        chunks[6] = fdat(2);
+
+        let animation = Animation::parse(&chunks, &ihdr()).unwrap().unwrap();
+        assert!(!animation.default_image_is_first_frame);
+        assert_eq!(animation.frames[0].data_indices, vec![5, 6]);
+    }
+
+    #[test]
+    fn test_invalid_animations() {
+        let ihdr = ihdr();
+
+        // Wrong sequence number
+        let mut chunks = apng_chunks();
+        chunks[7] = fdat(4);
+        assert!(Animation::parse(&chunks, &ihdr).is_err());
+
+        // Frame outside the image
+        let mut chunks = apng_chunks();
+        let mut control = frame_control(1, 5, 5);
+        control.x_offset = 6;
+        chunks[5] = control.as_chunk();
+        assert!(Animation::parse(&chunks, &ihdr).is_err());
+
+        // Wrong frame count
+        let mut chunks = apng_chunks();
+        chunks[1] = AnimationControl {
+            num_frames: 3,
+            num_plays: 0,
+        }
+        .as_chunk();
+        assert!(Animation::parse(&chunks, &ihdr).is_err());
+
+        // A chunk between a fcTL chunk and its frame data
+        let mut chunks = apng_chunks();
+        chunks.insert(6, new_chunk(b"ruSt", vec![]));
+        assert!(Animation::parse(&chunks, &ihdr).is_err());
+
+        // acTL after IDAT
+        let mut chunks = apng_chunks();
+        let actl = chunks.remove(1);
+        chunks.insert(4, actl);
+        assert!(Animation::parse(&chunks, &ihdr).is_err());
+
+        // First frame smaller than the image
+        let mut chunks = apng_chunks();
+        chunks[2] = frame_control(0, 5, 5).as_chunk();
+        assert!(Animation::parse(&chunks, &ihdr).is_err());
+    }
+
+    #[test]
+    fn test_delay_from_ms() {
+        assert_eq!(FrameControl::delay_from_ms(2000).unwrap(), (2, 1));
+        assert_eq!(FrameControl::delay_from_ms(100).unwrap(), (10, 100));
+        assert_eq!(FrameControl::delay_from_ms(125).unwrap(), (125, 1000));
+        assert_eq!(FrameControl::delay_from_ms(70_000).unwrap(), (70, 1));
+        assert_eq!(FrameControl::delay_from_ms(70_010).unwrap(), (7001, 100));
+        assert!(FrameControl::delay_from_ms(70_001).is_err());
+    }
+
+    fn solid_png(width: u32, height: u32, value: u8) -> Png {
+        let ihdr = Ihdr::new(width, height, 8, ColorType::Rgba, false).unwrap();
+        let idat = new_chunk(b"IDAT", vec![]);
+        let mut png = Png::from_chunks(vec![
+            ihdr.as_chunk(),
+            new_chunk(b"gAMA", vec![0, 0, 177, 143]),
+            idat,
+            new_chunk(b"IEND", vec![]),
+        ]);
+
+        let rows = vec![vec![value; width as usize * 4]; height as usize];
+        let image = crate::image_data::RawImage::new(ihdr, rows).unwrap();
+        png.set_image_data(&image, &Default::default()).unwrap();
+        png
+    }
+
+    #[test]
+    fn test_build_and_split() {
+        let frames = vec![
+            solid_png(10, 10, 1),
+            solid_png(10, 10, 2),
+            solid_png(4, 6, 3),
+        ];
+        let apng = build(&frames, 1, 10, 0).unwrap();
+
+        let animation = apng.animation().unwrap().unwrap();
+        assert_eq!(animation.frames.len(), 3);
+        assert!(animation.default_image_is_first_frame);
+        assert_eq!(animation.frames[2].control.width, 4);
+        assert_eq!(animation.frames[2].control.delay_ms(), 100.0);
+        assert!(apng.chunk_by_type("gAMA").is_some());
+
+        let split_frames = split(&apng).unwrap();
+        assert_eq!(split_frames.len(), 3);
+        for (frame, original) in split_frames.iter().zip(frames.iter()) {
+            assert_eq!(frame.as_bytes(), original.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_build_invalid_frames() {
+        assert!(build(&[], 1, 10, 0).is_err());
+
+        // Larger than the first frame
+        let frames = vec![solid_png(4, 4, 1), solid_png(5, 4, 1)];
+        assert!(build(&frames, 1, 10, 0).is_err());
+
+        // Different color type
+        let mut gray = solid_png(4, 4, 1);
+        let ihdr = Ihdr::new(4, 4, 8, ColorType::Grayscale, false).unwrap();
+        gray.chunks_mut()[0] = ihdr.as_chunk();
+        let frames = vec![solid_png(4, 4, 1), gray];
+        assert!(build(&frames, 1, 10, 0).is_err());
+
+        assert!(split(&solid_png(4, 4, 1)).is_err());
+    }
+
+    #[test]
+    fn test_set_image_data_keeps_interlacing() {
+        let mut apng = build(&[solid_png(4, 4, 1), solid_png(4, 4, 2)], 1, 10, 0).unwrap();
+        let mut image = apng.image_data().unwrap().unfilter();
+        let options = Default::default();
+
+        image.set_interlaced(true);
+        assert!(apng.set_image_data(&image, &options).is_err());
+        assert!(!apng.ihdr().unwrap().is_interlaced());
+
+        image.set_interlaced(false);
+        apng.set_image_data(&image, &options).unwrap();
+        assert_eq!(apng.animation().unwrap().unwrap().frames.len(), 2);
+    }
+
+    #[test]
+    fn test_build_different_transparency() {
+        let rgb_png = || {
+            let mut png = solid_png(4, 4, 1);
+            let ihdr = Ihdr::new(4, 4, 8, ColorType::Rgb, false).unwrap();
+            png.chunks_mut()[0] = ihdr.as_chunk();
+            png
+        };
+
+        let mut transparent = rgb_png();
+        transparent
+            .set_transparency(&crate::palette::Transparency::Rgb(1, 1, 1))
+            .unwrap();
+
+        assert!(build(&[rgb_png(), rgb_png()], 1, 10, 0).is_ok());
+        assert!(build(&[rgb_png(), transparent.clone()], 1, 10, 0).is_err());
+        assert!(build(&[transparent.clone(), transparent], 1, 10, 0).is_ok());
+    }
+
+    #[test]
+    fn test_build_from_apng() {
+        let apng = build(&[solid_png(4, 4, 1), solid_png(4, 4, 2)], 1, 10, 0).unwrap();
+
+        assert!(build(&[apng.clone(), solid_png(4, 4, 3)], 1, 10, 0).is_err());
+        let error = build(&[solid_png(4, 4, 3), apng], 1, 10, 0).unwrap_err();
+        assert!(error.to_string().contains("Frame 1"));
+    }
+}
@@ -0,0 +1,72 @@
+use crc::{Crc, Digest, Table, CRC_32_ISO_HDLC};
+
+/// CRC-32 algorithm used by PNG chunks. The lookup tables are built at compile time, and hold 16
+/// tables so that 16 bytes are processed at a time.
+static CRC: Crc<u32, Table<16>> = Crc::<u32, Table<16>>::new(&CRC_32_ISO_HDLC);
+
+/// Incremental CRC calculation, allowing a chunk's CRC to be calculated from its chunk type and
+/// data without concatenating them into a new buffer
+pub struct CrcDigest {
+    digest: Digest<'static, u32, Table<16>>,
+}
+
+impl CrcDigest {
+    pub fn new() -> Self {
+        Self {
+            digest: CRC.digest(),
+        }
+    }
+
+    pub fn update(&mut self, bytes: &[u8]) {
+        self.digest.update(bytes);
+    }
+
+    pub fn finalize(self) -> u32 {
+        self.digest.finalize()
+    }
+}
+
+impl Default for CrcDigest {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Calculates the CRC of a chunk from its chunk type and data
+pub fn chunk_crc(chunk_type: &[u8; 4], chunk_data: &[u8]) -> u32 {
+    let mut digest = CrcDigest::new();
+    digest.update(chunk_type);
+    digest.update(chunk_data);
+    digest.finalize()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_crc() {
+        let data = "This is where your secret message will be!".as_bytes();
+        assert_eq!(chunk_crc(b"RuSt", data), 2882656334);
+    }
+
+    #[test]
+    fn test_incremental_crc() {
+        let data = "This is where your secret message will be!".as_bytes();
+
+        let mut digest = CrcDigest::new();
+        digest.update(b"Ru");
+        digest.update(b"St");
+        for part in data.chunks(5) {
+            digest.update(part);
+        }
+
+        assert_eq!(digest.finalize(), 2882656334);
+    }
+
+    #[test]
+    fn test_empty_chunk_crc() {
+        // CRC of the IEND chunk, which has no data
+        assert_eq!(chunk_crc(b"IEND", &[]), 0xAE426082);
+    }
+}
@@ -1,11 +1,10 @@
 use core::fmt;
-use std::io::{BufReader, Read};
 
-use anyhow::{ensure, Context, Error, Result};
+use anyhow::{Context, Error, Result};
 
-use crate::chunk_type::ChunkType;
+use crate::{checksum, chunk_ref::ChunkRef, chunk_type::ChunkType};
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 pub struct Chunk {
     length: u32,
     chunk_type: ChunkType,
@@ -15,19 +14,15 @@ pub struct Chunk {
 
 impl Chunk {
     pub fn new(chunk_type: ChunkType, chunk_data: Vec<u8>) -> Self {
-        let length = chunk_data.len() as u32;
-
-        let mut bytes_to_calc: Vec<u8> = Vec::new();
-        bytes_to_calc.extend(&chunk_type.bytes());
-        bytes_to_calc.extend(&chunk_data);
-        let crc = Self::calculate_crc(&bytes_to_calc);
-
-        Self {
-            length,
+        let mut chunk = Self {
+            length: 0,
             chunk_type,
             chunk_data,
-            crc,
-        }
+            crc: 0,
+        };
+        chunk.update_length_and_crc();
+
+        chunk
     }
 
     pub fn length(&self) -> u32 {
@@ -46,6 +41,23 @@ impl Chunk {
         &self.chunk_type
     }
 
+    /// Replaces the chunk data, recalculating the length and CRC
+    pub fn set_data(&mut self, chunk_data: Vec<u8>) {
+        self.chunk_data = chunk_data;
+        self.update_length_and_crc();
+    }
+
+    /// Edits the chunk data in place, recalculating the length and CRC afterwards
+    pub fn update_data<F: FnOnce(&mut Vec<u8>)>(&mut self, f: F) {
+        f(&mut self.chunk_data);
+        self.update_length_and_crc();
+    }
+
+    fn update_length_and_crc(&mut self) {
+        self.length = self.chunk_data.len() as u32;
+        self.crc = checksum::chunk_crc(&self.chunk_type.bytes(), &self.chunk_data);
+    }
+
     pub fn data_as_string(&self) -> Result<String> {
         String::from_utf8(self.chunk_data.clone()).context("Unable to get message from data")
     }
@@ -64,9 +76,119 @@ impl Chunk {
         chunk_data
     }
 
-    fn calculate_crc(bytes: &[u8]) -> u32 {
-        let crc: crc::Crc<u32> = crc::Crc::<u32>::new(&crc::CRC_32_ISO_HDLC);
-        crc.checksum(bytes)
+    /// Formats the bytes of the chunk as a hex dump, with offset, hex and ASCII columns
+    pub fn hexdump(&self) -> HexDump<'_> {
+        HexDump {
+            chunk: self,
+            offset: 0,
+            highlight: false,
+        }
+    }
+}
+
+/// A hex dump of a chunk, 16 bytes per line, created by [Chunk::hexdump]
+///
+/// ```text
+/// 00000008  00 00 00 0d 49 48 44 52  00 00 00 01 00 00 00 01  |....IHDR........|
+/// ```
+pub struct HexDump<'a> {
+    chunk: &'a Chunk,
+    offset: usize,
+    highlight: bool,
+}
+
+impl HexDump<'_> {
+    /// Bytes per line
+    const WIDTH: usize = 16;
+
+    /// ANSI colors of the length, type and CRC fields
+    const LENGTH_COLOR: &'static str = "\x1b[36m";
+    const TYPE_COLOR: &'static str = "\x1b[1;33m";
+    const CRC_COLOR: &'static str = "\x1b[35m";
+    const RESET: &'static str = "\x1b[0m";
+
+    /// Starts the offset column at `offset`, like the position of the chunk in its file.
+    /// Defaults to 0.
+    pub fn offset(mut self, offset: usize) -> Self {
+        self.offset = offset;
+        self
+    }
+
+    /// Colors the length, type and CRC fields with ANSI escape codes. Disabled by default.
+    pub fn highlight(mut self, highlight: bool) -> Self {
+        self.highlight = highlight;
+        self
+    }
+
+    /// Returns the color of the byte at `index` in the chunk, if it's part of a highlighted field
+    fn color(&self, index: usize, length: usize) -> Option<&'static str> {
+        if !self.highlight {
+            return None;
+        }
+
+        match index {
+            0..=3 => Some(Self::LENGTH_COLOR),
+            4..=7 => Some(Self::TYPE_COLOR),
+            _ if index + 4 >= length => Some(Self::CRC_COLOR),
+            _ => None,
+        }
+    }
+
+    fn write_byte(
+        &self,
+        f: &mut fmt::Formatter<'_>,
+        index: usize,
+        length: usize,
+        text: fmt::Arguments<'_>,
+    ) -> fmt::Result {
+        match self.color(index, length) {
+            Some(color) => write!(f, "{}{}{}", color, text, Self::RESET),
+            None => write!(f, "{}", text),
+        }
+    }
+}
+
+impl fmt::Display for HexDump<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let bytes = self.chunk.as_bytes();
+
+        for (line, line_bytes) in bytes.chunks(Self::WIDTH).enumerate() {
+            let start = line * Self::WIDTH;
+            write!(f, "{:08x} ", self.offset + start)?;
+
+            for column in 0..Self::WIDTH {
+                // An extra space splits the hex column in two halves
+                if column % 8 == 0 {
+                    write!(f, " ")?;
+                }
+
+                match line_bytes.get(column) {
+                    Some(byte) => {
+                        self.write_byte(
+                            f,
+                            start + column,
+                            bytes.len(),
+                            format_args!("{:02x}", byte),
+                        )?;
+                        write!(f, " ")?;
+                    }
+                    None => write!(f, "   ")?,
+                }
+            }
+
+            write!(f, " |")?;
+            for (column, byte) in line_bytes.iter().enumerate() {
+                let c = if byte.is_ascii_graphic() || *byte == b' ' {
+                    *byte as char
+                } else {
+                    '.'
+                };
+                self.write_byte(f, start + column, bytes.len(), format_args!("{}", c))?;
+            }
+            writeln!(f, "|")?;
+        }
+
+        Ok(())
     }
 }
 
@@ -86,50 +208,21 @@ impl TryFrom<&[u8]> for Chunk {
     type Error = Error;
 
     fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
-        let mut reader = BufReader::new(data);
-
-        let mut length_buffer: [u8; 4] = [0; 4];
-        reader
-            .read_exact(&mut length_buffer)
-            .context("Unable to read length bytes")?;
-        let length = u32::from_be_bytes(length_buffer);
-
-        let mut chunk_type_buffer: [u8; 4] = [0; 4];
-        reader
-            .read_exact(&mut chunk_type_buffer)
-            .context("Unable to read chunk type bytes")?;
-
-        let chunk_type = ChunkType::try_from(chunk_type_buffer)?;
-
-        let mut data_buffer: Vec<u8> = vec![0u8; length as usize];
-        reader
-            .read_exact(&mut data_buffer)
-            .context("Unable to read data bytes")?;
-
-        let mut crc_buffer = [0u8; 4];
-        reader
-            .read_exact(&mut crc_buffer)
-            .context("Unable to read crc bytes")?;
-
-        let crc = u32::from_be_bytes(crc_buffer);
-
-        let mut bytes_to_calc: Vec<u8> = Vec::new();
-        bytes_to_calc.extend(&chunk_type_buffer);
-        bytes_to_calc.extend(&data_buffer);
-        let calculated_crc = Self::calculate_crc(&bytes_to_calc);
-
-        ensure!(
-            crc == calculated_crc,
-            "Invalid CRC, received: {}, expected: {}",
-            crc,
-            calculated_crc
-        );
+        Self::try_from(ChunkRef::parse(data)?)
+    }
+}
+
+impl TryFrom<ChunkRef<'_>> for Chunk {
+    type Error = Error;
+
+    fn try_from(chunk: ChunkRef<'_>) -> Result<Self, Self::Error> {
+        chunk.verify_crc()?;
 
         Ok(Self {
-            length,
-            chunk_type,
-            chunk_data: data_buffer,
-            crc,
+            length: chunk.length(),
+            chunk_type: *chunk.chunk_type(),
+            chunk_data: chunk.data().to_vec(),
+            crc: chunk.crc(),
         })
     }
 }
@@ -169,6 +262,29 @@ mod tests {
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_set_data() {
+        let mut chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![1, 2, 3]);
+        chunk.set_data(
+            "This is where your secret message will be!"
+                .as_bytes()
+                .to_vec(),
+        );
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.crc(), 2882656334);
+    }
+
+    #[test]
+    fn test_update_data() {
+        let mut chunk = Chunk::new(
+            ChunkType::from_str("RuSt").unwrap(),
+            "This is where your secret message".as_bytes().to_vec(),
+        );
+        chunk.update_data(|data| data.extend(" will be!".as_bytes()));
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.crc(), 2882656334);
+    }
+
     #[test]
     fn test_chunk_length() {
         let chunk = testing_chunk();
@@ -222,6 +338,46 @@ mod tests {
         assert_eq!(chunk.crc(), 2882656334);
     }
 
+    #[test]
+    fn test_hexdump() {
+        let chunk = Chunk::new(ChunkType::from_str("IHDR").unwrap(), b"Hi\x00".to_vec());
+        let expected =
+            "00000008  00 00 00 03 49 48 44 52  48 69 00 a9 35 52 e3     |....IHDRHi..5R.|\n";
+
+        assert_eq!(chunk.hexdump().offset(8).to_string(), expected);
+    }
+
+    #[test]
+    fn test_hexdump_lines() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![b'a'; 24]);
+        let lines: Vec<String> = chunk
+            .hexdump()
+            .to_string()
+            .lines()
+            .map(String::from)
+            .collect();
+
+        assert_eq!(lines.len(), 3);
+        assert!(lines[0].starts_with("00000000  00 00 00 18 52 75 53 74  61 61"));
+        assert!(lines[1].ends_with("|aaaaaaaaaaaaaaaa|"));
+        // The last line only holds the CRC, the hex column is padded
+        assert!(lines[2].starts_with("00000020  "));
+        assert_eq!(lines[2].split('|').nth(1).unwrap().chars().count(), 4);
+        assert_eq!(lines[0].len(), lines[2].len() + 12);
+    }
+
+    #[test]
+    fn test_hexdump_highlight() {
+        let chunk = Chunk::new(ChunkType::from_str("RuSt").unwrap(), vec![b'a'; 20]);
+        let dump = chunk.hexdump().highlight(true).to_string();
+
+        assert!(dump.contains("\x1b[1;33m52\x1b[0m"));
+        assert!(dump.contains("\x1b[1;33mR\x1b[0m"));
+        assert!(!dump.contains("\x1b[36m61"));
+        assert_eq!(dump.matches("\x1b[35m").count(), 8);
+        assert!(!chunk.hexdump().to_string().contains('\x1b'));
+    }
+
     #[test]
     fn test_invalid_chunk_from_bytes() {
         let data_length: u32 = 42;
@@ -0,0 +1,168 @@
+use anyhow::{ensure, Context, Result};
+
+use crate::{checksum, chunk::Chunk, chunk_type::ChunkType};
+
+/// A read-only view of a chunk that borrows its data from a byte buffer.
+///
+/// Unlike [Chunk], parsing a `ChunkRef` doesn't copy the chunk data nor check its CRC, which is
+/// only calculated when calling [ChunkRef::verify_crc].
+#[derive(Debug, Clone, Copy)]
+pub struct ChunkRef<'a> {
+    chunk_type: ChunkType,
+    data: &'a [u8],
+    crc: u32,
+}
+
+impl<'a> ChunkRef<'a> {
+    /// Number of bytes used by the length, chunk type and CRC fields of a chunk
+    pub const OVERHEAD: usize = 12;
+
+    pub(crate) fn new(chunk_type: ChunkType, data: &'a [u8], crc: u32) -> Self {
+        Self {
+            chunk_type,
+            data,
+            crc,
+        }
+    }
+
+    /// Parses the chunk at the start of `bytes`, any bytes after the chunk are ignored
+    pub fn parse(bytes: &'a [u8]) -> Result<Self> {
+        let length_bytes: [u8; 4] = bytes
+            .get(0..4)
+            .and_then(|b| b.try_into().ok())
+            .context("Unable to read length bytes")?;
+        let length = u32::from_be_bytes(length_bytes) as usize;
+
+        let chunk_type_bytes: [u8; 4] = bytes
+            .get(4..8)
+            .and_then(|b| b.try_into().ok())
+            .context("Unable to read chunk type bytes")?;
+        let chunk_type = ChunkType::try_from(chunk_type_bytes)?;
+
+        let data = bytes
+            .get(8..8 + length)
+            .context("Unable to read data bytes")?;
+
+        let crc_bytes: [u8; 4] = bytes
+            .get(8 + length..Self::OVERHEAD + length)
+            .and_then(|b| b.try_into().ok())
+            .context("Unable to read crc bytes")?;
+
+        Ok(Self {
+            chunk_type,
+            data,
+            crc: u32::from_be_bytes(crc_bytes),
+        })
+    }
+
+    pub fn length(&self) -> u32 {
+        self.data.len() as u32
+    }
+
+    pub fn chunk_type(&self) -> &ChunkType {
+        &self.chunk_type
+    }
+
+    pub fn data(&self) -> &'a [u8] {
+        self.data
+    }
+
+    pub fn crc(&self) -> u32 {
+        self.crc
+    }
+
+    /// Number of bytes the chunk takes in the buffer, including the length, chunk type and CRC
+    pub fn total_length(&self) -> usize {
+        self.data.len() + Self::OVERHEAD
+    }
+
+    /// Checks if the stored CRC matches the one calculated from the chunk type and data
+    pub fn verify_crc(&self) -> Result<()> {
+        let calculated_crc = checksum::chunk_crc(&self.chunk_type.bytes(), self.data);
+
+        ensure!(
+            self.crc == calculated_crc,
+            "Invalid CRC, received: {}, expected: {}",
+            self.crc,
+            calculated_crc
+        );
+
+        Ok(())
+    }
+}
+
+impl<'a> From<&'a Chunk> for ChunkRef<'a> {
+    fn from(chunk: &'a Chunk) -> Self {
+        Self::new(*chunk.chunk_type(), chunk.data(), chunk.crc())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    fn chunk_bytes(crc: u32) -> Vec<u8> {
+        let data_length: u32 = 42;
+        let chunk_type = "RuSt".as_bytes();
+        let message_bytes = "This is where your secret message will be!".as_bytes();
+
+        data_length
+            .to_be_bytes()
+            .iter()
+            .chain(chunk_type.iter())
+            .chain(message_bytes.iter())
+            .chain(crc.to_be_bytes().iter())
+            .copied()
+            .collect()
+    }
+
+    #[test]
+    fn test_parse_chunk_ref() {
+        let bytes = chunk_bytes(2882656334);
+        let chunk = ChunkRef::parse(&bytes).unwrap();
+
+        assert_eq!(chunk.length(), 42);
+        assert_eq!(chunk.chunk_type(), &ChunkType::from_str("RuSt").unwrap());
+        assert_eq!(
+            chunk.data(),
+            "This is where your secret message will be!".as_bytes()
+        );
+        assert_eq!(chunk.crc(), 2882656334);
+        assert_eq!(chunk.total_length(), bytes.len());
+        assert!(chunk.verify_crc().is_ok());
+    }
+
+    #[test]
+    fn test_chunk_ref_borrows_data() {
+        let bytes = chunk_bytes(2882656334);
+        let chunk = ChunkRef::parse(&bytes).unwrap();
+
+        assert!(std::ptr::eq(chunk.data().as_ptr(), bytes[8..].as_ptr()));
+    }
+
+    #[test]
+    fn test_chunk_ref_invalid_crc_is_lazy() {
+        let bytes = chunk_bytes(2882656333);
+        let chunk = ChunkRef::parse(&bytes).unwrap();
+
+        assert!(chunk.verify_crc().is_err());
+        assert!(Chunk::try_from(chunk).is_err());
+    }
+
+    #[test]
+    fn test_chunk_ref_truncated() {
+        let bytes = chunk_bytes(2882656334);
+
+        assert!(ChunkRef::parse(&bytes[..bytes.len() - 1]).is_err());
+        assert!(ChunkRef::parse(&bytes[..2]).is_err());
+    }
+
+    #[test]
+    fn test_chunk_ref_to_chunk() {
+        let bytes = chunk_bytes(2882656334);
+        let chunk = Chunk::try_from(ChunkRef::parse(&bytes).unwrap()).unwrap();
+
+        assert_eq!(chunk.as_bytes(), bytes);
+    }
+}
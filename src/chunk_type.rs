@@ -15,7 +15,7 @@ mod error {
     }
 }
 
-#[derive(Debug, PartialEq)]
+#[derive(Debug, Clone, Copy, PartialEq)]
 pub struct ChunkType {
     bytes: [u8; 4],
 }
@@ -1,4 +1,8 @@
-use clap::{Args, Parser, Subcommand};
+use std::str::FromStr;
+
+use anyhow::{ensure, Context, Error, Result};
+use clap::{Args, Parser, Subcommand, ValueEnum};
+use pngme::{container::ChunkPosition, filter::FilterStrategy, selector::Selector};
 
 #[derive(Args, Debug)]
 pub struct EncodeArgs {
@@ -14,21 +18,72 @@ pub struct EncodeArgs {
 
     /// Optional output file, if not specified the original image is overwritten
     pub output_file: Option<String>,
+
+    /// Replaces the message in the first chunk of this chunk type instead of adding a new chunk
+    #[arg(short, long)]
+    pub replace: bool,
+
+    /// Where to add the message chunk: "start" (after IHDR), "end" (before IEND) or a chunk
+    /// index. Adding it at the end of an image that is overwritten avoids rewriting the file
+    #[arg(short, long, default_value = "end")]
+    pub position: ChunkPosition,
+
+    /// Spreads the message across the frames of an animated image (APNG), in one chunk after
+    /// each frame. Chunks of this chunk type already in the image are replaced
+    #[arg(short, long, conflicts_with_all = ["replace", "position"])]
+    pub frames: bool,
+
+    /// Stores the message of a JPEG or GIF image in a comment (COM segment or comment extension)
+    /// instead of an APP15 segment or application extension
+    #[arg(short, long)]
+    pub comment: bool,
 }
 
 #[derive(Args, Debug)]
-pub struct DecodeArgs {
+pub struct UpdateArgs {
     /// File path of the image
     pub file_path: String,
 
     /// Type of the chunk containing the hidden message
     pub chunk_type: String,
+
+    /// The new message
+    pub message: String,
+
+    /// Which of the chunks matching the chunk type to update (zero-based)
+    #[arg(short, long, default_value_t = 0, conflicts_with = "all")]
+    pub index: usize,
+
+    /// Updates every chunk matching the chunk type
+    #[arg(short, long)]
+    pub all: bool,
+
+    /// Optional output file, if not specified the original image is overwritten
+    pub output_file: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct DecodeArgs {
+    /// File path of the image
+    pub file_path: String,
+
+    /// Selector of the chunks containing the hidden messages. Ex.: ruSt, ru??, "private && #3.."
+    pub selector: Selector,
+
+    /// Rebuilds a message spread across the frames of an animated image (APNG) from the
+    /// matching chunks, even if the animation chunks were removed
+    #[arg(short, long)]
+    pub frames: bool,
 }
 
 #[derive(Args, Debug)]
 pub struct PrintArgs {
     /// File path of the image
     pub file_path: String,
+
+    /// Selector of the chunks whose types are printed
+    #[arg(default_value = "private")]
+    pub selector: Selector,
 }
 
 #[derive(Args, Debug)]
@@ -36,8 +91,270 @@ pub struct RemoveArgs {
     /// File path of the image
     pub file_path: String,
 
-    /// Type of the chunk containing the hidden message
+    /// Selector of the chunks to remove. Ex.: ruSt, ru??, "private && #3.."
+    pub selector: Selector,
+}
+
+#[derive(ValueEnum, Clone, Copy, Debug)]
+pub enum StripProfile {
+    /// Removes all ancillary chunks except tRNS
+    Ancillary,
+
+    /// Removes all private chunks
+    Private,
+
+    /// Removes textual metadata chunks: tEXt, zTXt and iTXt
+    Text,
+}
+
+#[derive(Args, Debug)]
+pub struct StripArgs {
+    /// File path of the image
+    pub file_path: String,
+
+    /// Which chunks to remove. Critical chunks are never removed
+    #[arg(short, long, value_enum, default_value_t = StripProfile::Ancillary)]
+    pub profile: StripProfile,
+
+    /// Comma-separated chunk types that should not be removed. Ex.: sRGB,gAMA,pHYs
+    #[arg(short, long, value_delimiter = ',')]
+    pub keep: Vec<String>,
+
+    /// Optional output file, if not specified the original image is overwritten
+    pub output_file: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct OptimizeArgs {
+    /// File path of the image
+    pub file_path: String,
+
+    /// zlib compression level, from 0 (no compression) to 9 (best compression)
+    #[arg(short, long, default_value_t = 9, value_parser = clap::value_parser!(u32).range(0..=9))]
+    pub level: u32,
+
+    /// How the filter of each scanline is chosen: none, sub, up, average, paeth (same filter for
+    /// every scanline), minimum-sum or brute-force
+    #[arg(short, long, default_value = "minimum-sum")]
+    pub filter: FilterStrategy,
+
+    /// Maximum size in bytes of each IDAT chunk. By default the image data is stored in a single
+    /// IDAT chunk
+    #[arg(long)]
+    pub idat_size: Option<usize>,
+
+    /// Optional output file, if not specified the original image is overwritten
+    pub output_file: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct InspectArgs {
+    /// File path of the image
+    pub file_path: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ChunkExtractArgs {
+    /// File path of the image
+    pub file_path: String,
+
+    /// Type of the chunk to extract
     pub chunk_type: String,
+
+    /// Which of the chunks matching the chunk type to extract (zero-based)
+    #[arg(short, long, default_value_t = 0)]
+    pub index: usize,
+
+    /// File the raw chunk data is written to
+    #[arg(short, long)]
+    pub output_file: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ChunkInsertArgs {
+    /// File path of the image
+    pub file_path: String,
+
+    /// Type of the chunk to insert
+    pub chunk_type: String,
+
+    /// File containing the raw chunk data
+    #[arg(short, long)]
+    pub data_file: String,
+
+    /// Where to insert the chunk: "start" (after IHDR), "end" (before IEND) or a chunk index
+    #[arg(short, long, default_value = "end")]
+    pub position: ChunkPosition,
+
+    /// Allows chunk types that are not valid for messages, like critical or public chunk types
+    #[arg(long)]
+    pub allow_critical: bool,
+
+    /// Optional output file, if not specified the original image is overwritten
+    pub output_file: Option<String>,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ChunkCommands {
+    /// Writes the raw data of a chunk to a file
+    Extract(ChunkExtractArgs),
+
+    /// Inserts a chunk with raw data read from a file
+    Insert(ChunkInsertArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ChunkArgs {
+    #[command(subcommand)]
+    pub command: ChunkCommands,
+}
+
+#[derive(Args, Debug)]
+pub struct PaletteHideArgs {
+    /// File path of the indexed image
+    pub file_path: String,
+
+    /// The message
+    pub message: String,
+
+    /// Optional output file, if not specified the original image is overwritten
+    pub output_file: Option<String>,
+}
+
+#[derive(Args, Debug)]
+pub struct PaletteRevealArgs {
+    /// File path of the indexed image
+    pub file_path: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum PaletteCommands {
+    /// Hides a message in the order of the palette entries, without changing how the image looks
+    Hide(PaletteHideArgs),
+
+    /// Prints the message hidden in the order of the palette entries
+    Reveal(PaletteRevealArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct PaletteArgs {
+    #[command(subcommand)]
+    pub command: PaletteCommands,
+}
+
+/// How long an animation frame is displayed, in milliseconds
+#[derive(Clone, Copy, Debug)]
+pub struct FrameDelay(pub u32);
+
+impl FromStr for FrameDelay {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let error = || {
+            format!(
+                "Invalid delay \"{}\", it should be a duration like 100ms or 1.5s",
+                s
+            )
+        };
+
+        let ms = if let Some(ms) = s.strip_suffix("ms") {
+            ms.parse().with_context(error)?
+        } else if let Some(seconds) = s.strip_suffix('s') {
+            let seconds: f64 = seconds.parse().with_context(error)?;
+            ensure!((0.0..1e6).contains(&seconds), error());
+            (seconds * 1000.0).round() as u32
+        } else {
+            s.parse().with_context(error)?
+        };
+
+        Ok(Self(ms))
+    }
+}
+
+#[derive(Args, Debug)]
+pub struct ApngSplitArgs {
+    /// File path of the animated image
+    pub file_path: String,
+
+    /// Directory the frames are written to, as frame_000.png, frame_001.png...
+    #[arg(short, long)]
+    pub output_dir: String,
+}
+
+#[derive(Args, Debug)]
+pub struct ApngBuildArgs {
+    /// File paths of the frames, in order. The first frame is also shown by viewers without
+    /// APNG support
+    #[arg(required = true)]
+    pub frames: Vec<String>,
+
+    /// How long each frame is displayed. Ex.: 100ms, 1.5s
+    #[arg(short, long, default_value = "100ms")]
+    pub delay: FrameDelay,
+
+    /// Number of times the animation is played, 0 to loop forever
+    #[arg(short, long, default_value_t = 0)]
+    pub plays: u32,
+
+    /// File the animated image is written to
+    #[arg(short, long)]
+    pub output_file: String,
+}
+
+#[derive(Subcommand, Debug)]
+pub enum ApngCommands {
+    /// Writes each frame of an animated image as a standalone image
+    Split(ApngSplitArgs),
+
+    /// Assembles images into an animated image
+    Build(ApngBuildArgs),
+}
+
+#[derive(Args, Debug)]
+pub struct ApngArgs {
+    #[command(subcommand)]
+    pub command: ApngCommands,
+}
+
+#[derive(Args, Debug)]
+pub struct HexdumpArgs {
+    /// File path of the image
+    pub file_path: String,
+
+    /// Only shows the chunks of this chunk type
+    #[arg(short = 't', long = "type")]
+    pub chunk_type: Option<String>,
+
+    /// Only shows the chunk at this index (zero-based) among the chunks of the chunk type, or
+    /// among all chunks if no chunk type is given
+    #[arg(short, long)]
+    pub index: Option<usize>,
+}
+
+#[derive(Args, Debug)]
+pub struct DumpArgs {
+    /// File path of the image
+    pub file_path: String,
+
+    /// File the manifest is written to, as JSON or YAML depending on its extension (.json,
+    /// .yaml or .yml)
+    #[arg(short, long)]
+    pub manifest: String,
+
+    /// Writes the data of each chunk to a file in a directory next to the manifest, named after
+    /// the manifest with a .data extension, instead of storing it as base64
+    #[arg(short, long)]
+    pub sidecar: bool,
+}
+
+#[derive(Args, Debug)]
+pub struct BuildArgs {
+    /// File the image is written to
+    pub output_file: String,
+
+    /// Manifest describing the chunks of the image, written by the dump command
+    #[arg(short, long)]
+    pub manifest: String,
 }
 
 #[derive(Subcommand, Debug)]
@@ -45,14 +362,44 @@ pub enum Commands {
     /// Hides a message in an image by storing it in a non-critical chunk.
     Encode(EncodeArgs),
 
-    /// Prints hidden messages in chunks of a specific chunk type
+    /// Replaces the message in existing chunks of a specific chunk type, keeping their position
+    Update(UpdateArgs),
+
+    /// Prints hidden messages in the chunks matching a selector
     Decode(DecodeArgs),
 
-    /// Prints private chunk types in the image
+    /// Prints the chunk types in the image, private chunk types by default
     Print(PrintArgs),
 
-    /// Removes all chunks of a specific chunk type. This will overwrite the file.
+    /// Removes all chunks matching a selector. This will overwrite the file.
     Remove(RemoveArgs),
+
+    /// Removes metadata chunks, keeping only the chunks needed to display the image
+    Strip(StripArgs),
+
+    /// Extracts or inserts chunks with raw data
+    Chunk(ChunkArgs),
+
+    /// Recompresses the image data, keeping all other chunks as they are
+    Optimize(OptimizeArgs),
+
+    /// Prints the image header, the chunk list and the palette of an image
+    Inspect(InspectArgs),
+
+    /// Hides or reveals messages in the palette order of indexed images
+    Palette(PaletteArgs),
+
+    /// Splits animated images (APNG) into frames, or builds them from frames
+    Apng(ApngArgs),
+
+    /// Prints the bytes of the chunks of an image with offset, hex and ASCII columns
+    Hexdump(HexdumpArgs),
+
+    /// Writes a manifest describing every chunk of an image, with their data
+    Dump(DumpArgs),
+
+    /// Recreates an image from a manifest written by the dump command
+    Build(BuildArgs),
 }
 
 #[derive(Parser, Debug)]
@@ -0,0 +1,125 @@
+//! A common interface for the file formats messages can be hidden in.
+//!
+//! Each format exposes the parts of the file able to hold a message as [Chunk]s, along with
+//! their index in the file, so commands work the same way whatever the format is. [open] detects
+//! the format of a file and loads it with the matching [Container] implementation.
+
+use std::{borrow::Cow, path::Path, str::FromStr};
+
+use anyhow::{Context, Error, Result};
+
+use crate::{
+    chunk::Chunk, format::Format, gif::Gif, jpeg::Jpeg, png::Png, riff::Riff, selector::Selector,
+};
+
+/// Where a new chunk should be placed among the chunks of an image
+#[derive(Clone, Copy, Debug)]
+pub enum ChunkPosition {
+    /// Right after the IHDR chunk
+    Start,
+
+    /// Right before the IEND chunk
+    End,
+
+    /// At a specific (zero-based) index in the chunk list
+    Index(usize),
+}
+
+impl FromStr for ChunkPosition {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "start" => Ok(Self::Start),
+            "end" => Ok(Self::End),
+            _ => s.parse().map(Self::Index).context(format!(
+                "Invalid position \"{}\", it should be \"start\", \"end\" or a chunk index",
+                s
+            )),
+        }
+    }
+}
+
+/// How a payload is added to a container. Formats ignore the options that don't apply to them.
+#[derive(Clone, Copy, Debug)]
+pub struct PayloadOptions {
+    /// Where the payload goes, for formats where chunks can be placed anywhere (PNG, MNG, JNG)
+    pub position: ChunkPosition,
+
+    /// Stores the payload in a comment, for formats that have them (JPEG, GIF)
+    pub comment: bool,
+}
+
+impl Default for PayloadOptions {
+    fn default() -> Self {
+        Self {
+            position: ChunkPosition::End,
+            comment: false,
+        }
+    }
+}
+
+/// A file format holding payloads in chunks, or in segments exposed as chunks
+pub trait Container {
+    /// Loads a file of this format
+    fn open(path: &Path) -> Result<Self>
+    where
+        Self: Sized;
+
+    /// Lists the segments exposed as chunks, along with their index in the file. Formats storing
+    /// chunks as they are lend them instead of copying them.
+    fn segments(&self) -> Vec<(usize, Cow<'_, Chunk>)>;
+
+    /// Adds a payload, stored like the format stores messages
+    fn insert_payload(&mut self, chunk: Chunk, options: &PayloadOptions) -> Result<()>;
+
+    /// Replaces the data of the first payload of the chunk type of `chunk`, keeping its position.
+    /// Returns false if there's no such payload.
+    fn replace_payload(&mut self, chunk: &Chunk) -> Result<bool>;
+
+    /// Returns the segments matching the selector, copying only those
+    fn extract_payloads(&self, selector: &Selector) -> Vec<Chunk> {
+        self.segments()
+            .into_iter()
+            .filter(|(index, chunk)| selector.matches(*index, chunk))
+            .map(|(_, chunk)| chunk.into_owned())
+            .collect()
+    }
+
+    /// Removes the segments matching the selector, returning them. Fails without removing
+    /// anything if a segment the format requires is selected.
+    fn remove_payloads(&mut self, selector: &Selector) -> Result<Vec<Chunk>>;
+
+    /// Returns the bytes of the whole file
+    fn to_bytes(&self) -> Vec<u8>;
+}
+
+/// Detects the format of a file and loads it
+pub fn open<P: AsRef<Path>>(path: P) -> Result<Box<dyn Container>> {
+    let path = path.as_ref();
+
+    Ok(match Format::detect_file(path)? {
+        Format::Png(_) => Box::new(Png::open(path)?),
+        Format::Jpeg => Box::new(Jpeg::open(path)?),
+        Format::Riff => Box::new(Riff::open(path)?),
+        Format::Gif => Box::new(Gif::open(path)?),
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_chunk_position_from_str() {
+        assert!(matches!(
+            ChunkPosition::from_str("start").unwrap(),
+            ChunkPosition::Start
+        ));
+        assert!(matches!(
+            ChunkPosition::from_str("3").unwrap(),
+            ChunkPosition::Index(3)
+        ));
+        assert!(ChunkPosition::from_str("middle").is_err());
+    }
+}
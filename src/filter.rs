@@ -0,0 +1,342 @@
+use std::{fmt::Display, io::Write, str::FromStr};
+
+use anyhow::{bail, Error, Result};
+use flate2::{write::ZlibEncoder, Compression};
+
+/// Filter applied to a scanline before compression, stored in the first byte of each scanline
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterType {
+    None,
+    Sub,
+    Up,
+    Average,
+    Paeth,
+}
+
+impl FilterType {
+    pub const ALL: [FilterType; 5] = [
+        FilterType::None,
+        FilterType::Sub,
+        FilterType::Up,
+        FilterType::Average,
+        FilterType::Paeth,
+    ];
+}
+
+impl TryFrom<u8> for FilterType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(FilterType::None),
+            1 => Ok(FilterType::Sub),
+            2 => Ok(FilterType::Up),
+            3 => Ok(FilterType::Average),
+            4 => Ok(FilterType::Paeth),
+            _ => bail!("Invalid filter type {}", value),
+        }
+    }
+}
+
+impl From<FilterType> for u8 {
+    fn from(filter_type: FilterType) -> Self {
+        filter_type as u8
+    }
+}
+
+/// Predicts a byte from the bytes to its left (`a`), above (`b`) and above-left (`c`)
+fn paeth_predictor(a: u8, b: u8, c: u8) -> u8 {
+    let p = a as i16 + b as i16 - c as i16;
+    let pa = (p - a as i16).abs();
+    let pb = (p - b as i16).abs();
+    let pc = (p - c as i16).abs();
+
+    if pa <= pb && pa <= pc {
+        a
+    } else if pb <= pc {
+        b
+    } else {
+        c
+    }
+}
+
+/// Reverses the filter of a scanline in place.
+///
+/// `previous` is the unfiltered previous scanline, or `None` for the first scanline, and
+/// `bytes_per_pixel` the number of bytes per complete pixel, rounded up to 1.
+pub fn unfilter_scanline(
+    filter_type: FilterType,
+    line: &mut [u8],
+    previous: Option<&[u8]>,
+    bytes_per_pixel: usize,
+) {
+    let above = |i: usize| previous.map_or(0, |p| p[i]);
+
+    for i in 0..line.len() {
+        let left = if i >= bytes_per_pixel {
+            line[i - bytes_per_pixel]
+        } else {
+            0
+        };
+        let above_left = if i >= bytes_per_pixel {
+            above(i - bytes_per_pixel)
+        } else {
+            0
+        };
+
+        let prediction = match filter_type {
+            FilterType::None => 0,
+            FilterType::Sub => left,
+            FilterType::Up => above(i),
+            FilterType::Average => ((left as u16 + above(i) as u16) / 2) as u8,
+            FilterType::Paeth => paeth_predictor(left, above(i), above_left),
+        };
+
+        line[i] = line[i].wrapping_add(prediction);
+    }
+}
+
+/// Applies a filter to a scanline, the reverse of [unfilter_scanline].
+///
+/// `previous` is the unfiltered previous scanline, or `None` for the first scanline.
+pub fn filter_scanline(
+    filter_type: FilterType,
+    line: &[u8],
+    previous: Option<&[u8]>,
+    bytes_per_pixel: usize,
+) -> Vec<u8> {
+    let above = |i: usize| previous.map_or(0, |p| p[i]);
+
+    (0..line.len())
+        .map(|i| {
+            let left = if i >= bytes_per_pixel {
+                line[i - bytes_per_pixel]
+            } else {
+                0
+            };
+            let above_left = if i >= bytes_per_pixel {
+                above(i - bytes_per_pixel)
+            } else {
+                0
+            };
+
+            let prediction = match filter_type {
+                FilterType::None => 0,
+                FilterType::Sub => left,
+                FilterType::Up => above(i),
+                FilterType::Average => ((left as u16 + above(i) as u16) / 2) as u8,
+                FilterType::Paeth => paeth_predictor(left, above(i), above_left),
+            };
+
+            line[i].wrapping_sub(prediction)
+        })
+        .collect()
+}
+
+/// How the filter type of each scanline is chosen when encoding the image data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum FilterStrategy {
+    /// Uses the same filter type for every scanline
+    Fixed(FilterType),
+
+    /// Picks the filter type minimizing the sum of the filtered bytes, taken as signed values.
+    /// This is the heuristic recommended by the PNG spec.
+    MinimumSum,
+
+    /// Compresses the scanline with each filter type and picks the one with the smallest
+    /// output. Much slower than the other strategies.
+    BruteForce,
+}
+
+impl FilterStrategy {
+    /// Filters a scanline, returning the chosen filter type along with the filtered bytes
+    pub fn apply(
+        &self,
+        line: &[u8],
+        previous: Option<&[u8]>,
+        bytes_per_pixel: usize,
+    ) -> (FilterType, Vec<u8>) {
+        let candidates = match self {
+            FilterStrategy::Fixed(filter_type) => {
+                let filtered = filter_scanline(*filter_type, line, previous, bytes_per_pixel);
+                return (*filter_type, filtered);
+            }
+            FilterStrategy::MinimumSum | FilterStrategy::BruteForce => {
+                FilterType::ALL.map(|filter_type| {
+                    let filtered = filter_scanline(filter_type, line, previous, bytes_per_pixel);
+                    (filter_type, filtered)
+                })
+            }
+        };
+
+        let cost = |filtered: &[u8]| -> usize {
+            match self {
+                FilterStrategy::BruteForce => compressed_length(filtered),
+                _ => filtered
+                    .iter()
+                    .map(|b| (*b as i8).unsigned_abs() as usize)
+                    .sum(),
+            }
+        };
+
+        candidates
+            .into_iter()
+            .min_by_key(|(_, filtered)| cost(filtered))
+            .expect("There's a candidate for each filter type")
+    }
+}
+
+fn compressed_length(bytes: &[u8]) -> usize {
+    let mut encoder = ZlibEncoder::new(Vec::new(), Compression::best());
+    // Writing to a `Vec` should not fail
+    encoder
+        .write_all(bytes)
+        .and_then(|_| encoder.finish())
+        .map_or(usize::MAX, |compressed| compressed.len())
+}
+
+impl FromStr for FilterStrategy {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "none" => Ok(FilterStrategy::Fixed(FilterType::None)),
+            "sub" => Ok(FilterStrategy::Fixed(FilterType::Sub)),
+            "up" => Ok(FilterStrategy::Fixed(FilterType::Up)),
+            "average" => Ok(FilterStrategy::Fixed(FilterType::Average)),
+            "paeth" => Ok(FilterStrategy::Fixed(FilterType::Paeth)),
+            "minimum-sum" => Ok(FilterStrategy::MinimumSum),
+            "brute-force" => Ok(FilterStrategy::BruteForce),
+            _ => bail!(
+                "Invalid filter strategy \"{}\", it should be none, sub, up, average, paeth, \
+                 minimum-sum or brute-force",
+                s
+            ),
+        }
+    }
+}
+
+impl Display for FilterStrategy {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            FilterStrategy::Fixed(FilterType::None) => "none",
+            FilterStrategy::Fixed(FilterType::Sub) => "sub",
+            FilterStrategy::Fixed(FilterType::Up) => "up",
+            FilterStrategy::Fixed(FilterType::Average) => "average",
+            FilterStrategy::Fixed(FilterType::Paeth) => "paeth",
+            FilterStrategy::MinimumSum => "minimum-sum",
+            FilterStrategy::BruteForce => "brute-force",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_filter_type_from_byte() {
+        for filter_type in FilterType::ALL {
+            assert_eq!(
+                FilterType::try_from(u8::from(filter_type)).unwrap(),
+                filter_type
+            );
+        }
+        assert!(FilterType::try_from(5).is_err());
+    }
+
+    #[test]
+    fn test_paeth_predictor() {
+        assert_eq!(paeth_predictor(10, 20, 10), 20);
+        assert_eq!(paeth_predictor(20, 10, 10), 20);
+        assert_eq!(paeth_predictor(10, 20, 20), 10);
+    }
+
+    #[test]
+    fn test_unfilter_scanlines() {
+        let previous = [10, 20, 30, 40];
+
+        let cases = [
+            (FilterType::None, [1, 2, 3, 4], [1, 2, 3, 4]),
+            (FilterType::Sub, [1, 2, 3, 4], [1, 2, 4, 6]),
+            (FilterType::Up, [1, 2, 3, 4], [11, 22, 33, 44]),
+            (FilterType::Average, [1, 2, 3, 4], [6, 12, 21, 30]),
+            (FilterType::Paeth, [1, 2, 3, 4], [11, 22, 33, 44]),
+        ];
+
+        for (filter_type, mut line, expected) in cases {
+            unfilter_scanline(filter_type, &mut line, Some(&previous), 2);
+            assert_eq!(line, expected, "{:?}", filter_type);
+        }
+    }
+
+    #[test]
+    fn test_unfilter_first_scanline() {
+        let mut line = [1, 2, 3, 4];
+        unfilter_scanline(FilterType::Up, &mut line, None, 1);
+        assert_eq!(line, [1, 2, 3, 4]);
+
+        let mut line = [1, 2, 3, 4];
+        unfilter_scanline(FilterType::Average, &mut line, None, 1);
+        assert_eq!(line, [1, 2, 4, 6]);
+    }
+
+    #[test]
+    fn test_filter_round_trip() {
+        let previous = [10, 20, 30, 40, 250, 1];
+        let line = [200, 3, 17, 90, 0, 255];
+
+        for filter_type in FilterType::ALL {
+            let mut filtered = filter_scanline(filter_type, &line, Some(&previous), 2);
+            unfilter_scanline(filter_type, &mut filtered, Some(&previous), 2);
+            assert_eq!(filtered, line, "{:?}", filter_type);
+
+            let mut filtered = filter_scanline(filter_type, &line, None, 3);
+            unfilter_scanline(filter_type, &mut filtered, None, 3);
+            assert_eq!(filtered, line, "{:?}", filter_type);
+        }
+    }
+
+    #[test]
+    fn test_minimum_sum_strategy() {
+        // A gradient is best predicted from the pixel to the left
+        let line: Vec<u8> = (0..32).map(|i| i * 3).collect();
+        let (filter_type, filtered) = FilterStrategy::MinimumSum.apply(&line, None, 1);
+
+        assert_eq!(filter_type, FilterType::Sub);
+        assert!(filtered[1..].iter().all(|b| *b == 3));
+
+        // A row equal to the previous one is best predicted from above
+        let (filter_type, _) = FilterStrategy::MinimumSum.apply(&line, Some(&line), 1);
+        assert_eq!(filter_type, FilterType::Up);
+    }
+
+    #[test]
+    fn test_fixed_and_brute_force_strategies() {
+        let line: Vec<u8> = (0..64u32).map(|i| (i * i * 31 + i * 7) as u8).collect();
+
+        let (filter_type, _) = FilterStrategy::Fixed(FilterType::Paeth).apply(&line, None, 1);
+        assert_eq!(filter_type, FilterType::Paeth);
+
+        let (filter_type, filtered) = FilterStrategy::BruteForce.apply(&line, Some(&line), 1);
+        assert_eq!(filter_type, FilterType::Up);
+        assert!(filtered.iter().all(|b| *b == 0));
+    }
+
+    #[test]
+    fn test_filter_strategy_from_str() {
+        for s in [
+            "none",
+            "sub",
+            "up",
+            "average",
+            "paeth",
+            "minimum-sum",
+            "brute-force",
+        ] {
+            assert_eq!(FilterStrategy::from_str(s).unwrap().to_string(), s);
+        }
+        assert!(FilterStrategy::from_str("adaptive").is_err());
+    }
+}
@@ -0,0 +1,97 @@
+use std::{fmt::Display, fs::File, io::Read, path::Path};
+
+use anyhow::{Context, Result};
+
+use crate::{gif::Gif, jpeg::Jpeg, riff::Riff, signature::Signature};
+
+/// The file formats messages can be hidden in, detected from the first bytes of a file
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Format {
+    /// PNG, MNG or JNG, which share the same chunk layout
+    Png(Signature),
+
+    Jpeg,
+
+    /// RIFF files, like WebP, WAV or AVI
+    Riff,
+
+    Gif,
+}
+
+impl Format {
+    /// Number of bytes needed to detect any format
+    const DETECTION_LENGTH: usize = 12;
+
+    /// Detects the format from the first bytes of a file
+    pub fn detect(bytes: &[u8]) -> Option<Self> {
+        if let Some(signature) = bytes
+            .get(..8)
+            .and_then(|header| Signature::from_bytes(header.try_into().ok()?))
+        {
+            return Some(Format::Png(signature));
+        }
+
+        if bytes.starts_with(&Jpeg::SIGNATURE) {
+            return Some(Format::Jpeg);
+        }
+
+        if bytes.starts_with(&Gif::SIGNATURE) {
+            return Some(Format::Gif);
+        }
+
+        // The form type follows the signature and the size
+        if bytes.len() >= 12 && bytes.starts_with(&Riff::SIGNATURE) {
+            return Some(Format::Riff);
+        }
+
+        None
+    }
+
+    /// Detects the format of a file, only reading its first bytes
+    pub fn detect_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path).context("Unable to open file")?;
+
+        let mut bytes = Vec::with_capacity(Self::DETECTION_LENGTH);
+        file.take(Self::DETECTION_LENGTH as u64)
+            .read_to_end(&mut bytes)
+            .context("Unable to read file")?;
+
+        Self::detect(&bytes)
+            .context("Unsupported file format, expected a PNG, MNG, JNG, JPEG, RIFF or GIF file")
+    }
+}
+
+impl Display for Format {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Format::Png(signature) => write!(f, "{}", signature),
+            Format::Jpeg => write!(f, "JPEG"),
+            Format::Riff => write!(f, "RIFF"),
+            Format::Gif => write!(f, "GIF"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_detect() {
+        assert_eq!(
+            Format::detect(&Signature::Mng.bytes()[..]),
+            Some(Format::Png(Signature::Mng))
+        );
+        assert_eq!(
+            Format::detect(&[0xff, 0xd8, 0xff, 0xe0, 0, 16]),
+            Some(Format::Jpeg)
+        );
+        assert_eq!(Format::detect(&[0xff, 0xd8]), None);
+        assert_eq!(
+            Format::detect(b"RIFF\x04\x00\x00\x00WEBP"),
+            Some(Format::Riff)
+        );
+        assert_eq!(Format::detect(b"RIFF"), None);
+        assert_eq!(Format::detect(b"GIF89a"), Some(Format::Gif));
+    }
+}
@@ -0,0 +1,238 @@
+//! Hides a payload across the frames of an APNG.
+//!
+//! The payload is split into one part per frame, each stored in a private chunk placed right
+//! after the frame data, between frames. Every part starts with the sequence number of the fcTL
+//! chunk of its frame and the number of parts, so the payload can be rebuilt from the parts
+//! alone, even after a player or editor drops the animation chunks.
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, selector::Selector};
+
+/// Length of the header of each part: the fcTL sequence number and the number of parts
+const HEADER_LENGTH: usize = 8;
+
+/// A part of a payload, stored after the frame data of the fcTL chunk `sequence_number`
+#[derive(Debug, Clone, PartialEq)]
+pub struct Part {
+    pub sequence_number: u32,
+    pub part_count: u32,
+    pub data: Vec<u8>,
+}
+
+impl Part {
+    pub fn as_chunk(&self, chunk_type: ChunkType) -> Chunk {
+        let mut data = Vec::with_capacity(HEADER_LENGTH + self.data.len());
+        data.extend(self.sequence_number.to_be_bytes());
+        data.extend(self.part_count.to_be_bytes());
+        data.extend(&self.data);
+
+        Chunk::new(chunk_type, data)
+    }
+}
+
+impl TryFrom<&Chunk> for Part {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        let data = chunk.data();
+        ensure!(
+            data.len() >= HEADER_LENGTH,
+            "The {} chunk is too short to hold a part of a payload",
+            chunk.chunk_type()
+        );
+
+        let part = Self {
+            sequence_number: u32::from_be_bytes([data[0], data[1], data[2], data[3]]),
+            part_count: u32::from_be_bytes([data[4], data[5], data[6], data[7]]),
+            data: data[HEADER_LENGTH..].to_vec(),
+        };
+        ensure!(
+            part.part_count > 0,
+            "The {} chunk holds a part of a payload without any part",
+            chunk.chunk_type()
+        );
+
+        Ok(part)
+    }
+}
+
+/// Hides `payload` in chunks of type `chunk_type` placed after the data of each frame of an
+/// APNG, replacing any payload already stored in chunks of that type. Returns the number of
+/// parts, which is the number of frames.
+pub fn hide(png: &mut Png, chunk_type: &ChunkType, payload: &[u8]) -> Result<usize> {
+    chunk_type.is_valid_for_message()?;
+
+    png.retain(|chunk| chunk.chunk_type() != chunk_type);
+    let animation = png
+        .animation()?
+        .context("The image has no acTL chunk, only animated images can spread a payload")?;
+
+    let frames = animation.frames;
+    let part_length = payload.len().div_ceil(frames.len());
+    let mut parts = payload.chunks(part_length.max(1));
+
+    // The chunk indices of the frames stay valid as long as the parts are inserted from the
+    // last frame, so they're computed first
+    let mut insertions: Vec<(usize, Part)> = frames
+        .iter()
+        .map(|frame| {
+            let index = frame
+                .data_indices
+                .last()
+                .map_or(frame.control_index, |index| *index)
+                + 1;
+            let part = Part {
+                sequence_number: frame.control.sequence_number,
+                part_count: frames.len() as u32,
+                data: parts.next().unwrap_or_default().to_vec(),
+            };
+            (index, part)
+        })
+        .collect();
+
+    while let Some((index, part)) = insertions.pop() {
+        png.insert_chunk(index, part.as_chunk(*chunk_type));
+    }
+
+    Ok(frames.len())
+}
+
+/// Rebuilds a payload hidden with [hide] from the chunks matching `selector`, ordering the
+/// parts by their fcTL sequence number. The animation chunks themselves aren't needed.
+pub fn reveal(png: &Png, selector: &Selector) -> Result<Vec<u8>> {
+    let mut parts = png
+        .select(selector)
+        .map(|(_, chunk)| Part::try_from(chunk))
+        .collect::<Result<Vec<_>>>()?;
+
+    if parts.is_empty() {
+        bail!("No chunks matching \"{}\" found", selector);
+    }
+
+    parts.sort_by_key(|part| part.sequence_number);
+
+    let part_count = parts[0].part_count;
+    ensure!(
+        parts.iter().all(|part| part.part_count == part_count),
+        "The parts belong to different payloads"
+    );
+    ensure!(
+        parts
+            .windows(2)
+            .all(|pair| pair[0].sequence_number != pair[1].sequence_number),
+        "Two parts are tied to the same frame"
+    );
+    ensure!(
+        parts.len() == part_count as usize,
+        "Found {} part(s) of a payload made of {} parts",
+        parts.len(),
+        part_count
+    );
+
+    Ok(parts.into_iter().flat_map(|part| part.data).collect())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::apng::{self, FrameControl};
+    use crate::ihdr::{ColorType, Ihdr};
+    use crate::image_data::{EncodeOptions, RawImage};
+    use std::str::FromStr;
+
+    fn frame(width: u32, height: u32, value: u8) -> Png {
+        let ihdr = Ihdr::new(width, height, 8, ColorType::Grayscale, false).unwrap();
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]);
+        let mut png = Png::from_chunks(vec![ihdr.as_chunk(), iend]);
+
+        let rows = vec![vec![value; width as usize]; height as usize];
+        let image = RawImage::new(ihdr, rows).unwrap();
+        png.insert_chunk(1, Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![]));
+        png.set_image_data(&image, &EncodeOptions::default())
+            .unwrap();
+
+        png
+    }
+
+    fn animated_png() -> Png {
+        let frames = vec![frame(4, 4, 0), frame(2, 2, 100), frame(3, 1, 200)];
+        let (delay_num, delay_den) = FrameControl::delay_from_ms(100).unwrap();
+        apng::build(&frames, delay_num, delay_den, 0).unwrap()
+    }
+
+    fn chunk_type() -> ChunkType {
+        ChunkType::from_str("ruSt").unwrap()
+    }
+
+    fn selector() -> Selector {
+        Selector::from_str("ruSt").unwrap()
+    }
+
+    #[test]
+    fn test_hide_and_reveal() {
+        let mut png = animated_png();
+        let payload = b"spread across the frames";
+
+        assert_eq!(hide(&mut png, &chunk_type(), payload).unwrap(), 3);
+        assert_eq!(reveal(&png, &selector()).unwrap(), payload);
+
+        // The animation is still valid, with a part after the data of each frame
+        let animation = png.animation().unwrap().unwrap();
+        for frame in &animation.frames {
+            let after = &png.chunks()[frame.data_indices.last().unwrap() + 1];
+            assert_eq!(after.chunk_type(), &chunk_type());
+            let part = Part::try_from(after).unwrap();
+            assert_eq!(part.sequence_number, frame.control.sequence_number);
+        }
+    }
+
+    #[test]
+    fn test_hide_replaces_payload() {
+        let mut png = animated_png();
+        hide(&mut png, &chunk_type(), b"first payload").unwrap();
+        hide(&mut png, &chunk_type(), b"hi").unwrap();
+
+        assert_eq!(png.iter_by_type("ruSt").count(), 3);
+        assert_eq!(reveal(&png, &selector()).unwrap(), b"hi");
+
+        hide(&mut png, &chunk_type(), b"").unwrap();
+        assert_eq!(reveal(&png, &selector()).unwrap(), b"");
+    }
+
+    #[test]
+    fn test_reveal_without_animation() {
+        let mut png = animated_png();
+        hide(&mut png, &chunk_type(), b"survives flattening").unwrap();
+
+        // Players that don't support APNG drop the animation chunks but keep safe to copy chunks
+        png.retain(|chunk| !matches!(&chunk.chunk_type().bytes(), b"acTL" | b"fcTL" | b"fdAT"));
+        assert_eq!(reveal(&png, &selector()).unwrap(), b"survives flattening");
+    }
+
+    #[test]
+    fn test_reveal_missing_part() {
+        let mut png = animated_png();
+        hide(&mut png, &chunk_type(), b"incomplete payload").unwrap();
+
+        let index = png
+            .chunks()
+            .iter()
+            .rposition(|chunk| chunk.chunk_type() == &chunk_type())
+            .unwrap();
+        png.remove_chunk(index);
+
+        assert!(reveal(&png, &selector()).is_err());
+    }
+
+    #[test]
+    fn test_hide_invalid() {
+        let mut png = frame(2, 2, 0);
+        assert!(hide(&mut png, &chunk_type(), b"not animated").is_err());
+
+        let mut png = animated_png();
+        let public = ChunkType::from_str("RUST").unwrap();
+        assert!(hide(&mut png, &public, b"critical").is_err());
+        assert!(reveal(&png, &selector()).is_err());
+    }
+}
@@ -0,0 +1,532 @@
+//! Reads and writes the blocks of GIF files, to hide messages in comment and application
+//! extensions.
+//!
+//! After the header and the logical screen descriptor, GIF files are a series of extensions and
+//! images ending with a trailer byte. Extensions and image data are split into sub-blocks of at
+//! most 255 bytes. Messages are stored in a comment extension starting with their chunk type and
+//! a NUL byte, like JPEG comments, or in an application extension whose identifier is the chunk
+//! type followed by `pngm`. Both are exposed as [Chunk]s, selected like PNG chunks.
+
+use std::{borrow::Cow, fs, path::Path, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    container::{Container, PayloadOptions},
+    selector::Selector,
+};
+
+/// Introduces an extension block
+const EXTENSION_INTRODUCER: u8 = 0x21;
+/// Introduces an image block
+const IMAGE_SEPARATOR: u8 = 0x2c;
+/// Ends the file
+const TRAILER: u8 = 0x3b;
+
+/// Label of comment extensions
+pub const COMMENT_LABEL: u8 = 0xfe;
+/// Label of application extensions
+pub const APPLICATION_LABEL: u8 = 0xff;
+
+/// Length of the logical screen descriptor following the header
+const SCREEN_DESCRIPTOR_LENGTH: usize = 7;
+/// Length of an image descriptor, after the image separator
+const IMAGE_DESCRIPTOR_LENGTH: usize = 9;
+
+/// End of the application identifier and the authentication code of message extensions, after
+/// the chunk type
+const APPLICATION_SUFFIX: &[u8; 7] = b"pngm1.0";
+/// Length of the first sub-block of application extensions: an 8-byte identifier and a 3-byte
+/// authentication code
+const APPLICATION_HEADER_LENGTH: usize = 11;
+
+/// Where a message is stored in a GIF image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadBlock {
+    /// An application extension identified by the chunk type
+    Application,
+
+    /// A comment extension
+    Comment,
+}
+
+/// Size of a color table from the packed fields of a descriptor, 0 if there's none
+fn color_table_length(packed: u8) -> usize {
+    if packed & 0x80 == 0 {
+        return 0;
+    }
+
+    3 * (1 << ((packed & 0x07) + 1))
+}
+
+/// Reads sub-blocks starting at `offset` up to the block terminator (an empty sub-block).
+/// Returns the sub-blocks and the offset after the terminator.
+fn read_sub_blocks(bytes: &[u8], mut offset: usize) -> Result<(Vec<Vec<u8>>, usize)> {
+    let mut sub_blocks = Vec::new();
+
+    loop {
+        let size = *bytes
+            .get(offset)
+            .context("Invalid GIF file, the file ends in the middle of a block")?
+            as usize;
+        offset += 1;
+
+        if size == 0 {
+            return Ok((sub_blocks, offset));
+        }
+
+        ensure!(
+            offset + size <= bytes.len(),
+            "Invalid GIF file, the sub-block at offset {} is larger than the file",
+            offset - 1
+        );
+        sub_blocks.push(bytes[offset..offset + size].to_vec());
+        offset += size;
+    }
+}
+
+fn write_sub_blocks(bytes: &mut Vec<u8>, sub_blocks: &[Vec<u8>]) {
+    for sub_block in sub_blocks {
+        bytes.push(sub_block.len() as u8);
+        bytes.extend(sub_block);
+    }
+    bytes.push(0);
+}
+
+/// Splits data into sub-blocks of at most 255 bytes
+fn split_sub_blocks(data: &[u8]) -> Vec<Vec<u8>> {
+    data.chunks(u8::MAX as usize)
+        .map(|sub_block| sub_block.to_vec())
+        .collect()
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Block {
+    /// An extension, with its label and data sub-blocks
+    Extension { label: u8, sub_blocks: Vec<Vec<u8>> },
+
+    /// An image: its descriptor, local color table and LZW minimum code size, followed by the
+    /// sub-blocks of the compressed image data
+    Image {
+        header: Vec<u8>,
+        sub_blocks: Vec<Vec<u8>>,
+    },
+}
+
+impl Block {
+    /// Returns the chunk type and data of extensions holding messages
+    pub fn as_chunk(&self) -> Option<Chunk> {
+        let Block::Extension { label, sub_blocks } = self else {
+            return None;
+        };
+
+        let (tag, data) = match *label {
+            COMMENT_LABEL => {
+                let data = sub_blocks.concat();
+                if data.len() < 5 || data[4] != 0 {
+                    return None;
+                }
+                (data[..4].to_vec(), data[5..].to_vec())
+            }
+            APPLICATION_LABEL => {
+                let header = sub_blocks.first()?;
+                if header.len() != APPLICATION_HEADER_LENGTH
+                    || header[4..] != APPLICATION_SUFFIX[..]
+                {
+                    return None;
+                }
+                (header[..4].to_vec(), sub_blocks[1..].concat())
+            }
+            _ => return None,
+        };
+
+        let tag = std::str::from_utf8(&tag).ok()?;
+        let chunk_type = ChunkType::from_str(tag).ok()?;
+
+        Some(Chunk::new(chunk_type, data))
+    }
+
+    /// Creates an extension holding the data of `chunk`
+    fn from_chunk(chunk: &Chunk, payload_block: PayloadBlock) -> Self {
+        let chunk_type = chunk.chunk_type().bytes();
+
+        match payload_block {
+            PayloadBlock::Comment => {
+                let mut data = chunk_type.to_vec();
+                data.push(0);
+                data.extend(chunk.data());
+
+                Block::Extension {
+                    label: COMMENT_LABEL,
+                    sub_blocks: split_sub_blocks(&data),
+                }
+            }
+            PayloadBlock::Application => {
+                let mut header = chunk_type.to_vec();
+                header.extend(APPLICATION_SUFFIX);
+
+                let mut sub_blocks = vec![header];
+                sub_blocks.extend(split_sub_blocks(chunk.data()));
+
+                Block::Extension {
+                    label: APPLICATION_LABEL,
+                    sub_blocks,
+                }
+            }
+        }
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        match self {
+            Block::Extension { label, sub_blocks } => {
+                bytes.push(EXTENSION_INTRODUCER);
+                bytes.push(*label);
+                write_sub_blocks(&mut bytes, sub_blocks);
+            }
+            Block::Image { header, sub_blocks } => {
+                bytes.push(IMAGE_SEPARATOR);
+                bytes.extend(header);
+                write_sub_blocks(&mut bytes, sub_blocks);
+            }
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Gif {
+    /// The signature and version, the logical screen descriptor and the global color table
+    header: Vec<u8>,
+    blocks: Vec<Block>,
+
+    /// Bytes after the trailer, kept so they're written back unchanged
+    trailing: Vec<u8>,
+}
+
+impl Gif {
+    pub const SIGNATURE: [u8; 3] = *b"GIF";
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path).context("Unable to read file")?;
+        Self::try_from(&bytes[..])
+    }
+
+    /// The version of the format, `87a` or `89a`
+    pub fn version(&self) -> &[u8] {
+        &self.header[3..6]
+    }
+
+    pub fn blocks(&self) -> &[Block] {
+        &self.blocks
+    }
+
+    /// Returns the extensions holding messages as chunks, along with their index in the block
+    /// list
+    pub fn chunks(&self) -> Vec<(usize, Chunk)> {
+        self.blocks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, block)| block.as_chunk().map(|chunk| (index, chunk)))
+            .collect()
+    }
+
+    pub fn select(&self, selector: &Selector) -> Vec<(usize, Chunk)> {
+        self.chunks()
+            .into_iter()
+            .filter(|(index, chunk)| selector.matches(*index, chunk))
+            .collect()
+    }
+
+    /// Adds an extension holding the data of `chunk` right before the trailer. Extensions only
+    /// exist since version 89a, so older files are upgraded.
+    pub fn append_chunk(&mut self, chunk: &Chunk, payload_block: PayloadBlock) {
+        self.header[3..6].copy_from_slice(b"89a");
+        self.blocks.push(Block::from_chunk(chunk, payload_block));
+    }
+
+    /// Replaces the data of the first extension holding a message of the chunk type of `chunk`,
+    /// keeping its position and kind. Returns false if there's no such extension.
+    pub fn replace_chunk(&mut self, chunk: &Chunk) -> bool {
+        let Some(index) = self
+            .chunks()
+            .into_iter()
+            .find_map(|(index, c)| (c.chunk_type() == chunk.chunk_type()).then_some(index))
+        else {
+            return false;
+        };
+
+        let payload_block = match self.blocks[index] {
+            Block::Extension {
+                label: COMMENT_LABEL,
+                ..
+            } => PayloadBlock::Comment,
+            _ => PayloadBlock::Application,
+        };
+        self.blocks[index] = Block::from_chunk(chunk, payload_block);
+
+        true
+    }
+
+    /// Removes the extensions holding messages matching the selector, returning them as chunks
+    pub fn remove_selected(&mut self, selector: &Selector) -> Vec<Chunk> {
+        let selected = self.select(selector);
+
+        // Removing from the end keeps the indices of the other selected blocks valid
+        for (index, _) in selected.iter().rev() {
+            self.blocks.remove(*index);
+        }
+
+        selected.into_iter().map(|(_, chunk)| chunk).collect()
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = self.header.clone();
+        for block in self.blocks.iter() {
+            bytes.extend(block.as_bytes());
+        }
+        bytes.push(TRAILER);
+        bytes.extend(&self.trailing);
+        bytes
+    }
+}
+
+impl Container for Gif {
+    fn open(path: &Path) -> Result<Self> {
+        Self::from_file(path)
+    }
+
+    fn segments(&self) -> Vec<(usize, Cow<'_, Chunk>)> {
+        self.chunks()
+            .into_iter()
+            .map(|(index, chunk)| (index, Cow::Owned(chunk)))
+            .collect()
+    }
+
+    /// Adds an application or comment extension before the trailer, the position is ignored
+    fn insert_payload(&mut self, chunk: Chunk, options: &PayloadOptions) -> Result<()> {
+        let block = if options.comment {
+            PayloadBlock::Comment
+        } else {
+            PayloadBlock::Application
+        };
+        self.append_chunk(&chunk, block);
+        Ok(())
+    }
+
+    fn replace_payload(&mut self, chunk: &Chunk) -> Result<bool> {
+        Ok(self.replace_chunk(chunk))
+    }
+
+    fn remove_payloads(&mut self, selector: &Selector) -> Result<Vec<Chunk>> {
+        Ok(self.remove_selected(selector))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+impl TryFrom<&[u8]> for Gif {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        ensure!(
+            bytes.starts_with(b"GIF87a") || bytes.starts_with(b"GIF89a"),
+            "Invalid GIF file, the file does not start with a GIF87a or GIF89a header"
+        );
+        ensure!(
+            bytes.len() >= 6 + SCREEN_DESCRIPTOR_LENGTH,
+            "Invalid GIF file, the logical screen descriptor is missing"
+        );
+
+        let packed = bytes[10];
+        let header_length = 6 + SCREEN_DESCRIPTOR_LENGTH + color_table_length(packed);
+        ensure!(
+            header_length <= bytes.len(),
+            "Invalid GIF file, the global color table is truncated"
+        );
+
+        let mut blocks = Vec::new();
+        let mut offset = header_length;
+
+        loop {
+            let introducer = *bytes
+                .get(offset)
+                .context("Invalid GIF file, the file ends without a trailer")?;
+
+            match introducer {
+                TRAILER => break,
+                EXTENSION_INTRODUCER => {
+                    let label = *bytes
+                        .get(offset + 1)
+                        .context("Invalid GIF file, the extension label is missing")?;
+                    let (sub_blocks, end) = read_sub_blocks(bytes, offset + 2)?;
+
+                    blocks.push(Block::Extension { label, sub_blocks });
+                    offset = end;
+                }
+                IMAGE_SEPARATOR => {
+                    let descriptor_end = offset + 1 + IMAGE_DESCRIPTOR_LENGTH;
+                    ensure!(
+                        descriptor_end <= bytes.len(),
+                        "Invalid GIF file, the image descriptor at offset {} is truncated",
+                        offset
+                    );
+
+                    // The local color table and the LZW minimum code size follow the descriptor
+                    let header_end =
+                        descriptor_end + color_table_length(bytes[descriptor_end - 1]) + 1;
+                    ensure!(
+                        header_end <= bytes.len(),
+                        "Invalid GIF file, the image at offset {} is truncated",
+                        offset
+                    );
+                    let (sub_blocks, end) = read_sub_blocks(bytes, header_end)?;
+
+                    blocks.push(Block::Image {
+                        header: bytes[offset + 1..header_end].to_vec(),
+                        sub_blocks,
+                    });
+                    offset = end;
+                }
+                _ => bail!(
+                    "Invalid GIF file, unknown block introducer 0x{:02x} at offset {}",
+                    introducer,
+                    offset
+                ),
+            }
+        }
+
+        Ok(Self {
+            header: bytes[..header_length].to_vec(),
+            blocks,
+            trailing: bytes[offset + 1..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A 1x1 GIF87a image with a 2-color global color table
+    fn testing_gif_bytes() -> Vec<u8> {
+        #[rustfmt::skip]
+        let bytes = vec![
+            b'G', b'I', b'F', b'8', b'7', b'a',
+            1, 0, 1, 0, 0x80, 0, 0,                   // Logical screen descriptor
+            0, 0, 0, 255, 255, 255,                   // Global color table
+            0x2c, 0, 0, 0, 0, 1, 0, 1, 0, 0,          // Image descriptor
+            2,                                        // LZW minimum code size
+            2, 0x44, 0x01, 0,                         // Image data
+            0x3b,
+        ];
+        bytes
+    }
+
+    fn testing_gif() -> Gif {
+        Gif::try_from(&testing_gif_bytes()[..]).unwrap()
+    }
+
+    fn message_chunk(chunk_type: &str, message: &[u8]) -> Chunk {
+        Chunk::new(ChunkType::from_str(chunk_type).unwrap(), message.to_vec())
+    }
+
+    #[test]
+    fn test_parse_blocks() {
+        let gif = testing_gif();
+
+        assert_eq!(gif.version(), b"87a");
+        assert_eq!(
+            gif.blocks(),
+            &[Block::Image {
+                header: vec![0, 0, 0, 0, 1, 0, 1, 0, 0, 2],
+                sub_blocks: vec![vec![0x44, 0x01]],
+            }]
+        );
+        assert_eq!(gif.as_bytes(), testing_gif_bytes());
+    }
+
+    #[test]
+    fn test_append_splits_sub_blocks() {
+        let mut gif = testing_gif();
+        let message = vec![b'a'; 600];
+        gif.append_chunk(&message_chunk("ruSt", &message), PayloadBlock::Application);
+        gif.append_chunk(&message_chunk("ruSt", b"a comment"), PayloadBlock::Comment);
+
+        assert_eq!(gif.version(), b"89a");
+        let Block::Extension { label, sub_blocks } = &gif.blocks()[1] else {
+            panic!("Expected an extension");
+        };
+        assert_eq!(*label, APPLICATION_LABEL);
+        assert_eq!(sub_blocks[0], b"ruStpngm1.0");
+        let sizes: Vec<usize> = sub_blocks[1..].iter().map(|b| b.len()).collect();
+        assert_eq!(sizes, vec![255, 255, 90]);
+
+        let gif = Gif::try_from(&gif.as_bytes()[..]).unwrap();
+        let selector = Selector::from_str("ruSt").unwrap();
+        let messages: Vec<Vec<u8>> = gif
+            .select(&selector)
+            .into_iter()
+            .map(|(_, chunk)| chunk.data().clone())
+            .collect();
+        assert_eq!(messages, vec![message, b"a comment".to_vec()]);
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let mut gif = testing_gif();
+        gif.append_chunk(&message_chunk("ruSt", b"first"), PayloadBlock::Comment);
+
+        assert!(gif.replace_chunk(&message_chunk("ruSt", b"second")));
+        assert!(!gif.replace_chunk(&message_chunk("abCd", b"missing")));
+        assert_eq!(gif.chunks()[0].1.data(), b"second");
+        assert!(matches!(
+            gif.blocks()[1],
+            Block::Extension {
+                label: COMMENT_LABEL,
+                ..
+            }
+        ));
+
+        let selector = Selector::from_str("*").unwrap();
+        assert_eq!(gif.remove_selected(&selector).len(), 1);
+        assert_eq!(gif.blocks().len(), 1);
+    }
+
+    #[test]
+    fn test_other_extensions_are_not_chunks() {
+        let mut bytes = testing_gif_bytes();
+        bytes[3..6].copy_from_slice(b"89a");
+
+        // A NETSCAPE2.0 looping extension and a comment without a chunk type
+        #[rustfmt::skip]
+        let extensions = [
+            &[0x21, 0xff, 11][..], b"NETSCAPE2.0", &[3, 1, 0, 0, 0],
+            &[0x21, 0xfe, 5], b"hello", &[0],
+        ]
+        .concat();
+        bytes.splice(19..19, extensions);
+
+        let gif = Gif::try_from(&bytes[..]).unwrap();
+        assert_eq!(gif.blocks().len(), 3);
+        assert!(gif.chunks().is_empty());
+        assert_eq!(gif.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_invalid_gif() {
+        let bytes = testing_gif_bytes();
+
+        assert!(Gif::try_from(&bytes[1..]).is_err());
+        assert!(Gif::try_from(&bytes[..bytes.len() - 1]).is_err());
+        assert!(Gif::try_from(&bytes[..15]).is_err());
+
+        let mut unknown_block = bytes.clone();
+        unknown_block[19] = 0x99;
+        assert!(Gif::try_from(&unknown_block[..]).is_err());
+    }
+}
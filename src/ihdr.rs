@@ -0,0 +1,276 @@
+use std::fmt::Display;
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType};
+
+/// How pixels are represented in the image data
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ColorType {
+    Grayscale,
+    Rgb,
+    Indexed,
+    GrayscaleAlpha,
+    Rgba,
+}
+
+impl ColorType {
+    /// Number of samples in each pixel
+    pub fn channels(&self) -> u8 {
+        match self {
+            ColorType::Grayscale | ColorType::Indexed => 1,
+            ColorType::GrayscaleAlpha => 2,
+            ColorType::Rgb => 3,
+            ColorType::Rgba => 4,
+        }
+    }
+
+    /// Bit depths allowed by the PNG spec for this color type
+    pub fn allowed_bit_depths(&self) -> &'static [u8] {
+        match self {
+            ColorType::Grayscale => &[1, 2, 4, 8, 16],
+            ColorType::Indexed => &[1, 2, 4, 8],
+            ColorType::Rgb | ColorType::GrayscaleAlpha | ColorType::Rgba => &[8, 16],
+        }
+    }
+}
+
+impl TryFrom<u8> for ColorType {
+    type Error = Error;
+
+    fn try_from(value: u8) -> Result<Self, Self::Error> {
+        match value {
+            0 => Ok(ColorType::Grayscale),
+            2 => Ok(ColorType::Rgb),
+            3 => Ok(ColorType::Indexed),
+            4 => Ok(ColorType::GrayscaleAlpha),
+            6 => Ok(ColorType::Rgba),
+            _ => bail!("Invalid color type {}", value),
+        }
+    }
+}
+
+impl From<ColorType> for u8 {
+    fn from(color_type: ColorType) -> Self {
+        match color_type {
+            ColorType::Grayscale => 0,
+            ColorType::Rgb => 2,
+            ColorType::Indexed => 3,
+            ColorType::GrayscaleAlpha => 4,
+            ColorType::Rgba => 6,
+        }
+    }
+}
+
+impl Display for ColorType {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            ColorType::Grayscale => "Grayscale",
+            ColorType::Rgb => "RGB",
+            ColorType::Indexed => "Indexed",
+            ColorType::GrayscaleAlpha => "Grayscale + alpha",
+            ColorType::Rgba => "RGBA",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// The image header, stored in the IHDR chunk
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Ihdr {
+    width: u32,
+    height: u32,
+    bit_depth: u8,
+    color_type: ColorType,
+    interlaced: bool,
+}
+
+impl Ihdr {
+    /// Length of the IHDR chunk data
+    pub const LENGTH: usize = 13;
+
+    /// Largest width and height allowed by the PNG spec, 2^31 - 1
+    pub const MAX_SIZE: u32 = i32::MAX as u32;
+
+    pub fn new(
+        width: u32,
+        height: u32,
+        bit_depth: u8,
+        color_type: ColorType,
+        interlaced: bool,
+    ) -> Result<Self> {
+        ensure!(
+            width > 0 && height > 0,
+            "Invalid image size {}x{}, the width and height should not be zero",
+            width,
+            height
+        );
+
+        ensure!(
+            width <= Self::MAX_SIZE && height <= Self::MAX_SIZE,
+            "Invalid image size {}x{}, the width and height should be at most {}",
+            width,
+            height,
+            Self::MAX_SIZE
+        );
+
+        ensure!(
+            color_type.allowed_bit_depths().contains(&bit_depth),
+            "Invalid bit depth {} for the {} color type",
+            bit_depth,
+            color_type
+        );
+
+        Ok(Self {
+            width,
+            height,
+            bit_depth,
+            color_type,
+            interlaced,
+        })
+    }
+
+    pub fn width(&self) -> u32 {
+        self.width
+    }
+
+    pub fn height(&self) -> u32 {
+        self.height
+    }
+
+    pub fn bit_depth(&self) -> u8 {
+        self.bit_depth
+    }
+
+    pub fn color_type(&self) -> ColorType {
+        self.color_type
+    }
+
+    /// Checks if the image data uses Adam7 interlacing
+    pub fn is_interlaced(&self) -> bool {
+        self.interlaced
+    }
+
+    pub fn set_interlaced(&mut self, interlaced: bool) {
+        self.interlaced = interlaced;
+    }
+
+    pub fn bits_per_pixel(&self) -> usize {
+        self.color_type.channels() as usize * self.bit_depth as usize
+    }
+
+    /// Number of bytes per complete pixel, rounded up to 1. Used by the filters to find the
+    /// corresponding byte in the previous pixel.
+    pub fn filter_bytes_per_pixel(&self) -> usize {
+        self.bits_per_pixel().div_ceil(8)
+    }
+
+    /// Number of bytes in a row of `width` pixels, without the filter type byte
+    pub fn row_length(&self, width: u32) -> usize {
+        (width as usize * self.bits_per_pixel()).div_ceil(8)
+    }
+
+    pub fn as_chunk(&self) -> Chunk {
+        let mut data = Vec::with_capacity(Self::LENGTH);
+        data.extend(self.width.to_be_bytes());
+        data.extend(self.height.to_be_bytes());
+        data.push(self.bit_depth);
+        data.push(self.color_type.into());
+        // Compression and filter methods, 0 is the only method defined by the PNG spec
+        data.push(0);
+        data.push(0);
+        data.push(self.interlaced as u8);
+
+        // Since "IHDR" is a valid chunk type, this should not fail
+        let chunk_type = ChunkType::try_from(*b"IHDR").expect("IHDR is a valid chunk type");
+        Chunk::new(chunk_type, data)
+    }
+}
+
+impl TryFrom<&[u8]> for Ihdr {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        let data: &[u8; Self::LENGTH] = data.try_into().context(format!(
+            "Invalid IHDR chunk, its data should have {} bytes",
+            Self::LENGTH
+        ))?;
+
+        let width = u32::from_be_bytes([data[0], data[1], data[2], data[3]]);
+        let height = u32::from_be_bytes([data[4], data[5], data[6], data[7]]);
+        let color_type = ColorType::try_from(data[9])?;
+
+        ensure!(data[10] == 0, "Invalid compression method {}", data[10]);
+        ensure!(data[11] == 0, "Invalid filter method {}", data[11]);
+        ensure!(data[12] <= 1, "Invalid interlace method {}", data[12]);
+
+        Self::new(width, height, data[8], color_type, data[12] == 1)
+    }
+}
+
+impl TryFrom<&Chunk> for Ihdr {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        ensure!(
+            chunk.chunk_type().bytes() == *b"IHDR",
+            "Expected an IHDR chunk, found \"{}\"",
+            chunk.chunk_type()
+        );
+
+        Self::try_from(&chunk.data()[..])
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_ihdr_from_bytes() {
+        let data = [0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 0];
+        let ihdr = Ihdr::try_from(&data[..]).unwrap();
+
+        assert_eq!(ihdr.width(), 50);
+        assert_eq!(ihdr.height(), 40);
+        assert_eq!(ihdr.bit_depth(), 8);
+        assert_eq!(ihdr.color_type(), ColorType::Rgba);
+        assert!(!ihdr.is_interlaced());
+    }
+
+    #[test]
+    fn test_invalid_ihdr() {
+        // Too short
+        assert!(Ihdr::try_from(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0][..]).is_err());
+        // Zero width
+        assert!(Ihdr::try_from(&[0, 0, 0, 0, 0, 0, 0, 40, 8, 6, 0, 0, 0][..]).is_err());
+        // Height above 2^31 - 1
+        assert!(Ihdr::try_from(&[0, 0, 0, 50, 128, 0, 0, 0, 8, 6, 0, 0, 0][..]).is_err());
+        // Invalid color type
+        assert!(Ihdr::try_from(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 5, 0, 0, 0][..]).is_err());
+        // Invalid bit depth for RGB
+        assert!(Ihdr::try_from(&[0, 0, 0, 50, 0, 0, 0, 40, 4, 2, 0, 0, 0][..]).is_err());
+        // Invalid interlace method
+        assert!(Ihdr::try_from(&[0, 0, 0, 50, 0, 0, 0, 40, 8, 6, 0, 0, 2][..]).is_err());
+    }
+
+    #[test]
+    fn test_row_length() {
+        let ihdr = Ihdr::new(10, 1, 1, ColorType::Grayscale, false).unwrap();
+        assert_eq!(ihdr.row_length(10), 2);
+        assert_eq!(ihdr.filter_bytes_per_pixel(), 1);
+
+        let ihdr = Ihdr::new(10, 1, 16, ColorType::Rgb, false).unwrap();
+        assert_eq!(ihdr.row_length(10), 60);
+        assert_eq!(ihdr.filter_bytes_per_pixel(), 6);
+    }
+
+    #[test]
+    fn test_ihdr_chunk_round_trip() {
+        let ihdr = Ihdr::new(640, 480, 4, ColorType::Indexed, true).unwrap();
+        let chunk = ihdr.as_chunk();
+
+        assert_eq!(chunk.length(), 13);
+        assert_eq!(Ihdr::try_from(&chunk).unwrap(), ihdr);
+    }
+}
@@ -0,0 +1,583 @@
+use std::io::{Read, Write};
+
+use anyhow::{bail, ensure, Context, Result};
+use flate2::{read::ZlibDecoder, write::ZlibEncoder, Compression};
+
+use crate::{
+    adam7,
+    filter::{self, FilterStrategy, FilterType},
+    ihdr::Ihdr,
+};
+
+/// A row of the image as stored in the IDAT chunks, with its filter still applied
+#[derive(Debug, Clone, PartialEq)]
+pub struct Scanline {
+    filter_type: FilterType,
+    data: Vec<u8>,
+}
+
+impl Scanline {
+    pub fn filter_type(&self) -> FilterType {
+        self.filter_type
+    }
+
+    /// Filtered bytes of the row, without the filter type byte
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+}
+
+/// The decompressed image data of a PNG, split into filtered scanlines.
+///
+/// The scanlines are kept in the order they are stored in. For interlaced images, that's the
+/// scanlines of each of the seven Adam7 passes, one pass after the other.
+#[derive(Debug, Clone)]
+pub struct ImageData {
+    ihdr: Ihdr,
+    scanlines: Vec<Scanline>,
+}
+
+/// Width and height of each pass of the image, or of the whole image if it's not interlaced
+fn pass_sizes(ihdr: &Ihdr) -> Vec<(u32, u32)> {
+    if ihdr.is_interlaced() {
+        adam7::PASSES
+            .iter()
+            .map(|pass| {
+                (
+                    pass.width(ihdr.width()),
+                    pass.height(ihdr.width(), ihdr.height()),
+                )
+            })
+            .collect()
+    } else {
+        vec![(ihdr.width(), ihdr.height())]
+    }
+}
+
+/// Length of the decompressed image data, with the filter type byte of each scanline. Fails if
+/// the length doesn't fit in a `usize`.
+fn filtered_length(ihdr: &Ihdr) -> Result<usize> {
+    pass_sizes(ihdr)
+        .into_iter()
+        .try_fold(0usize, |total, (width, height)| {
+            let row_length = (width as usize)
+                .checked_mul(ihdr.bits_per_pixel())?
+                .div_ceil(8)
+                .checked_add(1)?;
+            total.checked_add(row_length.checked_mul(height as usize)?)
+        })
+        .context(format!(
+            "The {}x{} image is too large to decode",
+            ihdr.width(),
+            ihdr.height()
+        ))
+}
+
+impl ImageData {
+    /// Inflates the zlib stream stored in the concatenated data of the IDAT chunks and splits
+    /// it into scanlines. Inflating stops as soon as the data is longer than the image needs.
+    pub fn decode(ihdr: Ihdr, compressed: &[u8]) -> Result<Self> {
+        let expected_length = filtered_length(&ihdr)?;
+
+        let mut bytes = Vec::new();
+        ZlibDecoder::new(compressed)
+            .take(expected_length as u64 + 1)
+            .read_to_end(&mut bytes)
+            .context("Unable to inflate the image data")?;
+
+        if bytes.len() > expected_length {
+            bail!(
+                "Invalid image data, it's longer than the {} bytes expected for a {}x{} image",
+                expected_length,
+                ihdr.width(),
+                ihdr.height()
+            );
+        }
+
+        Self::from_filtered_bytes(ihdr, &bytes)
+    }
+
+    /// Splits decompressed image data into scanlines, each starting with its filter type byte
+    pub fn from_filtered_bytes(ihdr: Ihdr, bytes: &[u8]) -> Result<Self> {
+        let expected_length = filtered_length(&ihdr)?;
+
+        ensure!(
+            bytes.len() == expected_length,
+            "Invalid image data length {}, expected {} bytes for a {}x{} image",
+            bytes.len(),
+            expected_length,
+            ihdr.width(),
+            ihdr.height()
+        );
+
+        let mut scanlines = Vec::new();
+        let mut bytes = bytes;
+
+        for (width, height) in pass_sizes(&ihdr) {
+            let row_length = ihdr.row_length(width) + 1;
+            let (pass_bytes, rest) = bytes.split_at(height as usize * row_length);
+            bytes = rest;
+
+            for row in pass_bytes.chunks_exact(row_length) {
+                scanlines.push(Scanline {
+                    filter_type: FilterType::try_from(row[0])?,
+                    data: row[1..].to_vec(),
+                });
+            }
+        }
+
+        Ok(Self { ihdr, scanlines })
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    pub fn scanlines(&self) -> &[Scanline] {
+        &self.scanlines
+    }
+
+    /// Returns the scanlines of each pass. Images that aren't interlaced have a single pass,
+    /// and passes of interlaced images can be empty when the image is small.
+    pub fn passes(&self) -> Vec<&[Scanline]> {
+        let mut scanlines = &self.scanlines[..];
+
+        pass_sizes(&self.ihdr)
+            .into_iter()
+            .map(|(_, height)| {
+                let (pass, rest) = scanlines.split_at(height as usize);
+                scanlines = rest;
+                pass
+            })
+            .collect()
+    }
+
+    /// Returns the scanlines with their filter type byte, as stored before compression
+    pub fn filtered_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        for scanline in self.scanlines.iter() {
+            bytes.push(scanline.filter_type.into());
+            bytes.extend_from_slice(&scanline.data);
+        }
+        bytes
+    }
+
+    /// Compresses the scanlines into a zlib stream, ready to be stored in IDAT chunks. The
+    /// compression level goes from 0 (no compression) to 9 (best compression).
+    pub fn encode(&self, compression_level: u32) -> Result<Vec<u8>> {
+        ensure!(
+            compression_level <= 9,
+            "Invalid compression level {}, it should be between 0 and 9",
+            compression_level
+        );
+
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::new(compression_level));
+        encoder
+            .write_all(&self.filtered_bytes())
+            .context("Unable to compress the image data")?;
+
+        encoder
+            .finish()
+            .context("Unable to compress the image data")
+    }
+
+    /// Reverses the filter of every scanline, returning the raw pixel rows. Interlaced images
+    /// are merged back into a full raster.
+    pub fn unfilter(&self) -> RawImage {
+        let bytes_per_pixel = self.ihdr.filter_bytes_per_pixel();
+
+        let passes: Vec<Vec<Vec<u8>>> = self
+            .passes()
+            .into_iter()
+            .map(|scanlines| {
+                let mut rows: Vec<Vec<u8>> = Vec::with_capacity(scanlines.len());
+
+                // The previous scanline starts over at the start of each pass
+                for scanline in scanlines {
+                    let mut row = scanline.data.clone();
+                    filter::unfilter_scanline(
+                        scanline.filter_type,
+                        &mut row,
+                        rows.last().map(|previous| &previous[..]),
+                        bytes_per_pixel,
+                    );
+                    rows.push(row);
+                }
+
+                rows
+            })
+            .collect();
+
+        let rows = if self.ihdr.is_interlaced() {
+            adam7::deinterlace(&self.ihdr, &passes)
+        } else {
+            passes.into_iter().flatten().collect()
+        };
+
+        RawImage {
+            ihdr: self.ihdr,
+            rows,
+        }
+    }
+}
+
+/// How the image data is written back to IDAT chunks
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct EncodeOptions {
+    /// zlib compression level, from 0 (no compression) to 9 (best compression)
+    pub compression_level: u32,
+
+    pub filter_strategy: FilterStrategy,
+
+    /// Maximum size of the data of each IDAT chunk, or `None` to store all the image data in a
+    /// single chunk
+    pub idat_size: Option<usize>,
+}
+
+impl Default for EncodeOptions {
+    fn default() -> Self {
+        Self {
+            compression_level: 6,
+            filter_strategy: FilterStrategy::MinimumSum,
+            idat_size: None,
+        }
+    }
+}
+
+/// Unfiltered image data, with the pixels of each row packed according to the bit depth
+#[derive(Debug, Clone, PartialEq)]
+pub struct RawImage {
+    ihdr: Ihdr,
+    rows: Vec<Vec<u8>>,
+}
+
+impl RawImage {
+    /// Creates an image from its unfiltered rows, which should hold the full raster even if
+    /// the image is interlaced
+    pub fn new(ihdr: Ihdr, rows: Vec<Vec<u8>>) -> Result<Self> {
+        let row_length = ihdr.row_length(ihdr.width());
+
+        ensure!(
+            rows.len() == ihdr.height() as usize,
+            "Invalid image data, expected {} rows, found {}",
+            ihdr.height(),
+            rows.len()
+        );
+        ensure!(
+            rows.iter().all(|row| row.len() == row_length),
+            "Invalid image data, all rows should have {} bytes",
+            row_length
+        );
+
+        Ok(Self { ihdr, rows })
+    }
+
+    pub fn ihdr(&self) -> &Ihdr {
+        &self.ihdr
+    }
+
+    /// Sets whether the image is interlaced with Adam7 when filtered
+    pub fn set_interlaced(&mut self, interlaced: bool) {
+        self.ihdr.set_interlaced(interlaced);
+    }
+
+    pub fn rows(&self) -> &[Vec<u8>] {
+        &self.rows
+    }
+
+    /// Returns a sample of the pixel at (`x`, `y`), unpacked from the row bytes. Samples of 16
+    /// bit images are big endian, and samples of images with less than 8 bits are packed from
+    /// the most significant bit.
+    pub fn sample(&self, x: u32, y: u32, channel: u8) -> Option<u16> {
+        let channels = self.ihdr.color_type().channels();
+        if x >= self.ihdr.width() || channel >= channels {
+            return None;
+        }
+
+        let row = self.rows.get(y as usize)?;
+        let bit_depth = self.ihdr.bit_depth() as usize;
+        let index = x as usize * channels as usize + channel as usize;
+
+        let sample = match bit_depth {
+            16 => u16::from_be_bytes([row[index * 2], row[index * 2 + 1]]),
+            8 => row[index] as u16,
+            _ => {
+                let bit_offset = index * bit_depth;
+                let shift = 8 - bit_depth - bit_offset % 8;
+                let mask = (1u8 << bit_depth) - 1;
+                ((row[bit_offset / 8] >> shift) & mask) as u16
+            }
+        };
+
+        Some(sample)
+    }
+
+    /// Sets a sample of the pixel at (`x`, `y`), packing it like [RawImage::sample]
+    pub fn set_sample(&mut self, x: u32, y: u32, channel: u8, value: u16) -> Result<()> {
+        let channels = self.ihdr.color_type().channels();
+        let bit_depth = self.ihdr.bit_depth() as usize;
+
+        ensure!(
+            x < self.ihdr.width() && y < self.ihdr.height() && channel < channels,
+            "Invalid sample ({}, {}) of channel {} for a {}x{} image",
+            x,
+            y,
+            channel,
+            self.ihdr.width(),
+            self.ihdr.height()
+        );
+        ensure!(
+            (value as u32) < 1 << bit_depth,
+            "Invalid sample value {} for a {}-bit image",
+            value,
+            bit_depth
+        );
+
+        let row = &mut self.rows[y as usize];
+        let index = x as usize * channels as usize + channel as usize;
+
+        match bit_depth {
+            16 => row[index * 2..index * 2 + 2].copy_from_slice(&value.to_be_bytes()),
+            8 => row[index] = value as u8,
+            _ => {
+                let bit_offset = index * bit_depth;
+                let shift = 8 - bit_depth - bit_offset % 8;
+                let mask = (1u8 << bit_depth) - 1;
+                let byte = &mut row[bit_offset / 8];
+                *byte = (*byte & !(mask << shift)) | ((value as u8) << shift);
+            }
+        }
+
+        Ok(())
+    }
+
+    /// Filters the rows into scanlines, split into the seven Adam7 passes if the image is
+    /// interlaced
+    pub fn filter(&self, strategy: FilterStrategy) -> ImageData {
+        let bytes_per_pixel = self.ihdr.filter_bytes_per_pixel();
+        let passes = if self.ihdr.is_interlaced() {
+            adam7::interlace(&self.ihdr, &self.rows)
+        } else {
+            vec![self.rows.clone()]
+        };
+
+        let mut scanlines = Vec::with_capacity(self.rows.len());
+        for rows in passes.iter() {
+            let mut previous: Option<&[u8]> = None;
+            for row in rows.iter() {
+                let (filter_type, data) = strategy.apply(row, previous, bytes_per_pixel);
+                scanlines.push(Scanline { filter_type, data });
+                previous = Some(row);
+            }
+        }
+
+        ImageData {
+            ihdr: self.ihdr,
+            scanlines,
+        }
+    }
+
+    /// Returns all samples of the pixel at (`x`, `y`)
+    pub fn pixel(&self, x: u32, y: u32) -> Option<Vec<u16>> {
+        (0..self.ihdr.color_type().channels())
+            .map(|channel| self.sample(x, y, channel))
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::ihdr::ColorType;
+
+    fn compress(bytes: &[u8]) -> Vec<u8> {
+        let mut encoder = ZlibEncoder::new(Vec::new(), Compression::default());
+        encoder.write_all(bytes).unwrap();
+        encoder.finish().unwrap()
+    }
+
+    #[test]
+    fn test_decode_scanlines() {
+        let ihdr = Ihdr::new(2, 2, 8, ColorType::Rgb, false).unwrap();
+        let bytes = [0, 1, 2, 3, 4, 5, 6, 2, 1, 1, 1, 1, 1, 1];
+        let image_data = ImageData::decode(ihdr, &compress(&bytes)).unwrap();
+
+        assert_eq!(image_data.scanlines().len(), 2);
+        assert_eq!(image_data.scanlines()[0].filter_type(), FilterType::None);
+        assert_eq!(image_data.scanlines()[1].filter_type(), FilterType::Up);
+        assert_eq!(image_data.scanlines()[1].data(), &[1, 1, 1, 1, 1, 1]);
+
+        let raw = image_data.unfilter();
+        assert_eq!(raw.rows()[1], vec![2, 3, 4, 5, 6, 7]);
+        assert_eq!(raw.pixel(1, 1).unwrap(), vec![5, 6, 7]);
+    }
+
+    #[test]
+    fn test_invalid_image_data() {
+        let ihdr = Ihdr::new(2, 2, 8, ColorType::Rgb, false).unwrap();
+
+        // Missing a byte
+        let bytes = [0, 1, 2, 3, 4, 5, 6, 2, 1, 1, 1, 1, 1];
+        assert!(ImageData::decode(ihdr, &compress(&bytes)).is_err());
+
+        // Invalid filter type
+        let bytes = [0, 1, 2, 3, 4, 5, 6, 5, 1, 1, 1, 1, 1, 1];
+        assert!(ImageData::decode(ihdr, &compress(&bytes)).is_err());
+
+        // Not a zlib stream
+        assert!(ImageData::decode(ihdr, &bytes).is_err());
+    }
+
+    #[test]
+    fn test_image_data_too_long() {
+        let ihdr = Ihdr::new(2, 2, 8, ColorType::Rgb, false).unwrap();
+
+        // A stream inflating to far more data than the image needs
+        let bytes = vec![0; 1 << 20];
+        let error = ImageData::decode(ihdr, &compress(&bytes)).unwrap_err();
+        assert!(error.to_string().contains("longer than the 14 bytes"));
+    }
+
+    #[test]
+    fn test_huge_image_size() {
+        let ihdr = Ihdr::new(Ihdr::MAX_SIZE, Ihdr::MAX_SIZE, 16, ColorType::Rgba, false).unwrap();
+        // About 2^65 bytes of image data
+        assert!(filtered_length(&ihdr).is_err());
+        assert!(ImageData::decode(ihdr, &compress(&[0; 16])).is_err());
+    }
+
+    #[test]
+    fn test_unfilter_all_filter_types() {
+        let ihdr = Ihdr::new(2, 5, 8, ColorType::GrayscaleAlpha, false).unwrap();
+        let bytes = [
+            0, 10, 20, 30, 40, // None
+            1, 10, 20, 30, 40, // Sub
+            2, 1, 2, 3, 4, // Up
+            3, 0, 0, 0, 0, // Average
+            4, 0, 0, 0, 0, // Paeth
+        ];
+        let raw = ImageData::decode(ihdr, &compress(&bytes))
+            .unwrap()
+            .unfilter();
+
+        assert_eq!(raw.rows()[0], vec![10, 20, 30, 40]);
+        assert_eq!(raw.rows()[1], vec![10, 20, 40, 60]);
+        assert_eq!(raw.rows()[2], vec![11, 22, 43, 64]);
+        assert_eq!(raw.rows()[3], vec![5, 11, 24, 37]);
+        assert_eq!(raw.rows()[4], vec![5, 11, 24, 37]);
+    }
+
+    #[test]
+    fn test_low_bit_depth_samples() {
+        let ihdr = Ihdr::new(5, 1, 2, ColorType::Grayscale, false).unwrap();
+        let raw = ImageData::from_filtered_bytes(ihdr, &[0, 0b00_01_10_11, 0b01_00_00_00])
+            .unwrap()
+            .unfilter();
+
+        let samples: Vec<u16> = (0..5).map(|x| raw.sample(x, 0, 0).unwrap()).collect();
+        assert_eq!(samples, vec![0, 1, 2, 3, 1]);
+        assert!(raw.sample(5, 0, 0).is_none());
+        assert!(raw.sample(0, 0, 1).is_none());
+    }
+
+    #[test]
+    fn test_sixteen_bit_samples() {
+        let ihdr = Ihdr::new(1, 1, 16, ColorType::GrayscaleAlpha, false).unwrap();
+        let raw = ImageData::from_filtered_bytes(ihdr, &[0, 1, 2, 255, 255])
+            .unwrap()
+            .unfilter();
+
+        assert_eq!(raw.pixel(0, 0).unwrap(), vec![258, 65535]);
+    }
+
+    fn testing_raw_image(interlaced: bool) -> RawImage {
+        let ihdr = Ihdr::new(10, 7, 8, ColorType::Rgb, interlaced).unwrap();
+        let rows = (0..7u8)
+            .map(|y| (0..30u8).map(|x| y.wrapping_mul(31) ^ x).collect())
+            .collect();
+
+        RawImage::new(ihdr, rows).unwrap()
+    }
+
+    #[test]
+    fn test_interlaced_passes() {
+        let image_data = testing_raw_image(true).filter(FilterStrategy::MinimumSum);
+        let heights: Vec<usize> = image_data.passes().iter().map(|pass| pass.len()).collect();
+
+        assert_eq!(heights, vec![1, 1, 1, 2, 2, 4, 3]);
+        assert_eq!(image_data.scanlines()[0].data().len(), 6);
+    }
+
+    #[test]
+    fn test_interlaced_round_trip() {
+        let raw = testing_raw_image(true);
+        let compressed = raw.filter(FilterStrategy::MinimumSum).encode(9).unwrap();
+        let decoded = ImageData::decode(*raw.ihdr(), &compressed).unwrap();
+
+        assert!(decoded.ihdr().is_interlaced());
+        assert_eq!(decoded.unfilter(), raw);
+    }
+
+    #[test]
+    fn test_convert_interlacing() {
+        let mut raw = testing_raw_image(true);
+        let interlaced = raw.filter(FilterStrategy::MinimumSum).filtered_bytes();
+
+        raw.set_interlaced(false);
+        let image_data = raw.filter(FilterStrategy::MinimumSum);
+        assert_eq!(image_data.passes().len(), 1);
+        assert_ne!(image_data.filtered_bytes(), interlaced);
+        assert_eq!(image_data.unfilter().rows(), testing_raw_image(true).rows());
+
+        raw.set_interlaced(true);
+        assert_eq!(
+            raw.filter(FilterStrategy::MinimumSum).filtered_bytes(),
+            interlaced
+        );
+    }
+
+    #[test]
+    fn test_invalid_raw_image() {
+        let ihdr = Ihdr::new(2, 2, 8, ColorType::Rgb, false).unwrap();
+
+        assert!(RawImage::new(ihdr, vec![vec![0; 6]]).is_err());
+        assert!(RawImage::new(ihdr, vec![vec![0; 6], vec![0; 5]]).is_err());
+    }
+
+    #[test]
+    fn test_filter_strategies_round_trip() {
+        let raw = testing_raw_image(false);
+
+        for strategy in [
+            FilterStrategy::Fixed(FilterType::None),
+            FilterStrategy::Fixed(FilterType::Paeth),
+            FilterStrategy::MinimumSum,
+            FilterStrategy::BruteForce,
+        ] {
+            let image_data = raw.filter(strategy);
+            let decoded = ImageData::decode(*raw.ihdr(), &image_data.encode(1).unwrap()).unwrap();
+            assert_eq!(decoded.unfilter(), raw, "{}", strategy);
+        }
+
+        assert!(raw.filter(FilterStrategy::MinimumSum).encode(10).is_err());
+    }
+
+    #[test]
+    fn test_set_sample() {
+        let ihdr = Ihdr::new(5, 1, 2, ColorType::Grayscale, false).unwrap();
+        let mut raw = RawImage::new(ihdr, vec![vec![0, 0]]).unwrap();
+
+        raw.set_sample(1, 0, 0, 3).unwrap();
+        raw.set_sample(4, 0, 0, 2).unwrap();
+        assert_eq!(raw.rows()[0], vec![0b00_11_00_00, 0b10_00_00_00]);
+        assert_eq!(raw.sample(1, 0, 0), Some(3));
+
+        assert!(raw.set_sample(0, 0, 0, 4).is_err());
+        assert!(raw.set_sample(5, 0, 0, 1).is_err());
+
+        let ihdr = Ihdr::new(1, 1, 16, ColorType::Rgb, false).unwrap();
+        let mut raw = RawImage::new(ihdr, vec![vec![0; 6]]).unwrap();
+        raw.set_sample(0, 0, 2, 258).unwrap();
+        assert_eq!(raw.pixel(0, 0).unwrap(), vec![0, 0, 258]);
+    }
+}
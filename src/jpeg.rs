@@ -0,0 +1,553 @@
+//! Reads and writes the segments of JPEG files, to hide messages in APPn and COM segments.
+//!
+//! JPEG files are a series of markers (0xFF followed by a marker byte), most followed by a 2-byte
+//! length and data. APPn segments usually start with an identifier ending in a NUL byte, like
+//! `JFIF\0` or `Exif\0`. Segments whose identifier is a 4-letter chunk type are exposed as
+//! [Chunk]s, so messages are stored in an APP15 or COM segment tagged with their chunk type and
+//! selected like PNG chunks.
+
+use std::{borrow::Cow, fmt::Display, fs, path::Path, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    container::{Container, PayloadOptions},
+    selector::Selector,
+};
+
+/// Start of image
+pub const SOI: u8 = 0xd8;
+/// End of image
+pub const EOI: u8 = 0xd9;
+/// Start of scan, followed by the entropy-coded image data
+pub const SOS: u8 = 0xda;
+/// Comment
+pub const COM: u8 = 0xfe;
+/// APP15, the application segment messages are stored in
+pub const APP15: u8 = 0xef;
+
+/// Largest data a segment can hold, since its length includes the 2 bytes of the length itself
+pub const MAX_SEGMENT_DATA: usize = u16::MAX as usize - 2;
+
+/// Length of the identifier of tagged segments: a chunk type followed by a NUL byte
+const TAG_LENGTH: usize = 5;
+
+/// Checks if a marker stands alone, without a length or data
+fn is_standalone(marker: u8) -> bool {
+    // TEM, RST0 to RST7, SOI and EOI
+    matches!(marker, 0x01 | 0xd0..=0xd9)
+}
+
+/// Checks if a marker is APP0 to APP15
+fn is_app(marker: u8) -> bool {
+    (0xe0..=0xef).contains(&marker)
+}
+
+/// Where a message is stored in a JPEG image
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum PayloadSegment {
+    /// An APP15 segment, which is reserved for applications
+    App,
+
+    /// A comment segment
+    Comment,
+}
+
+impl PayloadSegment {
+    fn marker(&self) -> u8 {
+        match self {
+            PayloadSegment::App => APP15,
+            PayloadSegment::Comment => COM,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum Segment {
+    /// A marker without data: SOI, EOI, TEM or RSTn
+    Standalone(u8),
+
+    /// A marker followed by its data, like APPn, COM, DQT or SOS
+    Marker { marker: u8, data: Vec<u8> },
+
+    /// Entropy-coded image data following a SOS segment. It keeps its stuffed bytes and RSTn
+    /// markers as they are.
+    ScanData(Vec<u8>),
+}
+
+impl Segment {
+    pub fn marker(&self) -> Option<u8> {
+        match self {
+            Segment::Standalone(marker) | Segment::Marker { marker, .. } => Some(*marker),
+            Segment::ScanData(_) => None,
+        }
+    }
+
+    /// Returns the chunk type and data of segments tagged with a chunk type, like `Exif\0`
+    /// segments or the segments holding messages
+    pub fn as_chunk(&self) -> Option<Chunk> {
+        let Segment::Marker { marker, data } = self else {
+            return None;
+        };
+        if !(is_app(*marker) || *marker == COM) || data.len() < TAG_LENGTH || data[4] != 0 {
+            return None;
+        }
+
+        let tag = std::str::from_utf8(&data[..4]).ok()?;
+        let chunk_type = ChunkType::from_str(tag).ok()?;
+
+        Some(Chunk::new(chunk_type, data[TAG_LENGTH..].to_vec()))
+    }
+
+    /// Checks if the segment is a tagged APPn segment other than APP15, like the JFIF or Exif
+    /// segments, which readers rely on so they should never be removed or replaced
+    pub fn is_required(&self) -> bool {
+        self.marker()
+            .is_some_and(|marker| is_app(marker) && marker != APP15)
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        match self {
+            Segment::Standalone(marker) => vec![0xff, *marker],
+            Segment::Marker { marker, data } => {
+                // The length is checked when segments are created, so it fits in 2 bytes
+                let length = (data.len() + 2) as u16;
+                let mut bytes = vec![0xff, *marker];
+                bytes.extend(length.to_be_bytes());
+                bytes.extend(data);
+                bytes
+            }
+            Segment::ScanData(data) => data.clone(),
+        }
+    }
+}
+
+impl Display for Segment {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let Some(marker) = self.marker() else {
+            return write!(f, "scan data");
+        };
+
+        match marker {
+            SOI => write!(f, "SOI"),
+            EOI => write!(f, "EOI"),
+            SOS => write!(f, "SOS"),
+            COM => write!(f, "COM"),
+            0xdb => write!(f, "DQT"),
+            0xc4 => write!(f, "DHT"),
+            0xdd => write!(f, "DRI"),
+            0xd0..=0xd7 => write!(f, "RST{}", marker - 0xd0),
+            0xe0..=0xef => write!(f, "APP{}", marker - 0xe0),
+            0xc0..=0xcf => write!(f, "SOF{}", marker - 0xc0),
+            _ => write!(f, "0x{:02X}", marker),
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Jpeg {
+    segments: Vec<Segment>,
+
+    /// Bytes after the EOI marker, kept so they're written back unchanged
+    trailing: Vec<u8>,
+}
+
+impl Jpeg {
+    pub const SIGNATURE: [u8; 3] = [0xff, SOI, 0xff];
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path).context("Unable to read file")?;
+        Self::try_from(&bytes[..])
+    }
+
+    pub fn segments(&self) -> &[Segment] {
+        &self.segments
+    }
+
+    /// Returns the segments tagged with a chunk type as chunks, along with their index in the
+    /// segment list
+    pub fn chunks(&self) -> Vec<(usize, Chunk)> {
+        self.segments
+            .iter()
+            .enumerate()
+            .filter_map(|(index, segment)| segment.as_chunk().map(|chunk| (index, chunk)))
+            .collect()
+    }
+
+    /// Returns the tagged segments matching the selector as chunks, with their segment index
+    pub fn select(&self, selector: &Selector) -> Vec<(usize, Chunk)> {
+        self.chunks()
+            .into_iter()
+            .filter(|(index, chunk)| selector.matches(*index, chunk))
+            .collect()
+    }
+
+    /// Adds a segment tagged with the chunk type of `chunk` holding its data, after the APPn and
+    /// COM segments at the start of the image, so viewers expecting JFIF or Exif segments right
+    /// after SOI still find them. Returns the index of the new segment.
+    pub fn insert_chunk(&mut self, chunk: &Chunk, segment: PayloadSegment) -> Result<usize> {
+        let data = Self::tagged_data(chunk)?;
+
+        let index = self
+            .segments
+            .iter()
+            .skip(1)
+            .position(|s| !s.marker().is_some_and(|m| is_app(m) || m == COM))
+            .map_or(self.segments.len(), |position| position + 1);
+
+        self.segments.insert(
+            index,
+            Segment::Marker {
+                marker: segment.marker(),
+                data,
+            },
+        );
+
+        Ok(index)
+    }
+
+    /// Replaces the data of the first segment tagged with the chunk type of `chunk`, keeping its
+    /// position. Returns false if there's no such segment, and fails if it's a required segment.
+    pub fn replace_chunk(&mut self, chunk: &Chunk) -> Result<bool> {
+        let data = Self::tagged_data(chunk)?;
+
+        let Some(index) = self
+            .chunks()
+            .into_iter()
+            .find_map(|(index, c)| (c.chunk_type() == chunk.chunk_type()).then_some(index))
+        else {
+            return Ok(false);
+        };
+
+        ensure!(
+            !self.segments[index].is_required(),
+            "Unable to replace the {} segment ({}), which JPEG readers rely on",
+            chunk.chunk_type(),
+            self.segments[index]
+        );
+
+        if let Segment::Marker { data: old, .. } = &mut self.segments[index] {
+            *old = data;
+        }
+
+        Ok(true)
+    }
+
+    /// Removes the tagged segments matching the selector, returning them as chunks. Fails
+    /// without removing any segment if a required segment is selected.
+    pub fn remove_selected(&mut self, selector: &Selector) -> Result<Vec<Chunk>> {
+        let selected = self.select(selector);
+
+        if let Some((index, chunk)) = selected
+            .iter()
+            .find(|(index, _)| self.segments[*index].is_required())
+        {
+            bail!(
+                "The selector \"{}\" matches the {} segment ({}), which JPEG readers rely on",
+                selector,
+                chunk.chunk_type(),
+                self.segments[*index]
+            );
+        }
+
+        // Removing from the end keeps the indices of the other selected segments valid
+        for (index, _) in selected.iter().rev() {
+            self.segments.remove(*index);
+        }
+
+        Ok(selected.into_iter().map(|(_, chunk)| chunk).collect())
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes: Vec<u8> = self.segments.iter().flat_map(|s| s.as_bytes()).collect();
+        bytes.extend(&self.trailing);
+        bytes
+    }
+
+    /// Segment data tagged with the chunk type of `chunk`
+    fn tagged_data(chunk: &Chunk) -> Result<Vec<u8>> {
+        ensure!(
+            chunk.data().len() + TAG_LENGTH <= MAX_SEGMENT_DATA,
+            "The message has {} bytes, but JPEG segments can only hold {} bytes",
+            chunk.data().len(),
+            MAX_SEGMENT_DATA - TAG_LENGTH
+        );
+
+        let mut data = chunk.chunk_type().bytes().to_vec();
+        data.push(0);
+        data.extend(chunk.data());
+
+        Ok(data)
+    }
+
+    /// Reads entropy-coded data until the next marker, which is a 0xFF byte not followed by a
+    /// stuffed 0x00 byte or a RSTn marker. Returns the length of the data.
+    fn scan_data_length(bytes: &[u8]) -> usize {
+        let mut offset = 0;
+        while offset + 1 < bytes.len() {
+            if bytes[offset] == 0xff && !matches!(bytes[offset + 1], 0x00 | 0xd0..=0xd7) {
+                return offset;
+            }
+            offset += 1;
+        }
+
+        bytes.len()
+    }
+}
+
+impl Container for Jpeg {
+    fn open(path: &Path) -> Result<Self> {
+        Self::from_file(path)
+    }
+
+    fn segments(&self) -> Vec<(usize, Cow<'_, Chunk>)> {
+        self.chunks()
+            .into_iter()
+            .map(|(index, chunk)| (index, Cow::Owned(chunk)))
+            .collect()
+    }
+
+    /// Adds an APP15 or COM segment after the leading APPn and COM segments, the position is
+    /// ignored
+    fn insert_payload(&mut self, chunk: Chunk, options: &PayloadOptions) -> Result<()> {
+        let segment = if options.comment {
+            PayloadSegment::Comment
+        } else {
+            PayloadSegment::App
+        };
+        self.insert_chunk(&chunk, segment)?;
+        Ok(())
+    }
+
+    fn replace_payload(&mut self, chunk: &Chunk) -> Result<bool> {
+        self.replace_chunk(chunk)
+    }
+
+    fn remove_payloads(&mut self, selector: &Selector) -> Result<Vec<Chunk>> {
+        self.remove_selected(selector)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+impl TryFrom<&[u8]> for Jpeg {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        ensure!(
+            bytes.starts_with(&[0xff, SOI]),
+            "Invalid JPEG file, the file does not start with a SOI marker"
+        );
+
+        let mut segments = Vec::new();
+        let mut offset = 0;
+
+        loop {
+            ensure!(
+                offset < bytes.len() && bytes[offset] == 0xff,
+                "Invalid JPEG file, expected a marker at offset {}",
+                offset
+            );
+
+            // Markers may be preceded by any number of 0xFF fill bytes
+            while offset < bytes.len() && bytes[offset] == 0xff {
+                offset += 1;
+            }
+            let marker = *bytes
+                .get(offset)
+                .context("Invalid JPEG file, the file ends without an EOI marker")?;
+            offset += 1;
+
+            if is_standalone(marker) {
+                segments.push(Segment::Standalone(marker));
+                if marker == EOI {
+                    break;
+                }
+                continue;
+            }
+
+            ensure!(
+                offset + 2 <= bytes.len(),
+                "Invalid JPEG file, the length of the segment at offset {} is missing",
+                offset - 2
+            );
+            let length = u16::from_be_bytes([bytes[offset], bytes[offset + 1]]) as usize;
+            ensure!(
+                length >= 2 && offset + length <= bytes.len(),
+                "Invalid JPEG file, the segment at offset {} has an invalid length",
+                offset - 2
+            );
+
+            let data = bytes[offset + 2..offset + length].to_vec();
+            segments.push(Segment::Marker { marker, data });
+            offset += length;
+
+            if marker == SOS {
+                let length = Self::scan_data_length(&bytes[offset..]);
+                if length > 0 {
+                    segments.push(Segment::ScanData(bytes[offset..offset + length].to_vec()));
+                }
+                offset += length;
+            }
+        }
+
+        if segments.first() != Some(&Segment::Standalone(SOI)) {
+            bail!("Invalid JPEG file, the first segment should be SOI");
+        }
+
+        Ok(Self {
+            segments,
+            trailing: bytes[offset..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_jpeg_bytes() -> Vec<u8> {
+        #[rustfmt::skip]
+        let bytes = vec![
+            0xff, SOI,
+            0xff, 0xe0, 0, 9, b'J', b'F', b'I', b'F', 0, 1, 2, // APP0 JFIF
+            0xff, 0xdb, 0, 4, 1, 2,                           // DQT
+            0xff, 0xc0, 0, 3, 8,                              // SOF0
+            0xff, SOS, 0, 3, 1,                               // SOS
+            0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56,         // Scan data with RST0
+            0xff, EOI,
+            0xaa, 0xbb,                                       // Trailing bytes
+        ];
+        bytes
+    }
+
+    fn testing_jpeg() -> Jpeg {
+        Jpeg::try_from(&testing_jpeg_bytes()[..]).unwrap()
+    }
+
+    fn message_chunk(chunk_type: &str, message: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_parse_segments() {
+        let jpeg = testing_jpeg();
+        let names: Vec<String> = jpeg.segments().iter().map(|s| s.to_string()).collect();
+
+        assert_eq!(
+            names,
+            vec!["SOI", "APP0", "DQT", "SOF0", "SOS", "scan data", "EOI"]
+        );
+        assert_eq!(
+            jpeg.segments()[5],
+            Segment::ScanData(vec![0x12, 0xff, 0x00, 0x34, 0xff, 0xd0, 0x56])
+        );
+        assert_eq!(jpeg.as_bytes(), testing_jpeg_bytes());
+    }
+
+    #[test]
+    fn test_tagged_chunks() {
+        let jpeg = testing_jpeg();
+        let chunks = jpeg.chunks();
+
+        assert_eq!(chunks.len(), 1);
+        assert_eq!(chunks[0].0, 1);
+        assert_eq!(chunks[0].1.chunk_type().to_string(), "JFIF");
+        assert_eq!(chunks[0].1.data(), &vec![1, 2]);
+    }
+
+    #[test]
+    fn test_insert_replace_and_remove() {
+        let mut jpeg = testing_jpeg();
+
+        let index = jpeg
+            .insert_chunk(&message_chunk("ruSt", "in APP15"), PayloadSegment::App)
+            .unwrap();
+        assert_eq!(index, 2);
+        let index = jpeg
+            .insert_chunk(&message_chunk("ruSt", "in COM"), PayloadSegment::Comment)
+            .unwrap();
+        assert_eq!(index, 3);
+        assert_eq!(jpeg.segments()[3].to_string(), "COM");
+
+        let jpeg = Jpeg::try_from(&jpeg.as_bytes()[..]).unwrap();
+        let selector = Selector::from_str("ruSt").unwrap();
+        let messages: Vec<String> = jpeg
+            .select(&selector)
+            .iter()
+            .map(|(_, chunk)| chunk.data_as_string().unwrap())
+            .collect();
+        assert_eq!(messages, vec!["in APP15", "in COM"]);
+
+        let mut jpeg = jpeg;
+        assert!(jpeg
+            .replace_chunk(&message_chunk("ruSt", "replaced"))
+            .unwrap());
+        assert!(!jpeg
+            .replace_chunk(&message_chunk("abCd", "missing"))
+            .unwrap());
+        assert_eq!(
+            jpeg.select(&selector)[0].1.data_as_string().unwrap(),
+            "replaced"
+        );
+
+        assert_eq!(jpeg.remove_selected(&selector).unwrap().len(), 2);
+        assert_eq!(jpeg.as_bytes(), testing_jpeg_bytes());
+    }
+
+    #[test]
+    fn test_required_segments() {
+        let mut jpeg = testing_jpeg();
+        jpeg.insert_chunk(&message_chunk("ruSt", "message"), PayloadSegment::App)
+            .unwrap();
+
+        // The JFIF segment is in APP0
+        assert!(jpeg.segments()[1].is_required());
+        assert!(!jpeg.segments()[2].is_required());
+
+        for selector in ["*", "public", "JFIF"] {
+            let selector = Selector::from_str(selector).unwrap();
+            assert!(jpeg.remove_selected(&selector).is_err());
+        }
+        assert!(jpeg.replace_chunk(&message_chunk("JFIF", "data")).is_err());
+        assert_eq!(jpeg.chunks().len(), 2);
+
+        let selector = Selector::from_str("ruSt").unwrap();
+        assert_eq!(jpeg.remove_selected(&selector).unwrap().len(), 1);
+        assert_eq!(jpeg.as_bytes(), testing_jpeg_bytes());
+    }
+
+    #[test]
+    fn test_message_too_long() {
+        let mut jpeg = testing_jpeg();
+        let chunk = Chunk::new(
+            ChunkType::from_str("ruSt").unwrap(),
+            vec![0; MAX_SEGMENT_DATA],
+        );
+
+        assert!(jpeg.insert_chunk(&chunk, PayloadSegment::App).is_err());
+    }
+
+    #[test]
+    fn test_invalid_jpeg() {
+        let bytes = testing_jpeg_bytes();
+
+        // Missing SOI
+        assert!(Jpeg::try_from(&bytes[2..]).is_err());
+
+        // Truncated before EOI
+        assert!(Jpeg::try_from(&bytes[..20]).is_err());
+
+        // Segment length past the end of the file
+        let mut bad_length = bytes[..8].to_vec();
+        bad_length[5] = 200;
+        assert!(Jpeg::try_from(&bad_length[..]).is_err());
+    }
+}
@@ -0,0 +1,25 @@
+pub mod adam7;
+pub mod apng;
+pub mod checksum;
+pub mod chunk;
+pub mod chunk_ref;
+pub mod chunk_type;
+pub mod container;
+pub mod filter;
+pub mod format;
+pub mod frame_payload;
+pub mod gif;
+pub mod ihdr;
+pub mod image_data;
+pub mod jpeg;
+pub mod manifest;
+pub mod mapped_png;
+pub mod palette;
+pub mod palette_order;
+pub mod png;
+pub mod png_ref;
+pub mod riff;
+pub mod selector;
+pub mod signature;
+
+pub use anyhow::{Error, Result};
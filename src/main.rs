@@ -1,59 +1,218 @@
-use anyhow::{Context, Result};
-use std::{collections::HashSet, fs, io::Write, path::Path, str::FromStr};
+use anyhow::{ensure, Context, Result};
+use std::{
+    collections::HashSet,
+    env, fs,
+    io::{self, IsTerminal, Write},
+    path::Path,
+    str::FromStr,
+};
 
 use clap::Parser;
-use cli::{Cli, Commands, DecodeArgs, EncodeArgs, PrintArgs, RemoveArgs};
+use cli::{
+    ApngArgs, ApngBuildArgs, ApngCommands, ApngSplitArgs, BuildArgs, ChunkArgs, ChunkCommands,
+    ChunkExtractArgs, ChunkInsertArgs, Cli, Commands, DecodeArgs, DumpArgs, EncodeArgs,
+    HexdumpArgs, InspectArgs, OptimizeArgs, PaletteArgs, PaletteCommands, PaletteHideArgs,
+    PaletteRevealArgs, PrintArgs, RemoveArgs, StripArgs, StripProfile, UpdateArgs,
+};
+
+use pngme::{
+    apng::{self, FrameControl},
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    container::{self, ChunkPosition, PayloadOptions},
+    filter::{FilterStrategy, FilterType},
+    format::Format,
+    frame_payload,
+    image_data::EncodeOptions,
+    manifest::{Manifest, ManifestFormat},
+    mapped_png::MappedPng,
+    palette::Transparency,
+    palette_order,
+    png::Png,
+    selector::Selector,
+};
 
-use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png};
-
-mod chunk;
-mod chunk_type;
 mod cli;
-mod png;
 
-/// Hides a message in an image by storing it in a non-critical chunk
+/// Chunk types holding textual metadata, as defined by the PNG spec
+const TEXT_CHUNK_TYPES: [&[u8; 4]; 3] = [b"tEXt", b"zTXt", b"iTXt"];
+
+/// Chunk types of APNG animations, which are only valid together
+const ANIMATION_CHUNK_TYPES: [&[u8; 4]; 3] = [b"acTL", b"fcTL", b"fdAT"];
+
+/// Writes the bytes of an image to `path`, replacing its contents if the file already exists.
+/// Returns the number of bytes written.
+fn write_image<P: AsRef<Path>>(path: P, bytes: &[u8]) -> Result<usize> {
+    let mut file = fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        // Truncate empties the file after opening it, this is necessary since we want to
+        // replace its contents
+        .truncate(true)
+        .open(path)
+        .context("Unable to open image file to write")?;
+
+    file.write_all(bytes).context("Error writing image file")?;
+
+    Ok(bytes.len())
+}
+
+fn write_png<P: AsRef<Path>>(path: P, png: &Png) -> Result<usize> {
+    write_image(path, &png.as_bytes())
+}
+
+/// Hides a message in an image by storing it in a non-critical chunk, or in the segments of other
+/// formats holding messages
 fn encode(args: EncodeArgs) -> Result<()> {
     let chunk_type = ChunkType::from_str(&args.chunk_type)?;
     chunk_type.is_valid_for_message()?;
 
     let path = Path::new(&args.file_path);
-    let mut png = Png::from_file(path).context("Unable to load image file")?;
-
     let data: Vec<u8> = args.message.bytes().collect();
-    let message_chunk = Chunk::new(chunk_type, data);
+    let format = Format::detect_file(path)?;
 
-    png.append_chunk(message_chunk);
+    if args.frames {
+        ensure!(
+            matches!(format, Format::Png(_)),
+            "Only animated PNG images can spread a message across frames"
+        );
 
-    let destination = args.output_file.unwrap_or(args.file_path);
+        let mut png = Png::from_file(path).context("Unable to load image file")?;
+        let part_count = frame_payload::hide(&mut png, &chunk_type, &data)?;
 
-    let mut file = fs::OpenOptions::new()
-        .write(true)
-        .create(true)
-        .open(&destination)
-        .context("Unable to open image file to write")?;
+        let destination = args.output_file.unwrap_or(args.file_path);
+        let bytes_written = write_png(destination, &png)?;
+
+        println!("Message successfuly encoded in {} frame(s)", part_count);
+        println!("Bytes written: {}", bytes_written);
+        return Ok(());
+    }
+
+    // Adding the chunk right before IEND in the original file doesn't require rewriting it
+    let in_place = matches!(format, Format::Png(_))
+        && args.output_file.is_none()
+        && !args.replace
+        && matches!(args.position, ChunkPosition::End);
 
-    file.write_all(&png.as_bytes())
-        .context("Error writing image file")?;
+    let chunk = Chunk::new(chunk_type, data);
+
+    if in_place {
+        if let Some(bytes_written) = Png::append_chunk_to_file(path, &chunk)? {
+            println!("Message successfuly encoded");
+            println!("Bytes written: {}", bytes_written);
+            return Ok(());
+        }
+    }
+
+    let mut image = container::open(path).context("Unable to load image file")?;
+
+    let replaced = args.replace && image.replace_payload(&chunk)?;
+    if !replaced {
+        let options = PayloadOptions {
+            position: args.position,
+            comment: args.comment,
+        };
+        image.insert_payload(chunk, &options)?;
+    }
+
+    let destination = args.output_file.unwrap_or(args.file_path);
+    let bytes_written = write_image(destination, &image.to_bytes())?;
 
     println!("Message successfuly encoded");
+    println!("Bytes written: {}", bytes_written);
 
     Ok(())
 }
 
-/// Prints hidden messages in chunks of a specific chunk type
-fn decode(args: DecodeArgs) -> Result<()> {
+/// Replaces the data of the chunks of a chunk type, keeping their position. If `index` is
+/// specified only the chunk at that index (among the chunks of that type) is replaced, otherwise
+/// all of them are.
+///
+/// Returns the number of chunks replaced.
+fn replace_chunk_data(
+    png: &mut Png,
+    chunk_type: &ChunkType,
+    data: &[u8],
+    index: Option<usize>,
+) -> usize {
+    let chunks = png
+        .chunks_mut()
+        .iter_mut()
+        .filter(|chunk| chunk.chunk_type() == chunk_type);
+
+    let chunks: Vec<&mut Chunk> = match index {
+        Some(index) => chunks.skip(index).take(1).collect(),
+        None => chunks.collect(),
+    };
+
+    let replaced_chunk_count = chunks.len();
+    for chunk in chunks {
+        chunk.set_data(data.to_vec());
+    }
+
+    replaced_chunk_count
+}
+
+/// Replaces the message in existing chunks of a specific chunk type
+fn update(args: UpdateArgs) -> Result<()> {
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    chunk_type.is_valid_for_message()?;
+
     let path = Path::new(&args.file_path);
     let mut png = Png::from_file(path).context("Unable to load image file")?;
 
+    let data: Vec<u8> = args.message.bytes().collect();
+    let index = if args.all { None } else { Some(args.index) };
+
+    let updated_chunk_count = replace_chunk_data(&mut png, &chunk_type, &data, index);
+    if updated_chunk_count == 0 {
+        match index {
+            Some(index) => println!(
+                "No chunk with chunk type \"{}\" found at index {}",
+                args.chunk_type, index
+            ),
+            None => println!("No chunk with chunk type \"{}\" found", args.chunk_type),
+        }
+        return Ok(());
+    }
+
+    let destination = args.output_file.unwrap_or(args.file_path);
+    write_png(destination, &png)?;
+
+    println!("Number of chunks updated: {}", updated_chunk_count);
+
+    Ok(())
+}
+
+/// Prints hidden messages in the chunks matching a selector
+fn decode(args: DecodeArgs) -> Result<()> {
+    let path = Path::new(&args.file_path);
+
+    if args.frames {
+        ensure!(
+            matches!(Format::detect_file(path)?, Format::Png(_)),
+            "Only animated PNG images can spread a message across frames"
+        );
+
+        let png = Png::from_file(path).context("Unable to load image file")?;
+        let payload = frame_payload::reveal(&png, &args.selector)?;
+        println!("Message:");
+        println!("{}", String::from_utf8_lossy(&payload));
+        return Ok(());
+    }
+
+    let image = container::open(path).context("Unable to load image file")?;
+    print_messages(&image.extract_payloads(&args.selector), &args.selector);
+
+    Ok(())
+}
+
+/// Prints the data of chunks holding messages, or that no chunks matched the selector
+fn print_messages(chunks: &[Chunk], selector: &Selector) {
     let mut messages: Vec<String> = Vec::new();
     let mut chunks_with_problem = 0;
 
-    loop {
-        let chunk = match png.remove_first_chunk(&args.chunk_type) {
-            Some(chunk) => chunk,
-            _ => break,
-        };
-
+    for chunk in chunks {
         if let Ok(message) = chunk.data_as_string() {
             messages.push(message);
         } else {
@@ -61,7 +220,7 @@ fn decode(args: DecodeArgs) -> Result<()> {
         }
     }
 
-    if messages.len() > 0 {
+    if !messages.is_empty() {
         println!("Messages:");
         println!("{}", messages.join("\n"));
     }
@@ -73,63 +232,488 @@ fn decode(args: DecodeArgs) -> Result<()> {
         );
     }
 
-    if messages.len() == 0 {
-        println!("No chunks with chunk type \"{}\" found", args.chunk_type);
+    if messages.is_empty() {
+        println!("No chunks matching \"{}\" found", selector);
     }
+}
+
+/// Prints the types of the chunks matching a selector. For formats other than PNG, MNG and JNG,
+/// only the segments tagged with a chunk type are listed.
+fn print(args: PrintArgs) -> Result<()> {
+    let image = container::open(&args.file_path).context("Unable to load image file")?;
+
+    let chunk_types: HashSet<String> = image
+        .extract_payloads(&args.selector)
+        .iter()
+        .map(|chunk| chunk.chunk_type().to_string())
+        .collect();
+
+    println!("{}", chunk_types.into_iter().collect::<Vec<_>>().join("\n"));
 
     Ok(())
 }
 
-/// Prints private chunk types in the image
-fn print(args: PrintArgs) -> Result<()> {
+/// Removes all chunks matching a selector. This will overwrite the file.
+///
+/// Chunks required by the format, like IHDR, IDAT and IEND for PNG, are never removed.
+fn remove(args: RemoveArgs) -> Result<()> {
+    let mut image = container::open(&args.file_path).context("Unable to load image file")?;
+
+    let removed_chunk_count = image.remove_payloads(&args.selector)?.len();
+
+    if removed_chunk_count > 0 {
+        write_image(&args.file_path, &image.to_bytes())?;
+
+        println!("Number of chunks removed: {}", removed_chunk_count);
+    } else {
+        println!("No chunk matching \"{}\" found", args.selector);
+    }
+
+    Ok(())
+}
+
+/// Checks if a chunk type should be removed by `strip`. Critical chunks, tRNS chunks (which
+/// change how the pixels look) and chunk types in the keep-list are never removed. The animation
+/// chunks are kept or removed together, so keeping any of them keeps the animation.
+fn should_strip(chunk_type: &ChunkType, profile: StripProfile, keep: &[ChunkType]) -> bool {
+    let bytes = chunk_type.bytes();
+    if chunk_type.is_critical() || bytes == *b"tRNS" || keep.contains(chunk_type) {
+        return false;
+    }
+
+    if ANIMATION_CHUNK_TYPES.contains(&&bytes)
+        && keep
+            .iter()
+            .any(|kept| ANIMATION_CHUNK_TYPES.contains(&&kept.bytes()))
+    {
+        return false;
+    }
+
+    match profile {
+        StripProfile::Ancillary => true,
+        StripProfile::Private => !chunk_type.is_public(),
+        StripProfile::Text => TEXT_CHUNK_TYPES.contains(&&chunk_type.bytes()),
+    }
+}
+
+/// Removes metadata chunks from the image, leaving the chunks needed to display it
+fn strip(args: StripArgs) -> Result<()> {
+    let keep = args
+        .keep
+        .iter()
+        .map(|chunk_type| ChunkType::from_str(chunk_type))
+        .collect::<Result<Vec<_>>>()?;
+
+    // Strip never removes critical chunks, so the image data can be copied straight from the
+    // mapped file
     let path = Path::new(&args.file_path);
-    let png = Png::from_file(path).context("Unable to load image file")?;
+    let mut png = MappedPng::open(path).context("Unable to load image file")?;
+    png.verify_crcs().context("Unable to load image file")?;
+
+    let mut removed_chunk_counts: Vec<(String, usize)> = Vec::new();
+    let mut bytes_saved = 0;
+    for chunk in png.chunks() {
+        if !should_strip(chunk.chunk_type(), args.profile, &keep) {
+            continue;
+        }
 
-    let mut chunk_types = HashSet::new();
+        // The data plus the length, chunk type and CRC
+        bytes_saved += chunk.data().len() + 12;
 
-    for chunk in png.chunks().iter() {
-        let chunk_type = chunk.chunk_type();
-        if !chunk_type.is_public() {
-            chunk_types.insert(chunk.chunk_type().to_string());
+        let chunk_type = chunk.chunk_type().to_string();
+        match removed_chunk_counts
+            .iter_mut()
+            .find(|(t, _)| *t == chunk_type)
+        {
+            Some((_, count)) => *count += 1,
+            None => removed_chunk_counts.push((chunk_type, 1)),
         }
     }
 
-    println!("{}", chunk_types.into_iter().collect::<Vec<_>>().join("\n"));
+    if removed_chunk_counts.is_empty() {
+        println!("No chunks to strip");
+        return Ok(());
+    }
+
+    png.retain(|chunk| !should_strip(chunk.chunk_type(), args.profile, &keep))?;
+
+    let destination = args.output_file.unwrap_or(args.file_path);
+    png.save(destination)?;
+
+    println!("Chunks removed:");
+    for (chunk_type, removed_chunk_count) in removed_chunk_counts.iter() {
+        println!("  {}: {}", chunk_type, removed_chunk_count);
+    }
+
+    println!("Bytes saved: {}", bytes_saved);
 
     Ok(())
 }
 
-/// Removes all chunks of a specific chunk type. This will overwrite the file.
-fn remove(args: RemoveArgs) -> Result<()> {
+/// Writes the raw data of a chunk to a file
+fn chunk_extract(args: ChunkExtractArgs) -> Result<()> {
+    let path = Path::new(&args.file_path);
+    let png = MappedPng::open(path).context("Unable to load image file")?;
+
+    let chunk = png
+        .chunks()
+        .filter(|chunk| chunk.chunk_type().bytes() == args.chunk_type.as_bytes())
+        .nth(args.index)
+        .context(format!(
+            "No chunk with chunk type \"{}\" found at index {}",
+            args.chunk_type, args.index
+        ))?;
+    chunk.verify_crc()?;
+
+    fs::write(&args.output_file, chunk.data()).context("Error writing data file")?;
+
+    println!(
+        "{} bytes written to {}",
+        chunk.data().len(),
+        args.output_file
+    );
+
+    Ok(())
+}
+
+/// Inserts a chunk with raw data read from a file
+fn chunk_insert(args: ChunkInsertArgs) -> Result<()> {
+    let chunk_type = ChunkType::from_str(&args.chunk_type)?;
+    if args.allow_critical {
+        chunk_type.is_valid()?;
+    } else {
+        chunk_type.is_valid_for_message()?;
+    }
+
     let path = Path::new(&args.file_path);
     let mut png = Png::from_file(path).context("Unable to load image file")?;
 
-    let mut removed_chunk_count = 0;
+    let data = fs::read(&args.data_file).context("Unable to read data file")?;
+    let data_length = data.len();
+
+    let index = png.position_index(args.position)?;
+    png.insert_chunk(index, Chunk::new(chunk_type, data));
+
+    let destination = args.output_file.unwrap_or(args.file_path);
+    write_png(destination, &png)?;
+
+    println!(
+        "Chunk with {} bytes of data inserted at index {}",
+        data_length, index
+    );
+
+    Ok(())
+}
+
+fn chunk(args: ChunkArgs) -> Result<()> {
+    match args.command {
+        ChunkCommands::Extract(args) => chunk_extract(args),
+        ChunkCommands::Insert(args) => chunk_insert(args),
+    }
+}
+
+/// Decodes the image data and encodes it again with the given compression settings
+fn optimize(args: OptimizeArgs) -> Result<()> {
+    let mut png = Png::from_file(&args.file_path).context("Unable to load image file")?;
+    let original_size = png.as_bytes().len();
+
+    let image = png
+        .image_data()
+        .context("Unable to decode the image data")?
+        .unfilter();
+
+    let options = EncodeOptions {
+        compression_level: args.level,
+        filter_strategy: args.filter,
+        idat_size: args.idat_size,
+    };
+    png.set_image_data(&image, &options)?;
+
+    let destination = args.output_file.unwrap_or(args.file_path);
+    let bytes_written = write_png(destination, &png)?;
+
+    println!("Image data re-encoded");
+    println!("Bytes before: {}", original_size);
+    println!("Bytes after: {}", bytes_written);
 
-    loop {
-        match png.remove_first_chunk(&args.chunk_type) {
-            Some(_) => removed_chunk_count += 1,
-            _ => break,
+    Ok(())
+}
+
+/// Prints the image header, the chunk list and the palette of an image
+fn inspect(args: InspectArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path).context("Unable to load image file")?;
+    let ihdr = png.ihdr()?;
+
+    println!(
+        "Image: {}x{}, {}-bit {}, {}",
+        ihdr.width(),
+        ihdr.height(),
+        ihdr.bit_depth(),
+        ihdr.color_type(),
+        if ihdr.is_interlaced() {
+            "Adam7 interlaced"
+        } else {
+            "not interlaced"
+        }
+    );
+
+    println!("Chunks:");
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        println!(
+            "  {}: {} ({} bytes)",
+            index,
+            chunk.chunk_type(),
+            chunk.length()
+        );
+    }
+
+    if let Some(animation) = png.animation()? {
+        let plays = match animation.control.num_plays {
+            0 => "looping forever".to_string(),
+            plays => format!("played {} time(s)", plays),
         };
+        println!(
+            "Animation: {} frame(s), {}{}",
+            animation.frames.len(),
+            plays,
+            if animation.default_image_is_first_frame {
+                ""
+            } else {
+                ", with a separate default image"
+            }
+        );
+
+        for (index, frame) in animation.frames.iter().enumerate() {
+            let control = &frame.control;
+            println!(
+                "  Frame {}: {}x{} at ({}, {}), {} ms, dispose {}, blend {}",
+                index,
+                control.width,
+                control.height,
+                control.x_offset,
+                control.y_offset,
+                control.delay_ms(),
+                control.dispose_op,
+                control.blend_op
+            );
+        }
     }
 
-    if removed_chunk_count > 0 {
-        let mut file = fs::OpenOptions::new()
-            .write(true)
-            // Truncate empties the file after opening it, this is necessary since we want to
-            // replace its contents
-            .truncate(true)
-            .open(&args.file_path)
-            .context("Unable to open image file to write")?;
+    let transparency = png.transparency()?;
+
+    if let Some(palette) = png.palette()? {
+        println!("Palette: {} entries", palette.len());
+        for (index, color) in palette.entries().iter().enumerate() {
+            match transparency.as_ref().and_then(|t| t.alpha(index)) {
+                Some(alpha) if alpha != 255 => {
+                    println!("  {}: {} (alpha {})", index, color, alpha)
+                }
+                _ => println!("  {}: {}", index, color),
+            }
+        }
+    }
 
-        file.write_all(&png.as_bytes())
-            .context("Error writing image file")?;
+    match transparency {
+        Some(Transparency::Grayscale(gray)) => {
+            println!("Transparent gray level: {}", gray)
+        }
+        Some(Transparency::Rgb(red, green, blue)) => {
+            println!("Transparent color: ({}, {}, {})", red, green, blue)
+        }
+        _ => {}
+    }
 
-        println!("Number of chunks removed: {}", removed_chunk_count);
-    } else {
-        println!("No chunk with chunk type \"{}\" found", args.chunk_type);
+    Ok(())
+}
+
+/// Hides a message in the palette order of an indexed image
+fn palette_hide(args: PaletteHideArgs) -> Result<()> {
+    let mut png = Png::from_file(&args.file_path).context("Unable to load image file")?;
+
+    // Filtering rarely helps indexed images, as recommended by the PNG spec
+    let options = EncodeOptions {
+        compression_level: 9,
+        filter_strategy: FilterStrategy::Fixed(FilterType::None),
+        idat_size: None,
+    };
+    palette_order::hide(&mut png, args.message.as_bytes(), &options)?;
+
+    let destination = args.output_file.unwrap_or(args.file_path);
+    let bytes_written = write_png(destination, &png)?;
+
+    println!("Message successfuly hidden in the palette order");
+    println!("Bytes written: {}", bytes_written);
+
+    Ok(())
+}
+
+/// Prints the message hidden in the palette order of an indexed image
+fn palette_reveal(args: PaletteRevealArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path).context("Unable to load image file")?;
+    let message = palette_order::reveal(&png)?;
+
+    println!("{}", String::from_utf8_lossy(&message));
+
+    Ok(())
+}
+
+fn palette(args: PaletteArgs) -> Result<()> {
+    match args.command {
+        PaletteCommands::Hide(args) => palette_hide(args),
+        PaletteCommands::Reveal(args) => palette_reveal(args),
+    }
+}
+
+/// Writes each frame of an animated image as a standalone image
+fn apng_split(args: ApngSplitArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path).context("Unable to load image file")?;
+    let frames = apng::split(&png)?;
+
+    let output_dir = Path::new(&args.output_dir);
+    fs::create_dir_all(output_dir).context("Unable to create output directory")?;
+
+    for (index, frame) in frames.iter().enumerate() {
+        let path = output_dir.join(format!("frame_{:03}.png", index));
+        write_png(&path, frame)?;
+        println!("{}", path.display());
     }
 
+    println!("Frames written: {}", frames.len());
+
+    Ok(())
+}
+
+/// Assembles images into an animated image
+fn apng_build(args: ApngBuildArgs) -> Result<()> {
+    let frames = args
+        .frames
+        .iter()
+        .map(|path| Png::from_file(path).context(format!("Unable to load image file {}", path)))
+        .collect::<Result<Vec<_>>>()?;
+
+    let (delay_num, delay_den) = FrameControl::delay_from_ms(args.delay.0)?;
+    let png = apng::build(&frames, delay_num, delay_den, args.plays)?;
+    let bytes_written = write_png(&args.output_file, &png)?;
+
+    println!("Animation built with {} frame(s)", frames.len());
+    println!("Bytes written: {}", bytes_written);
+
+    Ok(())
+}
+
+fn apng_command(args: ApngArgs) -> Result<()> {
+    match args.command {
+        ApngCommands::Split(args) => apng_split(args),
+        ApngCommands::Build(args) => apng_build(args),
+    }
+}
+
+/// Prints the bytes of the chunks of an image as a hex dump, with the length, type and CRC
+/// fields highlighted when printing to a terminal
+fn hexdump(args: HexdumpArgs) -> Result<()> {
+    let png = Png::from_file(&args.file_path).context("Unable to load image file")?;
+    let chunk_type = args
+        .chunk_type
+        .as_deref()
+        .map(ChunkType::from_str)
+        .transpose()?;
+
+    let highlight = io::stdout().is_terminal() && env::var_os("NO_COLOR").is_none();
+
+    // Chunks start after the signature, each one with 12 bytes of length, type and CRC
+    let mut offset = png.header().len();
+    let mut matching_chunk_count = 0;
+    let mut printed_chunk_count = 0;
+
+    for (index, chunk) in png.chunks().iter().enumerate() {
+        let chunk_offset = offset;
+        offset += chunk.length() as usize + 12;
+
+        if chunk_type.is_some_and(|chunk_type| chunk.chunk_type() != &chunk_type) {
+            continue;
+        }
+
+        matching_chunk_count += 1;
+        if args
+            .index
+            .is_some_and(|wanted| wanted != matching_chunk_count - 1)
+        {
+            continue;
+        }
+
+        if printed_chunk_count > 0 {
+            println!();
+        }
+        println!(
+            "Chunk {}: {} ({} bytes of data)",
+            index,
+            chunk.chunk_type(),
+            chunk.length()
+        );
+        print!(
+            "{}",
+            chunk.hexdump().offset(chunk_offset).highlight(highlight)
+        );
+        printed_chunk_count += 1;
+    }
+
+    if printed_chunk_count == 0 {
+        match (&args.chunk_type, args.index) {
+            (Some(chunk_type), Some(index)) => println!(
+                "No chunk with chunk type \"{}\" found at index {}",
+                chunk_type, index
+            ),
+            (Some(chunk_type), None) => {
+                println!("No chunk with chunk type \"{}\" found", chunk_type)
+            }
+            (None, Some(index)) => println!(
+                "No chunk at index {}, the image has {} chunk(s)",
+                index, matching_chunk_count
+            ),
+            (None, None) => println!("The image has no chunks"),
+        }
+    }
+
+    Ok(())
+}
+
+/// Writes a manifest describing every chunk of an image, with their data inline or in sidecar
+/// files
+fn dump(args: DumpArgs) -> Result<()> {
+    let manifest_path = Path::new(&args.manifest);
+    let format = ManifestFormat::from_path(manifest_path)?;
+
+    let png = Png::from_file(&args.file_path).context("Unable to load image file")?;
+
+    let manifest = if args.sidecar {
+        let base_dir = manifest_path.parent().unwrap_or(Path::new(""));
+        let data_dir = manifest_path.with_extension("data");
+        let data_dir = data_dir.file_name().context("Invalid manifest file path")?;
+        Manifest::with_data_files(&png, base_dir, Path::new(data_dir))?
+    } else {
+        Manifest::new(&png)
+    };
+
+    fs::write(manifest_path, manifest.serialize(format)?)
+        .context("Unable to write manifest file")?;
+
+    println!("Manifest written with {} chunk(s)", manifest.chunks.len());
+
+    Ok(())
+}
+
+/// Recreates an image from a manifest
+fn build(args: BuildArgs) -> Result<()> {
+    let manifest_path = Path::new(&args.manifest);
+    let manifest = Manifest::from_file(manifest_path)?;
+
+    let base_dir = manifest_path.parent().unwrap_or(Path::new(""));
+    let png = manifest.to_png(base_dir)?;
+    let bytes_written = write_png(&args.output_file, &png)?;
+
+    println!("Image built with {} chunk(s)", png.chunks().len());
+    println!("Bytes written: {}", bytes_written);
+
     Ok(())
 }
 
@@ -138,12 +722,168 @@ fn main() {
 
     let result = match cli.command {
         Commands::Encode(args) => encode(args),
+        Commands::Update(args) => update(args),
         Commands::Decode(args) => decode(args),
         Commands::Print(args) => print(args),
         Commands::Remove(args) => remove(args),
+        Commands::Strip(args) => strip(args),
+        Commands::Chunk(args) => chunk(args),
+        Commands::Optimize(args) => optimize(args),
+        Commands::Inspect(args) => inspect(args),
+        Commands::Palette(args) => palette(args),
+        Commands::Apng(args) => apng_command(args),
+        Commands::Hexdump(args) => hexdump(args),
+        Commands::Dump(args) => dump(args),
+        Commands::Build(args) => build(args),
     };
 
     if let Err(e) = result {
         println!("Error: {}", e);
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chunk_type(chunk_type: &str) -> ChunkType {
+        ChunkType::from_str(chunk_type).unwrap()
+    }
+
+    /// Chunk types left after stripping an image with these chunk types
+    fn strip_chunk_types<'a>(
+        chunk_types: &[&'a str],
+        profile: StripProfile,
+        keep: &[&str],
+    ) -> Vec<&'a str> {
+        let keep: Vec<ChunkType> = keep.iter().map(|kept| chunk_type(kept)).collect();
+        chunk_types
+            .iter()
+            .copied()
+            .filter(|kept| !should_strip(&chunk_type(kept), profile, &keep))
+            .collect()
+    }
+
+    #[test]
+    fn test_should_strip_ancillary() {
+        assert!(should_strip(
+            &chunk_type("tEXt"),
+            StripProfile::Ancillary,
+            &[]
+        ));
+        assert!(should_strip(
+            &chunk_type("gAMA"),
+            StripProfile::Ancillary,
+            &[]
+        ));
+        assert!(should_strip(
+            &chunk_type("ruSt"),
+            StripProfile::Ancillary,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn test_should_strip_private() {
+        assert!(should_strip(
+            &chunk_type("ruSt"),
+            StripProfile::Private,
+            &[]
+        ));
+        assert!(should_strip(
+            &chunk_type("prVt"),
+            StripProfile::Private,
+            &[]
+        ));
+        assert!(!should_strip(
+            &chunk_type("tEXt"),
+            StripProfile::Private,
+            &[]
+        ));
+        assert!(!should_strip(
+            &chunk_type("gAMA"),
+            StripProfile::Private,
+            &[]
+        ));
+    }
+
+    #[test]
+    fn test_should_strip_text() {
+        for text_chunk_type in ["tEXt", "zTXt", "iTXt"] {
+            assert!(should_strip(
+                &chunk_type(text_chunk_type),
+                StripProfile::Text,
+                &[]
+            ));
+        }
+        assert!(!should_strip(&chunk_type("gAMA"), StripProfile::Text, &[]));
+        assert!(!should_strip(&chunk_type("ruSt"), StripProfile::Text, &[]));
+    }
+
+    #[test]
+    fn test_should_strip_keeps_transparency() {
+        let indexed = ["IHDR", "PLTE", "tRNS", "tEXt", "IDAT", "IEND"];
+
+        assert_eq!(
+            strip_chunk_types(&indexed, StripProfile::Ancillary, &[]),
+            vec!["IHDR", "PLTE", "tRNS", "IDAT", "IEND"]
+        );
+    }
+
+    #[test]
+    fn test_should_strip_animation_as_a_group() {
+        let apng = [
+            "IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "tEXt", "IEND",
+        ];
+        let still = vec!["IHDR", "IDAT", "IEND"];
+        let animated = vec!["IHDR", "acTL", "fcTL", "IDAT", "fcTL", "fdAT", "IEND"];
+
+        assert_eq!(
+            strip_chunk_types(&apng, StripProfile::Ancillary, &[]),
+            still
+        );
+        assert_eq!(
+            strip_chunk_types(&apng, StripProfile::Private, &[]),
+            vec!["IHDR", "IDAT", "tEXt", "IEND"]
+        );
+        for kept in ["acTL", "fcTL", "fdAT"] {
+            assert_eq!(
+                strip_chunk_types(&apng, StripProfile::Ancillary, &[kept]),
+                animated
+            );
+        }
+        assert_eq!(strip_chunk_types(&apng, StripProfile::Text, &[]), animated);
+    }
+
+    #[test]
+    fn test_should_strip_keeps_listed_chunk_types() {
+        let keep = [chunk_type("sRGB"), chunk_type("tEXt")];
+
+        assert!(!should_strip(
+            &chunk_type("sRGB"),
+            StripProfile::Ancillary,
+            &keep
+        ));
+        assert!(!should_strip(
+            &chunk_type("tEXt"),
+            StripProfile::Text,
+            &keep
+        ));
+        assert!(should_strip(&chunk_type("zTXt"), StripProfile::Text, &keep));
+    }
+
+    #[test]
+    fn test_should_strip_never_removes_critical_chunks() {
+        let profiles = [
+            StripProfile::Ancillary,
+            StripProfile::Private,
+            StripProfile::Text,
+        ];
+
+        for profile in profiles {
+            for critical in ["IHDR", "PLTE", "IDAT", "IEND", "RUST"] {
+                assert!(!should_strip(&chunk_type(critical), profile, &[]));
+            }
+        }
+    }
+}
@@ -0,0 +1,357 @@
+//! Describes every chunk of an image in a JSON or YAML manifest, and rebuilds the exact same
+//! image from it.
+//!
+//! Each chunk is listed in file order with its type, length, CRC and property flags. Its data is
+//! stored inline as base64, or in a sidecar file whose path is relative to the manifest. When
+//! rebuilding, the length and CRC are checked against the data so an edited manifest can't
+//! silently produce a different image.
+
+use std::{
+    fs,
+    path::{Component, Path, PathBuf},
+    str::FromStr,
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use base64::{engine::general_purpose::STANDARD, Engine};
+use serde::{Deserialize, Serialize};
+
+use crate::{chunk::Chunk, chunk_type::ChunkType, png::Png, signature::Signature};
+
+/// The serialization format of a manifest, detected from its file extension
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ManifestFormat {
+    Json,
+    Yaml,
+}
+
+impl ManifestFormat {
+    pub fn from_path<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let extension = path
+            .extension()
+            .and_then(|extension| extension.to_str())
+            .map(|extension| extension.to_ascii_lowercase());
+
+        match extension.as_deref() {
+            Some("json") => Ok(Self::Json),
+            Some("yaml" | "yml") => Ok(Self::Yaml),
+            _ => bail!(
+                "Unknown manifest format for \"{}\", the extension should be .json, .yaml or .yml",
+                path.display()
+            ),
+        }
+    }
+}
+
+/// The properties encoded in the case of the chunk type letters
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+pub struct ChunkFlags {
+    pub critical: bool,
+    pub public: bool,
+    pub reserved_bit_valid: bool,
+    pub safe_to_copy: bool,
+}
+
+impl From<&ChunkType> for ChunkFlags {
+    fn from(chunk_type: &ChunkType) -> Self {
+        Self {
+            critical: chunk_type.is_critical(),
+            public: chunk_type.is_public(),
+            reserved_bit_valid: chunk_type.is_reserved_bit_valid(),
+            safe_to_copy: chunk_type.is_safe_to_copy(),
+        }
+    }
+}
+
+/// A chunk in a manifest. Exactly one of `data` and `data_file` is set.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct ChunkEntry {
+    #[serde(rename = "type")]
+    pub chunk_type: String,
+
+    pub length: u32,
+
+    /// The CRC as 8 hexadecimal digits
+    pub crc: String,
+
+    /// Informational only, the flags always follow the chunk type when rebuilding
+    pub flags: ChunkFlags,
+
+    /// The chunk data encoded in base64
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data: Option<String>,
+
+    /// A file holding the raw chunk data, relative to the manifest
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub data_file: Option<PathBuf>,
+}
+
+impl ChunkEntry {
+    /// Describes a chunk, with its data inline unless it's stored in `data_file`
+    fn new(chunk: &Chunk, data_file: Option<PathBuf>) -> Self {
+        Self {
+            chunk_type: chunk.chunk_type().to_string(),
+            length: chunk.length(),
+            crc: format!("{:08x}", chunk.crc()),
+            flags: ChunkFlags::from(chunk.chunk_type()),
+            data: data_file.is_none().then(|| STANDARD.encode(chunk.data())),
+            data_file,
+        }
+    }
+
+    /// Recreates the chunk, reading its data from `base_dir` if it's in a sidecar file
+    fn to_chunk(&self, base_dir: &Path) -> Result<Chunk> {
+        let chunk_type = ChunkType::from_str(&self.chunk_type)?;
+
+        let data = match (&self.data, &self.data_file) {
+            (Some(data), None) => STANDARD
+                .decode(data)
+                .context(format!("Invalid base64 data in the {} chunk", chunk_type))?,
+            (None, Some(data_file)) => {
+                // Keeps a manifest from reading files outside of its directory
+                let is_inside = data_file
+                    .components()
+                    .all(|component| matches!(component, Component::Normal(_) | Component::CurDir));
+                ensure!(
+                    is_inside,
+                    "Invalid data file \"{}\" in the {} chunk, it should be a relative path \
+                     without \"..\"",
+                    data_file.display(),
+                    chunk_type
+                );
+
+                let path = base_dir.join(data_file);
+                fs::read(&path).context(format!("Unable to read {}", path.display()))?
+            }
+            _ => bail!(
+                "The {} chunk should have either \"data\" or \"data_file\"",
+                chunk_type
+            ),
+        };
+
+        let chunk = Chunk::new(chunk_type, data);
+
+        ensure!(
+            chunk.length() == self.length,
+            "The {} chunk has {} bytes of data but the manifest says {}",
+            chunk_type,
+            chunk.length(),
+            self.length
+        );
+
+        let crc = u32::from_str_radix(&self.crc, 16).context(format!(
+            "Invalid CRC \"{}\" in the {} chunk",
+            self.crc, chunk_type
+        ))?;
+        ensure!(
+            chunk.crc() == crc,
+            "The CRC of the {} chunk is {:08x} but the manifest says {}",
+            chunk_type,
+            chunk.crc(),
+            self.crc
+        );
+
+        Ok(chunk)
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct Manifest {
+    /// PNG, MNG or JNG
+    pub signature: String,
+
+    pub chunks: Vec<ChunkEntry>,
+}
+
+impl Manifest {
+    /// Describes every chunk of an image, with its data inline
+    pub fn new(png: &Png) -> Self {
+        Self {
+            signature: png.signature().to_string(),
+            chunks: png
+                .chunks()
+                .iter()
+                .map(|chunk| ChunkEntry::new(chunk, None))
+                .collect(),
+        }
+    }
+
+    /// Describes every chunk of an image, writing the data of each chunk to a file in `data_dir`,
+    /// named after the chunk index and type, like `003_IDAT.bin`. `data_dir` is relative to
+    /// `base_dir`, usually the directory of the manifest.
+    pub fn with_data_files(png: &Png, base_dir: &Path, data_dir: &Path) -> Result<Self> {
+        fs::create_dir_all(base_dir.join(data_dir)).context("Unable to create data directory")?;
+
+        let chunks = png
+            .chunks()
+            .iter()
+            .enumerate()
+            .map(|(index, chunk)| {
+                let data_file = data_dir.join(format!("{:03}_{}.bin", index, chunk.chunk_type()));
+                let path = base_dir.join(&data_file);
+                fs::write(&path, chunk.data())
+                    .context(format!("Unable to write {}", path.display()))?;
+
+                Ok(ChunkEntry::new(chunk, Some(data_file)))
+            })
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Self {
+            signature: png.signature().to_string(),
+            chunks,
+        })
+    }
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let path = path.as_ref();
+        let format = ManifestFormat::from_path(path)?;
+        let contents = fs::read_to_string(path).context("Unable to read manifest file")?;
+
+        Self::parse(&contents, format)
+    }
+
+    pub fn parse(contents: &str, format: ManifestFormat) -> Result<Self> {
+        match format {
+            ManifestFormat::Json => serde_json::from_str(contents).context("Invalid JSON manifest"),
+            ManifestFormat::Yaml => serde_yaml::from_str(contents).context("Invalid YAML manifest"),
+        }
+    }
+
+    pub fn serialize(&self, format: ManifestFormat) -> Result<String> {
+        match format {
+            ManifestFormat::Json => {
+                serde_json::to_string_pretty(self).context("Unable to serialize manifest")
+            }
+            ManifestFormat::Yaml => {
+                serde_yaml::to_string(self).context("Unable to serialize manifest")
+            }
+        }
+    }
+
+    /// Rebuilds the image, reading sidecar data files relative to `base_dir`
+    pub fn to_png(&self, base_dir: &Path) -> Result<Png> {
+        let Some(signature) = Signature::ALL
+            .into_iter()
+            .find(|signature| signature.to_string() == self.signature)
+        else {
+            bail!(
+                "Unknown signature \"{}\", it should be PNG, MNG or JNG",
+                self.signature
+            );
+        };
+
+        let chunks = self
+            .chunks
+            .iter()
+            .map(|entry| entry.to_chunk(base_dir))
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Png::with_signature(signature, chunks))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_png() -> Png {
+        let chunks = [
+            ("IHDR", &b"header"[..]),
+            ("ruSt", b"\x00\xffsecret"),
+            ("IEND", b""),
+        ]
+        .into_iter()
+        .map(|(chunk_type, data)| {
+            Chunk::new(ChunkType::from_str(chunk_type).unwrap(), data.to_vec())
+        })
+        .collect();
+
+        Png::from_chunks(chunks)
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            ManifestFormat::from_path("a.json").unwrap(),
+            ManifestFormat::Json
+        );
+        assert_eq!(
+            ManifestFormat::from_path("a.YML").unwrap(),
+            ManifestFormat::Yaml
+        );
+        assert!(ManifestFormat::from_path("a.txt").is_err());
+        assert!(ManifestFormat::from_path("json").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let png = testing_png();
+        let manifest = Manifest::new(&png);
+
+        assert_eq!(manifest.chunks[1].chunk_type, "ruSt");
+        assert_eq!(manifest.chunks[1].length, 8);
+        assert!(!manifest.chunks[1].flags.critical);
+        assert!(manifest.chunks[0].flags.critical);
+
+        for format in [ManifestFormat::Json, ManifestFormat::Yaml] {
+            let contents = manifest.serialize(format).unwrap();
+            let parsed = Manifest::parse(&contents, format).unwrap();
+            assert_eq!(parsed, manifest);
+
+            let rebuilt = parsed.to_png(Path::new(".")).unwrap();
+            assert_eq!(rebuilt.as_bytes(), png.as_bytes());
+        }
+    }
+
+    #[test]
+    fn test_data_files() {
+        let dir = std::env::temp_dir().join(format!("pngme-manifest-{}", std::process::id()));
+        let png = testing_png();
+        let manifest = Manifest::with_data_files(&png, &dir, Path::new("data")).unwrap();
+
+        assert!(manifest.chunks.iter().all(|entry| entry.data.is_none()));
+        assert_eq!(
+            manifest.chunks[1].data_file.as_deref(),
+            Some(Path::new("data/001_ruSt.bin"))
+        );
+        assert_eq!(
+            fs::read(dir.join("data/001_ruSt.bin")).unwrap(),
+            b"\x00\xffsecret"
+        );
+        assert_eq!(manifest.to_png(&dir).unwrap().as_bytes(), png.as_bytes());
+
+        // Data files outside of the manifest directory are refused
+        for data_file in ["../data/001_ruSt.bin", "data/../../001_ruSt.bin"] {
+            let mut outside = manifest.clone();
+            outside.chunks[1].data_file = Some(PathBuf::from(data_file));
+            assert!(outside.to_png(&dir.join("data")).is_err());
+        }
+        let mut absolute = manifest.clone();
+        absolute.chunks[1].data_file = Some(dir.join("data/001_ruSt.bin"));
+        assert!(absolute.to_png(&dir).is_err());
+
+        std::fs::remove_dir_all(dir).unwrap();
+    }
+
+    #[test]
+    fn test_edited_manifest() {
+        let manifest = Manifest::new(&testing_png());
+
+        let mut wrong_crc = manifest.clone();
+        wrong_crc.chunks[1].crc = "00000000".to_string();
+        assert!(wrong_crc.to_png(Path::new(".")).is_err());
+
+        let mut wrong_length = manifest.clone();
+        wrong_length.chunks[1].length = 1;
+        assert!(wrong_length.to_png(Path::new(".")).is_err());
+
+        let mut no_data = manifest.clone();
+        no_data.chunks[1].data = None;
+        assert!(no_data.to_png(Path::new(".")).is_err());
+
+        let mut wrong_signature = manifest;
+        wrong_signature.signature = "GIF".to_string();
+        assert!(wrong_signature.to_png(Path::new(".")).is_err());
+    }
+}
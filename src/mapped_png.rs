@@ -0,0 +1,514 @@
+use std::{
+    fs::{self, File, Metadata},
+    io::{BufWriter, Write},
+    ops::Range,
+    path::{Path, PathBuf},
+};
+
+use anyhow::{bail, ensure, Context, Result};
+use memmap2::Mmap;
+
+use crate::{
+    chunk::Chunk, chunk_ref::ChunkRef, chunk_type::ChunkType, png::Png, png_ref::PngRef,
+    signature::Signature,
+};
+
+/// A temporary file next to the image being saved, removed when dropped unless it replaced the
+/// image
+struct TempFile {
+    path: PathBuf,
+    persisted: bool,
+}
+
+impl Drop for TempFile {
+    fn drop(&mut self) {
+        if !self.persisted {
+            // Best effort, the error that caused the early return is the one worth reporting
+            let _ = fs::remove_file(&self.path);
+        }
+    }
+}
+
+/// Checks if other paths lead to the same file, which replacing it would cut off
+#[cfg(unix)]
+fn has_hard_links(metadata: &Metadata) -> bool {
+    use std::os::unix::fs::MetadataExt;
+    metadata.nlink() > 1
+}
+
+#[cfg(not(unix))]
+fn has_hard_links(_metadata: &Metadata) -> bool {
+    false
+}
+
+#[derive(Debug)]
+enum MappedChunk {
+    /// A chunk left untouched since the file was opened, stored as the range of its data in the
+    /// mapped file
+    Mapped {
+        chunk_type: ChunkType,
+        data: Range<usize>,
+        crc: u32,
+    },
+
+    /// A chunk added after the file was opened
+    Owned(Chunk),
+}
+
+/// A PNG file accessed through a memory map, for images too large to comfortably load with
+/// [Png::from_file].
+///
+/// Opening the file only parses the chunk boundaries, chunk data stays in the mapped file until
+/// it's needed. Only ancillary chunks can be added or removed, so when saving, the critical
+/// chunks (including the IDAT chunks with the image data) are copied straight from the mapped
+/// file.
+#[derive(Debug)]
+pub struct MappedPng {
+    mmap: Mmap,
+    signature: Signature,
+    chunks: Vec<MappedChunk>,
+}
+
+impl MappedPng {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let file = File::open(path).context("Unable to open file")?;
+
+        // Safety: the map is only read from. Like with any memory-mapped file, the contents may
+        // still change if another process modifies the file while it's open.
+        let mmap = unsafe { Mmap::map(&file) }.context("Unable to map file")?;
+
+        let mut chunks = Vec::new();
+        let png = PngRef::try_from(&mmap[..])?;
+        let mut chunk_refs = png.chunks();
+
+        loop {
+            let offset = chunk_refs.offset();
+            let chunk = match chunk_refs.next() {
+                Some(chunk) => chunk?,
+                None => break,
+            };
+
+            // The data starts after the length and chunk type
+            let data_start = offset + 8;
+
+            chunks.push(MappedChunk::Mapped {
+                chunk_type: *chunk.chunk_type(),
+                data: data_start..data_start + chunk.data().len(),
+                crc: chunk.crc(),
+            });
+        }
+
+        let signature = png.signature();
+        Ok(Self {
+            mmap,
+            signature,
+            chunks,
+        })
+    }
+
+    /// Size in bytes of the mapped file
+    pub fn file_size(&self) -> usize {
+        self.mmap.len()
+    }
+
+    pub fn header(&self) -> &[u8; 8] {
+        self.signature.bytes()
+    }
+
+    pub fn signature(&self) -> Signature {
+        self.signature
+    }
+
+    pub fn len(&self) -> usize {
+        self.chunks.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.chunks.is_empty()
+    }
+
+    pub fn chunk(&self, index: usize) -> Option<ChunkRef<'_>> {
+        self.chunks.get(index).map(|chunk| self.chunk_ref(chunk))
+    }
+
+    /// Iterates over the chunks, borrowing the data of untouched chunks from the mapped file
+    pub fn chunks(&self) -> impl Iterator<Item = ChunkRef<'_>> {
+        self.chunks.iter().map(|chunk| self.chunk_ref(chunk))
+    }
+
+    fn chunk_ref<'a>(&'a self, chunk: &'a MappedChunk) -> ChunkRef<'a> {
+        match chunk {
+            MappedChunk::Mapped {
+                chunk_type,
+                data,
+                crc,
+            } => ChunkRef::new(*chunk_type, &self.mmap[data.clone()], *crc),
+            MappedChunk::Owned(chunk) => ChunkRef::from(chunk),
+        }
+    }
+
+    /// Inserts an ancillary chunk at `index`, shifting all chunks after it
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) -> Result<()> {
+        ensure!(
+            !chunk.chunk_type().is_critical(),
+            "Unable to insert the critical chunk \"{}\" in a memory-mapped image",
+            chunk.chunk_type()
+        );
+        ensure!(
+            index <= self.chunks.len(),
+            "Invalid position {}, the image only has {} chunk(s)",
+            index,
+            self.chunks.len()
+        );
+
+        self.chunks.insert(index, MappedChunk::Owned(chunk));
+
+        Ok(())
+    }
+
+    /// Removes the ancillary chunk at `index`, returning its chunk type
+    pub fn remove_chunk(&mut self, index: usize) -> Result<ChunkType> {
+        let chunk_type = *self
+            .chunk(index)
+            .context(format!("There's no chunk at index {}", index))?
+            .chunk_type();
+
+        ensure!(
+            !chunk_type.is_critical(),
+            "Unable to remove the critical chunk \"{}\" from a memory-mapped image",
+            chunk_type
+        );
+
+        self.chunks.remove(index);
+
+        Ok(chunk_type)
+    }
+
+    /// Keeps only the chunks for which `f` returns true. Fails without removing any chunks if a
+    /// critical chunk would be removed.
+    pub fn retain<F: FnMut(&ChunkRef) -> bool>(&mut self, mut f: F) -> Result<()> {
+        let keep: Vec<bool> = self.chunks().map(|chunk| f(&chunk)).collect();
+
+        if let Some(chunk) = self
+            .chunks()
+            .zip(keep.iter())
+            .find(|(chunk, keep)| !**keep && chunk.chunk_type().is_critical())
+            .map(|(chunk, _)| chunk)
+        {
+            bail!(
+                "Unable to remove the critical chunk \"{}\" from a memory-mapped image",
+                chunk.chunk_type()
+            );
+        }
+
+        let mut keep = keep.into_iter();
+        self.chunks.retain(|_| keep.next().unwrap_or(true));
+
+        Ok(())
+    }
+
+    /// Checks the CRC of every chunk
+    pub fn verify_crcs(&self) -> Result<()> {
+        for chunk in self.chunks() {
+            chunk.verify_crc()?;
+        }
+
+        Ok(())
+    }
+
+    /// Loads all chunks into a [Png], checking their CRCs
+    pub fn to_png(&self) -> Result<Png> {
+        let chunks = self
+            .chunks()
+            .map(Chunk::try_from)
+            .collect::<Result<Vec<_>>>()?;
+
+        Ok(Png::with_signature(self.signature, chunks))
+    }
+
+    /// Writes the image to `path`, returning the number of bytes written.
+    ///
+    /// Untouched chunks are copied from the mapped file, with consecutive ones copied in a single
+    /// write. The image is written to a temporary file which then replaces `path`, so it's safe
+    /// to save over the mapped file, and the temporary file is removed if saving fails. The file
+    /// is unmapped before being replaced.
+    ///
+    /// When `path` already exists, the new file gets its permissions, and symbolic links are
+    /// followed. Files with hard links are overwritten in place from the temporary file instead of
+    /// being replaced, so all their paths see the new image.
+    pub fn save<P: AsRef<Path>>(self, path: P) -> Result<u64> {
+        let path = path.as_ref();
+        let existing = fs::metadata(path).ok();
+
+        // Replacing a symbolic link would turn it into a regular file, so the file it points to
+        // is replaced instead
+        let target = match existing {
+            Some(_) => fs::canonicalize(path).context("Unable to resolve image file path")?,
+            None => path.to_path_buf(),
+        };
+        let file_name = target
+            .file_name()
+            .context("Invalid image file path")?
+            .to_string_lossy();
+
+        let mut temp_file = TempFile {
+            path: target.with_file_name(format!(".{}.pngme-tmp", file_name)),
+            persisted: false,
+        };
+
+        let file = File::create(&temp_file.path).context("Unable to open image file to write")?;
+        let mut writer = BufWriter::new(file);
+
+        let mut bytes_written = 0u64;
+        let mut write = |bytes: &[u8]| -> Result<()> {
+            writer
+                .write_all(bytes)
+                .context("Error writing image file")?;
+            bytes_written += bytes.len() as u64;
+            Ok(())
+        };
+
+        write(self.header())?;
+
+        // Range of the mapped file waiting to be written
+        let mut pending: Option<Range<usize>> = None;
+
+        for chunk in self.chunks.iter() {
+            match chunk {
+                MappedChunk::Mapped { data, .. } => {
+                    // Includes the length, chunk type and CRC surrounding the data
+                    let range = data.start - 8..data.end + 4;
+                    pending = match pending {
+                        Some(p) if p.end == range.start => Some(p.start..range.end),
+                        Some(p) => {
+                            write(&self.mmap[p])?;
+                            Some(range)
+                        }
+                        None => Some(range),
+                    };
+                }
+                MappedChunk::Owned(chunk) => {
+                    if let Some(p) = pending.take() {
+                        write(&self.mmap[p])?;
+                    }
+                    write(&chunk.as_bytes())?;
+                }
+            }
+        }
+
+        if let Some(p) = pending {
+            write(&self.mmap[p])?;
+        }
+
+        let file = writer.into_inner().context("Error writing image file")?;
+        if let Some(metadata) = &existing {
+            file.set_permissions(metadata.permissions())
+                .context("Unable to set image file permissions")?;
+        }
+        file.sync_all().context("Error writing image file")?;
+        drop(file);
+
+        // Some platforms don't allow replacing a file that is still mapped
+        drop(self);
+
+        if existing.as_ref().is_some_and(has_hard_links) {
+            fs::copy(&temp_file.path, &target).context("Unable to overwrite image file")?;
+        } else {
+            fs::rename(&temp_file.path, &target).context("Unable to replace image file")?;
+            temp_file.persisted = true;
+        }
+
+        Ok(bytes_written)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::{path::PathBuf, str::FromStr};
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Chunk {
+        let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+        Chunk::new(chunk_type, data.bytes().collect())
+    }
+
+    fn testing_png() -> Png {
+        Png::from_chunks(vec![
+            chunk_from_strings("IHDR", "header"),
+            chunk_from_strings("teXt", "some text"),
+            chunk_from_strings("IDAT", "image data"),
+            chunk_from_strings("IDAT", "more image data"),
+            chunk_from_strings("IEND", ""),
+        ])
+    }
+
+    /// Writes the testing PNG to a file unique to the test
+    fn testing_file(name: &str) -> PathBuf {
+        let path =
+            std::env::temp_dir().join(format!("pngme-mapped-{}-{}.png", std::process::id(), name));
+        fs::write(&path, testing_png().as_bytes()).unwrap();
+        path
+    }
+
+    #[test]
+    fn test_open() {
+        let path = testing_file("open");
+        let png = MappedPng::open(&path).unwrap();
+
+        assert_eq!(png.len(), 5);
+        assert_eq!(png.file_size(), testing_png().as_bytes().len());
+        assert_eq!(png.chunk(2).unwrap().data(), "image data".as_bytes());
+        assert!(png.verify_crcs().is_ok());
+        assert_eq!(png.to_png().unwrap().as_bytes(), testing_png().as_bytes());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_corrupted_crc() {
+        let path = testing_file("corrupted");
+        let mut bytes = fs::read(&path).unwrap();
+        // Flips a bit in the data of the teXt chunk
+        bytes[8 + 18 + 8] ^= 1;
+        fs::write(&path, bytes).unwrap();
+
+        let png = MappedPng::open(&path).unwrap();
+        assert!(png.verify_crcs().is_err());
+        assert!(png.chunk(1).unwrap().verify_crc().is_err());
+        assert!(png.chunk(2).unwrap().verify_crc().is_ok());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_unchanged() {
+        let path = testing_file("unchanged");
+        let output = path.with_extension("out.png");
+        let png = MappedPng::open(&path).unwrap();
+        let file_size = png.file_size();
+
+        let bytes_written = png.save(&output).unwrap();
+
+        assert_eq!(fs::read(&output).unwrap(), fs::read(&path).unwrap());
+        assert_eq!(bytes_written as usize, file_size);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[test]
+    fn test_save_ancillary_edits() {
+        let path = testing_file("edits");
+        let mut png = MappedPng::open(&path).unwrap();
+
+        png.remove_chunk(1).unwrap();
+        png.insert_chunk(3, chunk_from_strings("ruSt", "message"))
+            .unwrap();
+        png.save(&path).unwrap();
+
+        let mut expected = testing_png();
+        expected.remove_chunk(1);
+        expected.insert_chunk(3, chunk_from_strings("ruSt", "message"));
+
+        assert_eq!(fs::read(&path).unwrap(), expected.as_bytes());
+
+        fs::remove_file(path).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_keeps_permissions() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let path = testing_file("permissions");
+        let output = path.with_extension("out.png");
+        fs::set_permissions(&path, fs::Permissions::from_mode(0o640)).unwrap();
+
+        MappedPng::open(&path).unwrap().save(&path).unwrap();
+        let mode = fs::metadata(&path).unwrap().permissions().mode();
+        assert_eq!(mode & 0o777, 0o640);
+
+        // A new output file doesn't get the permissions of the source file
+        MappedPng::open(&path).unwrap().save(&output).unwrap();
+        let mode = fs::metadata(&output).unwrap().permissions().mode();
+        assert_ne!(mode & 0o777, 0o640);
+
+        fs::remove_file(path).unwrap();
+        fs::remove_file(output).unwrap();
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn test_save_through_links() {
+        let path = testing_file("links");
+        let symlink = path.with_extension("symlink.png");
+        let hard_link = path.with_extension("hardlink.png");
+        std::os::unix::fs::symlink(&path, &symlink).unwrap();
+        fs::hard_link(&path, &hard_link).unwrap();
+
+        let mut png = MappedPng::open(&symlink).unwrap();
+        png.remove_chunk(1).unwrap();
+        png.save(&symlink).unwrap();
+
+        let mut expected = testing_png();
+        expected.remove_chunk(1);
+
+        assert!(fs::symlink_metadata(&symlink)
+            .unwrap()
+            .file_type()
+            .is_symlink());
+        assert_eq!(fs::read(&path).unwrap(), expected.as_bytes());
+        assert_eq!(fs::read(&hard_link).unwrap(), expected.as_bytes());
+        assert!(!path
+            .with_file_name(format!(
+                ".{}.pngme-tmp",
+                path.file_name().unwrap().to_string_lossy()
+            ))
+            .exists());
+
+        fs::remove_file(symlink).unwrap();
+        fs::remove_file(hard_link).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_save_failure_removes_temp_file() {
+        let path = testing_file("failure");
+
+        // Renaming a file over a non-empty directory fails
+        let output = path.with_extension("dir.png");
+        fs::create_dir_all(output.join("inner")).unwrap();
+        let temp_path = output.with_file_name(format!(
+            ".{}.pngme-tmp",
+            output.file_name().unwrap().to_string_lossy()
+        ));
+
+        let png = MappedPng::open(&path).unwrap();
+        assert!(png.save(&output).is_err());
+        assert!(!temp_path.exists());
+
+        fs::remove_dir_all(output).unwrap();
+        fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_critical_edits_fail() {
+        let path = testing_file("critical");
+        let mut png = MappedPng::open(&path).unwrap();
+
+        assert!(png.remove_chunk(2).is_err());
+        assert!(png
+            .insert_chunk(1, chunk_from_strings("IDAT", "data"))
+            .is_err());
+        assert!(png
+            .retain(|chunk| chunk.chunk_type().bytes() != *b"IEND")
+            .is_err());
+        assert_eq!(png.len(), 5);
+
+        png.retain(|chunk| chunk.chunk_type().is_critical())
+            .unwrap();
+        assert_eq!(png.len(), 4);
+
+        fs::remove_file(path).unwrap();
+    }
+}
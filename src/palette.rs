@@ -0,0 +1,477 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    ihdr::{ColorType, Ihdr},
+};
+
+/// An 8-bit RGB color, as stored in the entries of a palette
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub struct Color {
+    pub red: u8,
+    pub green: u8,
+    pub blue: u8,
+}
+
+impl Color {
+    pub fn new(red: u8, green: u8, blue: u8) -> Self {
+        Self { red, green, blue }
+    }
+}
+
+impl FromStr for Color {
+    type Err = Error;
+
+    /// Parses a color written as `#rrggbb`
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let hex = s
+            .strip_prefix('#')
+            .filter(|hex| hex.len() == 6 && hex.is_ascii())
+            .context(format!(
+                "Invalid color \"{}\", it should be written as #rrggbb",
+                s
+            ))?;
+
+        let component = |i: usize| {
+            u8::from_str_radix(&hex[i..i + 2], 16).context(format!(
+                "Invalid color \"{}\", it should be written as #rrggbb",
+                s
+            ))
+        };
+
+        Ok(Self::new(component(0)?, component(2)?, component(4)?))
+    }
+}
+
+impl Display for Color {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "#{:02x}{:02x}{:02x}", self.red, self.green, self.blue)
+    }
+}
+
+/// Checks that `order` contains every index below `len` exactly once
+fn check_permutation(order: &[usize], len: usize) -> Result<()> {
+    ensure!(
+        order.len() == len,
+        "Invalid palette order, expected {} indices, found {}",
+        len,
+        order.len()
+    );
+
+    let mut seen = vec![false; len];
+    for &index in order {
+        ensure!(
+            index < len && !seen[index],
+            "Invalid palette order, each index below {} should appear exactly once",
+            len
+        );
+        seen[index] = true;
+    }
+
+    Ok(())
+}
+
+/// The palette of an image, stored in the PLTE chunk
+#[derive(Debug, Clone, PartialEq)]
+pub struct Palette {
+    entries: Vec<Color>,
+}
+
+impl Palette {
+    pub const MAX_ENTRIES: usize = 256;
+
+    pub fn new(entries: Vec<Color>) -> Result<Self> {
+        ensure!(
+            !entries.is_empty() && entries.len() <= Self::MAX_ENTRIES,
+            "Invalid palette with {} entries, it should have between 1 and {} entries",
+            entries.len(),
+            Self::MAX_ENTRIES
+        );
+
+        Ok(Self { entries })
+    }
+
+    pub fn entries(&self) -> &[Color] {
+        &self.entries
+    }
+
+    pub fn len(&self) -> usize {
+        self.entries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.entries.is_empty()
+    }
+
+    pub fn get(&self, index: usize) -> Option<Color> {
+        self.entries.get(index).copied()
+    }
+
+    pub fn set_color(&mut self, index: usize, color: Color) -> Result<()> {
+        let len = self.entries.len();
+        let entry = self.entries.get_mut(index).context(format!(
+            "Invalid palette index {}, the palette has {} entries",
+            index, len
+        ))?;
+        *entry = color;
+
+        Ok(())
+    }
+
+    /// Reorders the entries so that the entry at `order[i]` moves to index `i`. Pixel indices in
+    /// the image data are not remapped.
+    pub fn reorder(&mut self, order: &[usize]) -> Result<()> {
+        check_permutation(order, self.entries.len())?;
+        self.entries = order.iter().map(|&index| self.entries[index]).collect();
+
+        Ok(())
+    }
+
+    /// Checks if the palette is allowed for the image, and if all entries can be referenced by
+    /// the pixels of indexed images
+    pub fn validate(&self, ihdr: &Ihdr) -> Result<()> {
+        match ihdr.color_type() {
+            ColorType::Grayscale | ColorType::GrayscaleAlpha => bail!(
+                "A PLTE chunk is not allowed for the {} color type",
+                ihdr.color_type()
+            ),
+            ColorType::Indexed => ensure!(
+                self.entries.len() <= 1 << ihdr.bit_depth(),
+                "Invalid palette with {} entries, a {}-bit image can only index {} entries",
+                self.entries.len(),
+                ihdr.bit_depth(),
+                1 << ihdr.bit_depth()
+            ),
+            // The palette of truecolor images is only a suggestion for viewers with fewer colors
+            ColorType::Rgb | ColorType::Rgba => {}
+        }
+
+        Ok(())
+    }
+
+    pub fn as_chunk(&self) -> Chunk {
+        let data = self
+            .entries
+            .iter()
+            .flat_map(|color| [color.red, color.green, color.blue])
+            .collect();
+
+        // Since "PLTE" is a valid chunk type, this should not fail
+        let chunk_type = ChunkType::try_from(*b"PLTE").expect("PLTE is a valid chunk type");
+        Chunk::new(chunk_type, data)
+    }
+}
+
+impl TryFrom<&[u8]> for Palette {
+    type Error = Error;
+
+    fn try_from(data: &[u8]) -> Result<Self, Self::Error> {
+        ensure!(
+            data.len().is_multiple_of(3),
+            "Invalid PLTE chunk, its length {} is not a multiple of 3",
+            data.len()
+        );
+
+        Self::new(
+            data.chunks_exact(3)
+                .map(|rgb| Color::new(rgb[0], rgb[1], rgb[2]))
+                .collect(),
+        )
+    }
+}
+
+impl TryFrom<&Chunk> for Palette {
+    type Error = Error;
+
+    fn try_from(chunk: &Chunk) -> Result<Self, Self::Error> {
+        ensure!(
+            chunk.chunk_type().bytes() == *b"PLTE",
+            "Expected a PLTE chunk, found \"{}\"",
+            chunk.chunk_type()
+        );
+
+        Self::try_from(&chunk.data()[..])
+    }
+}
+
+/// Removes trailing opaque alpha values, which are implied for entries without an alpha value
+fn trim_opaque(alphas: &mut Vec<u8>) {
+    while alphas.last() == Some(&255) {
+        alphas.pop();
+    }
+}
+
+/// The transparency of an image, stored in the tRNS chunk
+#[derive(Debug, Clone, PartialEq)]
+pub enum Transparency {
+    /// Alpha values of the first palette entries. Entries without an alpha value are opaque.
+    Indexed(Vec<u8>),
+
+    /// Gray level of the pixels that are fully transparent
+    Grayscale(u16),
+
+    /// Color of the pixels that are fully transparent
+    Rgb(u16, u16, u16),
+}
+
+impl Transparency {
+    /// Parses the data of a tRNS chunk, whose layout depends on the color type of the image
+    pub fn parse(data: &[u8], ihdr: &Ihdr) -> Result<Self> {
+        let sample = |i: usize| u16::from_be_bytes([data[i], data[i + 1]]);
+
+        let transparency = match ihdr.color_type() {
+            ColorType::Indexed => Transparency::Indexed(data.to_vec()),
+            ColorType::Grayscale => {
+                ensure!(
+                    data.len() == 2,
+                    "Invalid tRNS chunk, it should have 2 bytes for grayscale images"
+                );
+                Transparency::Grayscale(sample(0))
+            }
+            ColorType::Rgb => {
+                ensure!(
+                    data.len() == 6,
+                    "Invalid tRNS chunk, it should have 6 bytes for RGB images"
+                );
+                Transparency::Rgb(sample(0), sample(2), sample(4))
+            }
+            ColorType::GrayscaleAlpha | ColorType::Rgba => bail!(
+                "A tRNS chunk is not allowed for the {} color type",
+                ihdr.color_type()
+            ),
+        };
+
+        Ok(transparency)
+    }
+
+    /// Checks if the transparency matches the color type and bit depth of the image, and for
+    /// indexed images, if there are no more alpha values than palette entries
+    pub fn validate(&self, ihdr: &Ihdr, palette: Option<&Palette>) -> Result<()> {
+        let max_sample = (1u32 << ihdr.bit_depth()) - 1;
+
+        match (self, ihdr.color_type()) {
+            (Transparency::Indexed(alphas), ColorType::Indexed) => {
+                let palette_len = palette.map_or(0, |palette| palette.len());
+                ensure!(
+                    alphas.len() <= palette_len,
+                    "Invalid tRNS chunk with {} alpha values, the palette only has {} entries",
+                    alphas.len(),
+                    palette_len
+                );
+            }
+            (Transparency::Grayscale(gray), ColorType::Grayscale) => ensure!(
+                *gray as u32 <= max_sample,
+                "Invalid transparent gray level {} for a {}-bit image",
+                gray,
+                ihdr.bit_depth()
+            ),
+            (Transparency::Rgb(red, green, blue), ColorType::Rgb) => ensure!(
+                [red, green, blue]
+                    .iter()
+                    .all(|sample| **sample as u32 <= max_sample),
+                "Invalid transparent color ({}, {}, {}) for a {}-bit image",
+                red,
+                green,
+                blue,
+                ihdr.bit_depth()
+            ),
+            _ => bail!(
+                "The tRNS chunk doesn't match the {} color type",
+                ihdr.color_type()
+            ),
+        }
+
+        Ok(())
+    }
+
+    /// Alpha value of a palette entry, 255 (opaque) if it doesn't have one
+    pub fn alpha(&self, index: usize) -> Option<u8> {
+        match self {
+            Transparency::Indexed(alphas) => Some(alphas.get(index).copied().unwrap_or(255)),
+            _ => None,
+        }
+    }
+
+    /// Sets the alpha value of a palette entry. Entries before it without an alpha value are
+    /// made explicitly opaque, and trailing opaque entries are dropped to keep the chunk short.
+    pub fn set_alpha(&mut self, index: usize, alpha: u8) -> Result<()> {
+        let Transparency::Indexed(alphas) = self else {
+            bail!("Alpha values can only be set on the transparency of indexed images");
+        };
+
+        if index >= alphas.len() {
+            alphas.resize(index + 1, 255);
+        }
+        alphas[index] = alpha;
+        trim_opaque(alphas);
+
+        Ok(())
+    }
+
+    /// Reorders the alpha values like [Palette::reorder], for a palette with `palette_len`
+    /// entries. Does nothing for images that aren't indexed.
+    pub fn reorder(&mut self, order: &[usize], palette_len: usize) -> Result<()> {
+        let Transparency::Indexed(alphas) = self else {
+            return Ok(());
+        };
+
+        check_permutation(order, palette_len)?;
+
+        *alphas = order
+            .iter()
+            .map(|&index| alphas.get(index).copied().unwrap_or(255))
+            .collect();
+        trim_opaque(alphas);
+
+        Ok(())
+    }
+
+    pub fn as_chunk(&self) -> Chunk {
+        let data = match self {
+            Transparency::Indexed(alphas) => alphas.clone(),
+            Transparency::Grayscale(gray) => gray.to_be_bytes().to_vec(),
+            Transparency::Rgb(red, green, blue) => [red, green, blue]
+                .iter()
+                .flat_map(|sample| sample.to_be_bytes())
+                .collect(),
+        };
+
+        // Since "tRNS" is a valid chunk type, this should not fail
+        let chunk_type = ChunkType::try_from(*b"tRNS").expect("tRNS is a valid chunk type");
+        Chunk::new(chunk_type, data)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn testing_palette() -> Palette {
+        Palette::new(vec![
+            Color::new(255, 0, 0),
+            Color::new(0, 255, 0),
+            Color::new(0, 0, 255),
+        ])
+        .unwrap()
+    }
+
+    #[test]
+    fn test_color_from_str() {
+        let color = Color::from_str("#ff8000").unwrap();
+
+        assert_eq!(color, Color::new(255, 128, 0));
+        assert_eq!(color.to_string(), "#ff8000");
+        assert!(Color::from_str("ff8000").is_err());
+        assert!(Color::from_str("#ff80").is_err());
+        assert!(Color::from_str("#gg8000").is_err());
+    }
+
+    #[test]
+    fn test_palette_chunk_round_trip() {
+        let palette = testing_palette();
+        let chunk = palette.as_chunk();
+
+        assert_eq!(chunk.data(), &[255, 0, 0, 0, 255, 0, 0, 0, 255]);
+        assert_eq!(Palette::try_from(&chunk).unwrap(), palette);
+        assert!(Palette::try_from(&[1, 2, 3, 4][..]).is_err());
+        assert!(Palette::try_from(&[][..]).is_err());
+    }
+
+    #[test]
+    fn test_palette_validate() {
+        let palette = testing_palette();
+
+        let ihdr = Ihdr::new(1, 1, 2, ColorType::Indexed, false).unwrap();
+        assert!(palette.validate(&ihdr).is_ok());
+
+        let ihdr = Ihdr::new(1, 1, 1, ColorType::Indexed, false).unwrap();
+        assert!(palette.validate(&ihdr).is_err());
+
+        let ihdr = Ihdr::new(1, 1, 8, ColorType::Grayscale, false).unwrap();
+        assert!(palette.validate(&ihdr).is_err());
+
+        let ihdr = Ihdr::new(1, 1, 8, ColorType::Rgb, false).unwrap();
+        assert!(palette.validate(&ihdr).is_ok());
+    }
+
+    #[test]
+    fn test_palette_edits() {
+        let mut palette = testing_palette();
+
+        palette.set_color(1, Color::new(1, 2, 3)).unwrap();
+        assert!(palette.set_color(3, Color::new(1, 2, 3)).is_err());
+
+        palette.reorder(&[2, 0, 1]).unwrap();
+        assert_eq!(
+            palette.entries(),
+            &[
+                Color::new(0, 0, 255),
+                Color::new(255, 0, 0),
+                Color::new(1, 2, 3)
+            ]
+        );
+
+        assert!(palette.reorder(&[0, 1]).is_err());
+        assert!(palette.reorder(&[0, 1, 1]).is_err());
+    }
+
+    #[test]
+    fn test_transparency_parse() {
+        let ihdr = Ihdr::new(1, 1, 8, ColorType::Indexed, false).unwrap();
+        assert_eq!(
+            Transparency::parse(&[0, 128], &ihdr).unwrap(),
+            Transparency::Indexed(vec![0, 128])
+        );
+
+        let ihdr = Ihdr::new(1, 1, 16, ColorType::Rgb, false).unwrap();
+        let transparency = Transparency::parse(&[1, 0, 0, 2, 0, 3], &ihdr).unwrap();
+        assert_eq!(transparency, Transparency::Rgb(256, 2, 3));
+        assert_eq!(transparency.as_chunk().data(), &[1, 0, 0, 2, 0, 3]);
+        assert!(Transparency::parse(&[1, 0], &ihdr).is_err());
+
+        let ihdr = Ihdr::new(1, 1, 8, ColorType::Rgba, false).unwrap();
+        assert!(Transparency::parse(&[1, 0], &ihdr).is_err());
+    }
+
+    #[test]
+    fn test_transparency_validate() {
+        let palette = testing_palette();
+        let ihdr = Ihdr::new(1, 1, 8, ColorType::Indexed, false).unwrap();
+
+        assert!(Transparency::Indexed(vec![0, 0, 0])
+            .validate(&ihdr, Some(&palette))
+            .is_ok());
+        assert!(Transparency::Indexed(vec![0, 0, 0, 0])
+            .validate(&ihdr, Some(&palette))
+            .is_err());
+        assert!(Transparency::Indexed(vec![0])
+            .validate(&ihdr, None)
+            .is_err());
+
+        let ihdr = Ihdr::new(1, 1, 4, ColorType::Grayscale, false).unwrap();
+        assert!(Transparency::Grayscale(15).validate(&ihdr, None).is_ok());
+        assert!(Transparency::Grayscale(16).validate(&ihdr, None).is_err());
+        assert!(Transparency::Rgb(0, 0, 0).validate(&ihdr, None).is_err());
+    }
+
+    #[test]
+    fn test_transparency_edits() {
+        let mut transparency = Transparency::Indexed(vec![]);
+
+        transparency.set_alpha(2, 0).unwrap();
+        assert_eq!(transparency, Transparency::Indexed(vec![255, 255, 0]));
+        assert_eq!(transparency.alpha(1), Some(255));
+        assert_eq!(transparency.alpha(7), Some(255));
+
+        transparency.reorder(&[2, 0, 1], 3).unwrap();
+        assert_eq!(transparency, Transparency::Indexed(vec![0]));
+
+        transparency.set_alpha(0, 255).unwrap();
+        assert_eq!(transparency, Transparency::Indexed(vec![]));
+
+        assert!(Transparency::Grayscale(0).set_alpha(0, 0).is_err());
+    }
+}
@@ -0,0 +1,356 @@
+//! Hides messages in the order of the palette entries of indexed images.
+//!
+//! A palette with `n` distinct entries can be arranged in `n!` orders, so the order alone can
+//! store `floor(log2(n!))` bits. The message is read as a number, which picks one of the orders
+//! through its factorial number system digits (Lehmer code), relative to the entries sorted by
+//! color and alpha. The pixel indices are remapped to follow their entries, so the visible image
+//! doesn't change and no chunk is added.
+
+use anyhow::{bail, ensure, Context, Result};
+
+use crate::{
+    image_data::EncodeOptions,
+    palette::{Color, Palette, Transparency},
+    png::Png,
+};
+
+/// Multiplies a big-endian number by `factor` and adds `addend`, growing it as needed
+fn mul_add(number: &mut Vec<u8>, factor: u32, addend: u32) {
+    let mut carry = addend;
+    for byte in number.iter_mut().rev() {
+        let value = *byte as u32 * factor + carry;
+        *byte = value as u8;
+        carry = value >> 8;
+    }
+
+    while carry > 0 {
+        number.insert(0, carry as u8);
+        carry >>= 8;
+    }
+}
+
+/// Divides a big-endian number by `divisor` in place, returning the remainder
+fn div_rem(number: &mut [u8], divisor: u32) -> u32 {
+    let mut remainder = 0;
+    for byte in number.iter_mut() {
+        let value = (remainder << 8) | *byte as u32;
+        *byte = (value / divisor) as u8;
+        remainder = value % divisor;
+    }
+
+    remainder
+}
+
+/// Number of bits that can be stored in the order of `palette_len` distinct entries, which is
+/// `floor(log2(palette_len!))`
+pub fn capacity_bits(palette_len: usize) -> usize {
+    let mut factorial = vec![1u8];
+    for k in 2..=palette_len as u32 {
+        mul_add(&mut factorial, k, 0);
+    }
+
+    // Since `factorial` is at least 1 and only grows with non-zero leading bytes, the first
+    // byte is never zero
+    let bit_length = factorial.len() * 8 - factorial[0].leading_zeros() as usize;
+    bit_length - 1
+}
+
+/// Number of message bytes that can be hidden in a palette of `palette_len` distinct entries,
+/// after the byte storing the message length
+pub fn capacity(palette_len: usize) -> usize {
+    (capacity_bits(palette_len) / 8).saturating_sub(1)
+}
+
+/// Returns each palette entry with its alpha value, checking that no two are the same, since
+/// swapping identical entries can't store anything
+fn entries(palette: &Palette, transparency: Option<&Transparency>) -> Result<Vec<(Color, u8)>> {
+    let entries: Vec<(Color, u8)> = palette
+        .entries()
+        .iter()
+        .enumerate()
+        .map(|(index, color)| {
+            let alpha = transparency.and_then(|t| t.alpha(index)).unwrap_or(255);
+            (*color, alpha)
+        })
+        .collect();
+
+    let mut sorted = entries.clone();
+    sorted.sort_by_key(|(color, alpha)| (color.red, color.green, color.blue, *alpha));
+    ensure!(
+        sorted.windows(2).all(|pair| pair[0] != pair[1]),
+        "The palette has duplicate entries, which can't store a message"
+    );
+
+    Ok(entries)
+}
+
+fn palette_of(png: &Png) -> Result<(Palette, Option<Transparency>)> {
+    let palette = png
+        .palette()?
+        .context("The image has no PLTE chunk, only indexed images can hide a message in it")?;
+
+    Ok((palette, png.transparency()?))
+}
+
+/// Hides `message` by reordering the palette of an indexed image, re-encoding the image data
+/// with `options`
+pub fn hide(png: &mut Png, message: &[u8], options: &EncodeOptions) -> Result<()> {
+    let (palette, transparency) = palette_of(png)?;
+    let entries = entries(&palette, transparency.as_ref())?;
+
+    let capacity = capacity(entries.len());
+    ensure!(
+        message.len() <= capacity,
+        "The message has {} bytes, but the palette of {} entries can only hide {} bytes",
+        message.len(),
+        entries.len(),
+        capacity
+    );
+
+    // The number stored is the message length followed by the message, padded with zeros to
+    // fill the capacity
+    let mut number = vec![0u8; capacity + 1];
+    number[0] = message.len() as u8;
+    number[1..1 + message.len()].copy_from_slice(message);
+
+    // Digits of the factorial number system, from the least significant one
+    let mut digits: Vec<usize> = (1..=entries.len() as u32)
+        .map(|radix| div_rem(&mut number, radix) as usize)
+        .collect();
+    digits.reverse();
+
+    let mut remaining: Vec<usize> = (0..entries.len()).collect();
+    remaining.sort_by_key(|&index| {
+        let (color, alpha) = entries[index];
+        (color.red, color.green, color.blue, alpha)
+    });
+
+    let order: Vec<usize> = digits
+        .into_iter()
+        .map(|digit| remaining.remove(digit))
+        .collect();
+
+    png.reorder_palette(&order, options)
+}
+
+/// Reads a message hidden with [hide] from the palette order of an indexed image
+pub fn reveal(png: &Png) -> Result<Vec<u8>> {
+    let (palette, transparency) = palette_of(png)?;
+    let entries = entries(&palette, transparency.as_ref())?;
+
+    let mut remaining = entries.clone();
+    remaining.sort_by_key(|(color, alpha)| (color.red, color.green, color.blue, *alpha));
+
+    let mut number = vec![0u8];
+    for (i, entry) in entries.iter().enumerate() {
+        // Since all entries are distinct and come from the palette, the entry is always found
+        let digit = remaining
+            .iter()
+            .position(|e| e == entry)
+            .context("Palette entry not found")?;
+        remaining.remove(digit);
+        mul_add(&mut number, (entries.len() - i) as u32, digit as u32);
+    }
+
+    let capacity = capacity(entries.len());
+    while number.len() > 1 && number[0] == 0 {
+        number.remove(0);
+    }
+    if number.len() > capacity + 1 {
+        bail!("The palette order doesn't hide a message");
+    }
+
+    let mut bytes = vec![0u8; capacity + 1 - number.len()];
+    bytes.extend(number);
+
+    let length = bytes[0] as usize;
+    ensure!(
+        length <= capacity && bytes[1 + length..].iter().all(|b| *b == 0),
+        "The palette order doesn't hide a message"
+    );
+
+    Ok(bytes[1..1 + length].to_vec())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        chunk::Chunk,
+        chunk_type::ChunkType,
+        filter::{FilterStrategy, FilterType},
+        ihdr::{ColorType, Ihdr},
+        image_data::RawImage,
+    };
+    use std::str::FromStr;
+
+    fn indexed_png(palette_len: usize) -> Png {
+        let ihdr = Ihdr::new(16, 16, 8, ColorType::Indexed, false).unwrap();
+        let idat = Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![]);
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]);
+        let mut png = Png::from_chunks(vec![ihdr.as_chunk(), idat, iend]);
+
+        let colors = (0..palette_len)
+            .map(|i| Color::new(i as u8, 255 - i as u8, (i * 7) as u8))
+            .collect();
+        png.set_palette(&Palette::new(colors).unwrap()).unwrap();
+        png.set_transparency(&Transparency::Indexed(vec![0, 128]))
+            .unwrap();
+
+        let rows = (0..16)
+            .map(|y| {
+                (0..16)
+                    .map(|x| ((x + y * 16) % palette_len) as u8)
+                    .collect()
+            })
+            .collect();
+        let image = RawImage::new(ihdr, rows).unwrap();
+        png.set_image_data(&image, &options()).unwrap();
+
+        png
+    }
+
+    fn options() -> EncodeOptions {
+        EncodeOptions {
+            filter_strategy: FilterStrategy::Fixed(FilterType::None),
+            ..EncodeOptions::default()
+        }
+    }
+
+    /// Colors and alpha values of every pixel
+    fn pixels(png: &Png) -> Vec<(Color, u8)> {
+        let palette = png.palette().unwrap().unwrap();
+        let transparency = png.transparency().unwrap();
+        let image = png.image_data().unwrap().unfilter();
+
+        (0..16)
+            .flat_map(|y| (0..16).map(move |x| (x, y)))
+            .map(|(x, y)| {
+                let index = image.sample(x, y, 0).unwrap() as usize;
+                let alpha = transparency.as_ref().and_then(|t| t.alpha(index));
+                (palette.get(index).unwrap(), alpha.unwrap_or(255))
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_capacity() {
+        assert_eq!(capacity_bits(1), 0);
+        assert_eq!(capacity_bits(2), 1);
+        // 10! = 3628800, between 2^21 and 2^22
+        assert_eq!(capacity_bits(10), 21);
+        assert_eq!(capacity_bits(256), 1683);
+
+        assert_eq!(capacity(10), 1);
+        assert_eq!(capacity(256), 209);
+    }
+
+    #[test]
+    fn test_hide_and_reveal() {
+        let mut png = indexed_png(40);
+        let original_pixels = pixels(&png);
+        let message = b"palette secret";
+
+        hide(&mut png, message, &options()).unwrap();
+
+        assert_eq!(reveal(&png).unwrap(), message);
+        assert_eq!(pixels(&png), original_pixels);
+        assert_eq!(png.chunks().len(), 5);
+        assert_ne!(png.palette().unwrap(), indexed_png(40).palette().unwrap());
+    }
+
+    #[test]
+    fn test_hide_empty_and_full_messages() {
+        let mut png = indexed_png(30);
+        let message = vec![0xa5; capacity(30)];
+
+        hide(&mut png, &message, &options()).unwrap();
+        assert_eq!(reveal(&png).unwrap(), message);
+
+        hide(&mut png, b"", &options()).unwrap();
+        assert_eq!(reveal(&png).unwrap(), b"");
+
+        let message = vec![0xa5; capacity(30) + 1];
+        assert!(hide(&mut png, &message, &options()).is_err());
+    }
+
+    #[test]
+    fn test_duplicate_entries() {
+        let mut png = indexed_png(20);
+        let mut palette = png.palette().unwrap().unwrap();
+        palette.set_color(5, palette.get(6).unwrap()).unwrap();
+        png.set_palette(&palette).unwrap();
+
+        assert!(hide(&mut png, b"hi", &options()).is_err());
+        assert!(reveal(&png).is_err());
+    }
+
+    /// Background color and the histogram count of each palette color
+    fn background_and_histogram(png: &Png) -> (Color, Vec<(Color, u16)>) {
+        let palette = png.palette().unwrap().unwrap();
+        let background = png.chunk_by_type("bKGD").unwrap().data()[0];
+        let mut histogram: Vec<(Color, u16)> = png
+            .chunk_by_type("hIST")
+            .unwrap()
+            .data()
+            .chunks_exact(2)
+            .enumerate()
+            .map(|(index, count)| {
+                let color = palette.get(index).unwrap();
+                (color, u16::from_be_bytes([count[0], count[1]]))
+            })
+            .collect();
+        histogram.sort_by_key(|(color, _)| (color.red, color.green, color.blue));
+
+        (palette.get(background as usize).unwrap(), histogram)
+    }
+
+    #[test]
+    fn test_hide_remaps_background_and_histogram() {
+        let mut png = indexed_png(10);
+        let idat = png
+            .chunks()
+            .iter()
+            .position(|chunk| chunk.chunk_type().bytes() == *b"IDAT")
+            .unwrap();
+        let histogram = (0..10u16).flat_map(|i| (i * 100).to_be_bytes()).collect();
+        png.insert_chunk(
+            idat,
+            Chunk::new(ChunkType::from_str("hIST").unwrap(), histogram),
+        );
+        png.insert_chunk(
+            idat,
+            Chunk::new(ChunkType::from_str("bKGD").unwrap(), vec![4]),
+        );
+        let original = background_and_histogram(&png);
+
+        hide(&mut png, b"x", &options()).unwrap();
+
+        assert_eq!(reveal(&png).unwrap(), b"x");
+        // Entry 4 moved to index 5
+        assert_eq!(png.chunk_by_type("bKGD").unwrap().data(), &vec![5]);
+        assert_eq!(background_and_histogram(&png), original);
+    }
+
+    #[test]
+    fn test_hide_in_animated_image() {
+        let frames = [indexed_png(10), indexed_png(10)];
+        let mut png = crate::apng::build(&frames, 1, 10, 0).unwrap();
+        let bytes = png.as_bytes();
+
+        let error = hide(&mut png, b"x", &options()).unwrap_err();
+        assert!(error.to_string().contains("animated"));
+        assert_eq!(png.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_big_number_helpers() {
+        let mut number = vec![0xff];
+        mul_add(&mut number, 256, 1);
+        assert_eq!(number, vec![0xff, 0x01]);
+
+        // 0xff01 = 65281 = 6528 * 10 + 1, and 6528 = 0x1980
+        assert_eq!(div_rem(&mut number, 10), 1);
+        assert_eq!(number, vec![0x19, 0x80]);
+    }
+}
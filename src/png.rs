@@ -1,31 +1,46 @@
 use anyhow::{bail, ensure, Context, Error, Result};
 use std::{
+    borrow::Cow,
     fmt::Display,
-    fs::File,
-    io::{self, BufReader, Read},
+    fs::{File, OpenOptions},
+    io::{Read, Seek, SeekFrom, Write},
     path::Path,
 };
 
-use crate::chunk::Chunk;
+use crate::{
+    apng::Animation,
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    container::{ChunkPosition, Container, PayloadOptions},
+    ihdr::{ColorType, Ihdr},
+    image_data::{EncodeOptions, ImageData, RawImage},
+    palette::{Palette, Transparency},
+    png_ref::PngRef,
+    selector::Selector,
+    signature::Signature,
+};
 
-#[derive(Debug)]
+/// A file made of chunks, like a PNG. MNG and JNG files share the chunk layout with a
+/// different [Signature], so they are loaded and edited the same way.
+#[derive(Debug, Clone)]
 pub struct Png {
-    header: [u8; 8],
+    signature: Signature,
     chunks: Vec<Chunk>,
 }
 
 impl Png {
-    const STANDARD_HEADER: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    pub const STANDARD_HEADER: [u8; 8] = *Signature::Png.bytes();
 
     pub fn from_chunks(chunks: Vec<Chunk>) -> Self {
-        Self {
-            header: Self::STANDARD_HEADER,
-            chunks,
-        }
+        Self::with_signature(Signature::Png, chunks)
+    }
+
+    pub fn with_signature(signature: Signature, chunks: Vec<Chunk>) -> Self {
+        Self { signature, chunks }
     }
 
     pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
-        let mut file = File::open(path).context(format!("Unable to open file"))?;
+        let mut file = File::open(path).context("Unable to open file")?;
 
         let mut bytes = Vec::new();
         file.read_to_end(&mut bytes)
@@ -36,63 +51,511 @@ impl Png {
         let mut header_bytes = [0u8; 8];
         // Since we make sure [bytes]'s length is at least 8, `clone_from_slice` should not panic
         header_bytes.clone_from_slice(&bytes[0..8]);
-
-        ensure!(
-            Self::is_png_header(header_bytes),
-            "Invalid PNG file, the file does not have a valid PNG header"
-        );
+        Self::check_signature(&header_bytes)?;
 
         Self::try_from(&bytes[..])
     }
 
+    /// Adds a chunk right before the IEND chunk of a PNG file (MEND for MNG files), without
+    /// reading or rewriting the rest of the file. The end chunk is overwritten by the new chunk,
+    /// followed by a new end chunk, extending the file.
+    ///
+    /// Only the header and the last chunk of the file are checked. Returns the number of bytes
+    /// written, or `None` if the file doesn't end with an end chunk, in which case the file is
+    /// left untouched. If writing fails, the file is truncated back to its original length and
+    /// its end chunk restored.
+    pub fn append_chunk_to_file<P: AsRef<Path>>(path: P, chunk: &Chunk) -> Result<Option<u64>> {
+        let mut file = OpenOptions::new()
+            .read(true)
+            .write(true)
+            .open(path)
+            .context("Unable to open file")?;
+
+        let mut header_bytes = [0u8; 8];
+        file.read_exact(&mut header_bytes)
+            .context("Invalid PNG file")?;
+        let signature = Self::check_signature(&header_bytes)?;
+
+        let iend = Self::end_chunk(signature).as_bytes();
+        let file_length = file.metadata().context("Unable to read file")?.len();
+
+        if file_length < (header_bytes.len() + iend.len()) as u64 {
+            return Ok(None);
+        }
+
+        let iend_offset = file
+            .seek(SeekFrom::End(-(iend.len() as i64)))
+            .context("Unable to read file")?;
+
+        let mut last_chunk = vec![0u8; iend.len()];
+        file.read_exact(&mut last_chunk)
+            .context("Unable to read file")?;
+
+        if last_chunk != iend {
+            return Ok(None);
+        }
+
+        let mut bytes = chunk.as_bytes();
+        bytes.extend(&iend);
+
+        file.seek(SeekFrom::Start(iend_offset))
+            .context("Unable to write file")?;
+
+        if let Err(e) = file.write_all(&bytes) {
+            // Best effort, the write error is the one worth reporting
+            let _ = file
+                .set_len(file_length)
+                .and_then(|_| file.seek(SeekFrom::Start(iend_offset)))
+                .and_then(|_| file.write_all(&iend));
+
+            return Err(e).context("Error writing image file");
+        }
+
+        Ok(Some(bytes.len() as u64))
+    }
+
+    fn end_chunk(signature: Signature) -> Chunk {
+        // Since "IEND" and "MEND" are valid chunk types, this should not fail
+        let chunk_type =
+            ChunkType::try_from(*signature.end_chunk_type()).expect("End chunk types are valid");
+        Chunk::new(chunk_type, Vec::new())
+    }
+
     pub fn append_chunk(&mut self, chunk: Chunk) {
         self.chunks.push(chunk);
     }
 
+    /// Inserts a chunk at `index`, shifting all chunks after it.
+    ///
+    /// Panics if `index` is greater than the number of chunks.
+    pub fn insert_chunk(&mut self, index: usize, chunk: Chunk) {
+        self.chunks.insert(index, chunk);
+    }
+
+    /// Removes and returns the chunk at `index`, shifting all chunks after it.
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn remove_chunk(&mut self, index: usize) -> Chunk {
+        self.chunks.remove(index)
+    }
+
+    /// Keeps only the chunks for which `f` returns true, preserving their order
+    pub fn retain<F: FnMut(&Chunk) -> bool>(&mut self, f: F) {
+        self.chunks.retain(f);
+    }
+
+    /// Replaces the chunk at `index`, keeping its position, and returns the old chunk.
+    ///
+    /// Panics if `index` is out of bounds.
+    pub fn replace_chunk(&mut self, index: usize, chunk: Chunk) -> Chunk {
+        std::mem::replace(&mut self.chunks[index], chunk)
+    }
+
     pub fn header(&self) -> &[u8; 8] {
-        &self.header
+        self.signature.bytes()
+    }
+
+    pub fn signature(&self) -> Signature {
+        self.signature
     }
 
     pub fn chunks(&self) -> &[Chunk] {
         &self.chunks[..]
     }
 
-    fn is_png_header(bytes: [u8; 8]) -> bool {
-        for i in [0..8] {
-            let matches = match (bytes.get(i.clone()), Png::STANDARD_HEADER.get(i)) {
-                (Some(a), Some(b)) => a == b,
-                _ => false,
-            };
+    /// Mutable access to the chunks. Chunk data edits through [Chunk::set_data] and
+    /// [Chunk::update_data] keep the length and CRC of the chunks up to date.
+    pub fn chunks_mut(&mut self) -> &mut [Chunk] {
+        &mut self.chunks[..]
+    }
 
-            if !matches {
-                return false;
-            }
-        }
+    pub fn into_chunks(self) -> Vec<Chunk> {
+        self.chunks
+    }
 
-        true
+    fn check_signature(bytes: &[u8; 8]) -> Result<Signature> {
+        Signature::from_bytes(bytes)
+            .context("Invalid PNG file, the file does not have a valid PNG, MNG or JNG signature")
     }
 
     pub fn chunk_by_type(&self, chunk_type: &str) -> Option<&Chunk> {
-        for chunk in self.chunks.iter() {
-            if chunk.chunk_type().to_string().eq(chunk_type) {
-                return Some(chunk);
-            }
-        }
-        None
+        self.chunks
+            .iter()
+            .find(|chunk| chunk.chunk_type().bytes() == chunk_type.as_bytes())
     }
 
     pub fn remove_first_chunk(&mut self, chunk_type_str: &str) -> Option<Chunk> {
         let pos = self
             .chunks
             .iter()
-            .position(|c| c.chunk_type().to_string() == chunk_type_str)?;
+            .position(|c| c.chunk_type().bytes() == chunk_type_str.as_bytes())?;
 
         Some(self.chunks.remove(pos))
     }
 
+    /// Iterates over the chunks of a chunk type, in order
+    pub fn iter_by_type<'a>(&'a self, chunk_type: &'a str) -> impl Iterator<Item = &'a Chunk> + 'a {
+        self.chunks
+            .iter()
+            .filter(move |chunk| chunk.chunk_type().bytes() == chunk_type.as_bytes())
+    }
+
+    /// Removes and returns all chunks of a chunk type
+    pub fn remove_all(&mut self, chunk_type: &str) -> Vec<Chunk> {
+        let mut removed = Vec::new();
+        while let Some(chunk) = self.remove_first_chunk(chunk_type) {
+            removed.push(chunk);
+        }
+        removed
+    }
+
+    /// Returns the chunks matching the selector, along with their index in the chunk list
+    pub fn select<'a>(
+        &'a self,
+        selector: &'a Selector,
+    ) -> impl Iterator<Item = (usize, &'a Chunk)> + 'a {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter(|(index, chunk)| selector.matches(*index, chunk))
+    }
+
+    /// Removes all chunks matching the selector. Indices in the selector refer to the positions
+    /// of the chunks before any of them is removed.
+    pub fn remove_selected(&mut self, selector: &Selector) -> Vec<Chunk> {
+        let mut removed = Vec::new();
+        let mut kept = Vec::new();
+
+        for (index, chunk) in std::mem::take(&mut self.chunks).into_iter().enumerate() {
+            if selector.matches(index, &chunk) {
+                removed.push(chunk);
+            } else {
+                kept.push(chunk);
+            }
+        }
+
+        self.chunks = kept;
+        removed
+    }
+
+    /// Parses the IHDR chunk, which should be the first chunk of the image
+    pub fn ihdr(&self) -> Result<Ihdr> {
+        let chunk = self
+            .chunks
+            .first()
+            .filter(|chunk| chunk.chunk_type().bytes() == *b"IHDR")
+            .context("Invalid PNG file, the first chunk should be IHDR")?;
+
+        Ihdr::try_from(chunk)
+    }
+
+    /// Parses the PLTE chunk, checking it against the IHDR chunk
+    pub fn palette(&self) -> Result<Option<Palette>> {
+        let Some(chunk) = self.chunk_by_type("PLTE") else {
+            return Ok(None);
+        };
+
+        let palette = Palette::try_from(chunk)?;
+        palette.validate(&self.ihdr()?)?;
+
+        Ok(Some(palette))
+    }
+
+    /// Parses the tRNS chunk, checking it against the IHDR and PLTE chunks
+    pub fn transparency(&self) -> Result<Option<Transparency>> {
+        let Some(chunk) = self.chunk_by_type("tRNS") else {
+            return Ok(None);
+        };
+
+        let ihdr = self.ihdr()?;
+        let transparency = Transparency::parse(chunk.data(), &ihdr)?;
+        transparency.validate(&ihdr, self.palette()?.as_ref())?;
+
+        Ok(Some(transparency))
+    }
+
+    /// Replaces the PLTE chunk, or adds one before the chunks that should follow it
+    pub fn set_palette(&mut self, palette: &Palette) -> Result<()> {
+        let ihdr = self.ihdr()?;
+        palette.validate(&ihdr)?;
+
+        if let Some(transparency) = self.transparency()? {
+            transparency
+                .validate(&ihdr, Some(palette))
+                .context("The new palette doesn't match the tRNS chunk")?;
+        }
+
+        self.replace_or_insert(palette.as_chunk(), &[b"tRNS", b"bKGD", b"hIST", b"IDAT"]);
+
+        Ok(())
+    }
+
+    /// Replaces the tRNS chunk, or adds one before the first IDAT chunk. When all palette
+    /// entries are opaque, the tRNS chunk is removed instead, since an empty one is not valid.
+    pub fn set_transparency(&mut self, transparency: &Transparency) -> Result<()> {
+        transparency.validate(&self.ihdr()?, self.palette()?.as_ref())?;
+
+        match transparency {
+            Transparency::Indexed(alphas) if alphas.is_empty() => {
+                self.remove_first_chunk("tRNS");
+            }
+            _ => self.replace_or_insert(transparency.as_chunk(), &[b"IDAT"]),
+        }
+
+        Ok(())
+    }
+
+    /// Reorders the palette entries of an indexed image so that the entry at `order[i]` moves
+    /// to index `i`. The tRNS and hIST chunks are reordered too, and the bKGD index and the
+    /// pixel indices of the image data are remapped, so the image looks the same.
+    ///
+    /// Animated images are refused, since the frames stored in fdAT chunks would keep their old
+    /// indices.
+    pub fn reorder_palette(&mut self, order: &[usize], options: &EncodeOptions) -> Result<()> {
+        let ihdr = self.ihdr()?;
+        ensure!(
+            ihdr.color_type() == ColorType::Indexed,
+            "Only the palette of indexed images can be reordered"
+        );
+        ensure!(
+            self.animation()?.is_none(),
+            "The palette of animated images can't be reordered"
+        );
+
+        let mut palette = self.palette()?.context("The image has no PLTE chunk")?;
+        let mut transparency = self.transparency()?;
+        let mut image = self.image_data()?.unfilter();
+
+        palette.reorder(order)?;
+        if let Some(transparency) = transparency.as_mut() {
+            transparency.reorder(order, order.len())?;
+        }
+
+        // Maps the old index of each entry to its new index
+        let mut new_indices = vec![0u16; order.len()];
+        for (new_index, &old_index) in order.iter().enumerate() {
+            new_indices[old_index] = new_index as u16;
+        }
+
+        for y in 0..ihdr.height() {
+            for x in 0..ihdr.width() {
+                // Since (x, y) is within the image, the sample should exist
+                let index = image.sample(x, y, 0).unwrap_or(0);
+                let new_index = new_indices.get(index as usize).context(format!(
+                    "Invalid pixel index {}, the palette only has {} entries",
+                    index,
+                    order.len()
+                ))?;
+                image.set_sample(x, y, 0, *new_index)?;
+            }
+        }
+
+        let background = match self.chunk_by_type("bKGD") {
+            Some(chunk) => {
+                let index = match chunk.data()[..] {
+                    [index] => index,
+                    _ => bail!("Invalid bKGD chunk, it should hold a single palette index"),
+                };
+                let new_index = new_indices.get(index as usize).context(format!(
+                    "Invalid background index {}, the palette only has {} entries",
+                    index,
+                    order.len()
+                ))?;
+                Some(Chunk::new(*chunk.chunk_type(), vec![*new_index as u8]))
+            }
+            None => None,
+        };
+
+        let histogram = match self.chunk_by_type("hIST") {
+            Some(chunk) => {
+                ensure!(
+                    chunk.data().len() == order.len() * 2,
+                    "Invalid hIST chunk, it should have one 2-byte entry per palette entry"
+                );
+                let data = order
+                    .iter()
+                    .flat_map(|&index| [chunk.data()[index * 2], chunk.data()[index * 2 + 1]])
+                    .collect();
+                Some(Chunk::new(*chunk.chunk_type(), data))
+            }
+            None => None,
+        };
+
+        self.replace_or_insert(palette.as_chunk(), &[b"IDAT"]);
+        if let Some(transparency) = transparency {
+            self.set_transparency(&transparency)?;
+        }
+        for chunk in [background, histogram].into_iter().flatten() {
+            self.replace_or_insert(chunk, &[b"IDAT"]);
+        }
+        self.set_image_data(&image, options)
+    }
+
+    /// Replaces the first chunk of the same type as `chunk`, or inserts it before the first
+    /// chunk of one of the `before` chunk types (before IEND if there are none)
+    fn replace_or_insert(&mut self, chunk: Chunk, before: &[&[u8; 4]]) {
+        let chunk_type = chunk.chunk_type().bytes();
+        if let Some(existing) = self
+            .chunks
+            .iter_mut()
+            .find(|c| c.chunk_type().bytes() == chunk_type)
+        {
+            *existing = chunk;
+            return;
+        }
+
+        let index = self
+            .chunks
+            .iter()
+            .position(|c| {
+                let bytes = c.chunk_type().bytes();
+                before.contains(&&bytes) || bytes == *b"IEND"
+            })
+            .unwrap_or(self.chunks.len());
+
+        self.chunks.insert(index, chunk);
+    }
+
+    /// Parses the animation chunks of an APNG, or returns `None` if the image isn't animated
+    pub fn animation(&self) -> Result<Option<Animation>> {
+        Animation::parse(&self.chunks, &self.ihdr()?)
+    }
+
+    /// Converts a position into an index in the chunk list. The start and end are relative to
+    /// the header and end chunks of the format, like IHDR and IEND for PNG or MHDR and MEND for
+    /// MNG.
+    pub fn position_index(&self, position: ChunkPosition) -> Result<usize> {
+        let chunks = &self.chunks;
+
+        let index = match position {
+            ChunkPosition::Start => match chunks.first() {
+                Some(chunk)
+                    if chunk.chunk_type().bytes() == *self.signature.header_chunk_type() =>
+                {
+                    1
+                }
+                _ => 0,
+            },
+            ChunkPosition::End => match chunks.last() {
+                Some(chunk) if chunk.chunk_type().bytes() == *self.signature.end_chunk_type() => {
+                    chunks.len() - 1
+                }
+                _ => chunks.len(),
+            },
+            ChunkPosition::Index(index) => {
+                ensure!(
+                    index <= chunks.len(),
+                    "Invalid position {}, the image only has {} chunk(s)",
+                    index,
+                    chunks.len()
+                );
+                index
+            }
+        };
+
+        Ok(index)
+    }
+
+    /// Returns the closest index at or before `index` where a chunk can be inserted without
+    /// splitting image data: an APNG frame (a fcTL chunk and its IDAT or fdAT chunks) or a run
+    /// of IDAT chunks.
+    pub fn frame_boundary(&self, index: usize) -> usize {
+        let is_data = |chunk: &Chunk| self.signature.is_image_data(chunk.chunk_type());
+
+        let mut start = 0;
+        while start < self.chunks.len() {
+            if self.chunks[start].chunk_type().bytes() != *b"fcTL" && !is_data(&self.chunks[start])
+            {
+                start += 1;
+                continue;
+            }
+
+            let mut end = start + 1;
+            while end < self.chunks.len() && is_data(&self.chunks[end]) {
+                end += 1;
+            }
+
+            if start < index && index < end {
+                return start;
+            }
+            start = end;
+        }
+
+        index
+    }
+
+    /// Concatenates the data of all IDAT chunks and inflates it into filtered scanlines
+    pub fn image_data(&self) -> Result<ImageData> {
+        let ihdr = self.ihdr()?;
+
+        let mut compressed = Vec::new();
+        for chunk in self.iter_by_type("IDAT") {
+            compressed.extend_from_slice(chunk.data());
+        }
+
+        ensure!(!compressed.is_empty(), "The image has no IDAT chunks");
+
+        ImageData::decode(ihdr, &compressed)
+    }
+
+    /// Filters, compresses and stores the image in new IDAT chunks, placed where the first
+    /// IDAT chunk was. The IHDR chunk is rewritten too, since the image may change the interlace
+    /// method, except for animated images whose frames in fdAT chunks use the interlace method
+    /// of the IHDR chunk. All other chunks are kept as they are.
+    pub fn set_image_data(&mut self, image: &RawImage, options: &EncodeOptions) -> Result<()> {
+        let ihdr = self.ihdr()?;
+        ensure!(
+            ihdr.width() == image.ihdr().width() && ihdr.height() == image.ihdr().height(),
+            "The image data is {}x{}, but the image is {}x{}",
+            image.ihdr().width(),
+            image.ihdr().height(),
+            ihdr.width(),
+            ihdr.height()
+        );
+        ensure!(
+            options.idat_size != Some(0),
+            "Invalid IDAT chunk size, it should not be zero"
+        );
+        if image.ihdr().is_interlaced() != ihdr.is_interlaced() {
+            ensure!(
+                self.animation()?.is_none(),
+                "The interlace method of animated images can't be changed, the frames would keep \
+                 the old one"
+            );
+        }
+
+        let compressed = image
+            .filter(options.filter_strategy)
+            .encode(options.compression_level)?;
+        let idat_size = options.idat_size.unwrap_or(compressed.len());
+
+        let index = self
+            .chunks
+            .iter()
+            .position(|chunk| chunk.chunk_type().bytes() == *b"IDAT")
+            .or_else(|| {
+                self.chunks
+                    .iter()
+                    .position(|chunk| chunk.chunk_type().bytes() == *b"IEND")
+            })
+            .unwrap_or(self.chunks.len());
+
+        self.chunks[0] = image.ihdr().as_chunk();
+        self.retain(|chunk| chunk.chunk_type().bytes() != *b"IDAT");
+
+        // Since "IDAT" is a valid chunk type, this should not fail
+        let chunk_type = ChunkType::try_from(*b"IDAT").expect("IDAT is a valid chunk type");
+        let idat_chunks = compressed
+            .chunks(idat_size)
+            .map(|data| Chunk::new(chunk_type, data.to_vec()));
+        self.chunks.splice(index..index, idat_chunks);
+
+        Ok(())
+    }
+
     pub fn as_bytes(&self) -> Vec<u8> {
         let mut bytes = Vec::new();
-        bytes.extend(self.header);
+        bytes.extend(self.header());
 
         for chunk in self.chunks.iter() {
             bytes.extend(chunk.as_bytes())
@@ -106,53 +569,75 @@ impl TryFrom<&[u8]> for Png {
     type Error = Error;
 
     fn try_from(value: &[u8]) -> Result<Self, Self::Error> {
-        let mut reader = BufReader::new(value);
-        let mut chunks: Vec<Chunk> = Vec::new();
-
-        let mut header_buffer = [0u8; 8];
-        reader
-            .read_exact(&mut header_buffer)
-            .context("Unable to read header bytes")?;
-
-        ensure!(Png::is_png_header(header_buffer), "Invalid PNG file");
-
-        loop {
-            let mut length_buffer = [0u8; 4];
-            if let Err(e) = reader.read_exact(&mut length_buffer) {
-                // No more bytes to read
-                if e.kind() == io::ErrorKind::UnexpectedEof {
-                    break;
-                }
+        let png = PngRef::try_from(value)?;
 
-                bail!("Unable to read length bytes: {}", e);
-            };
+        let chunks = png
+            .chunks()
+            .map(|chunk| Chunk::try_from(chunk?))
+            .collect::<Result<Vec<_>>>()?;
 
-            let length = u32::from_be_bytes(length_buffer);
+        Ok(Self {
+            signature: png.signature(),
+            chunks,
+        })
+    }
+}
 
-            let mut data_buffer = vec![0u8; length as usize + 8];
-            reader
-                .read_exact(&mut data_buffer)
-                .context("Unable to read data bytes")?;
+impl Container for Png {
+    fn open(path: &Path) -> Result<Self> {
+        Self::from_file(path)
+    }
 
-            let mut chunk_bytes = Vec::new();
-            chunk_bytes.extend(length_buffer);
-            chunk_bytes.extend(data_buffer);
+    fn segments(&self) -> Vec<(usize, Cow<'_, Chunk>)> {
+        self.chunks.iter().map(Cow::Borrowed).enumerate().collect()
+    }
 
-            let chunk = Chunk::try_from(&chunk_bytes[..])?;
+    fn insert_payload(&mut self, chunk: Chunk, options: &PayloadOptions) -> Result<()> {
+        // Payloads should not split the image data, like the frames of an APNG
+        let index = self.frame_boundary(self.position_index(options.position)?);
+        self.insert_chunk(index, chunk);
+        Ok(())
+    }
 
-            chunks.push(chunk);
+    fn replace_payload(&mut self, chunk: &Chunk) -> Result<bool> {
+        match self
+            .chunks
+            .iter_mut()
+            .find(|c| c.chunk_type() == chunk.chunk_type())
+        {
+            Some(existing) => {
+                existing.set_data(chunk.data().clone());
+                Ok(true)
+            }
+            None => Ok(false),
         }
+    }
 
-        Ok(Self {
-            header: header_buffer,
-            chunks,
-        })
+    fn remove_payloads(&mut self, selector: &Selector) -> Result<Vec<Chunk>> {
+        let signature = self.signature;
+        if let Some((_, chunk)) = self
+            .select(selector)
+            .find(|(_, chunk)| signature.is_required(chunk.chunk_type()))
+        {
+            bail!(
+                "The selector \"{}\" matches the {} chunk, which {} files require",
+                selector,
+                chunk.chunk_type(),
+                signature
+            );
+        }
+
+        Ok(self.remove_selected(selector))
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
     }
 }
 
 impl Display for Png {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        let header = self.header.map(|v| v.to_string()).join(", ");
+        let header = self.header().map(|v| v.to_string()).join(", ");
 
         writeln!(f, "Png {{")?;
         writeln!(f, "  Header: [{}],", header)?;
@@ -177,6 +662,8 @@ mod tests {
     use super::*;
     use crate::chunk::Chunk;
     use crate::chunk_type::ChunkType;
+    use crate::filter::{FilterStrategy, FilterType};
+    use crate::palette::Color;
     use std::convert::TryFrom;
     use std::str::FromStr;
 
@@ -244,6 +731,19 @@ mod tests {
         assert!(png.is_err());
     }
 
+    #[test]
+    fn test_mng_and_jng_from_bytes() {
+        for signature in [Signature::Mng, Signature::Jng] {
+            let png = Png::with_signature(signature, testing_chunks());
+            let bytes = png.as_bytes();
+            assert_eq!(&bytes[..8], signature.bytes());
+
+            let actual = Png::try_from(bytes.as_ref()).unwrap();
+            assert_eq!(actual.signature(), signature);
+            assert_eq!(actual.as_bytes(), bytes);
+        }
+    }
+
     #[test]
     fn test_invalid_chunk() {
         let mut chunk_bytes: Vec<u8> = testing_chunks()
@@ -290,6 +790,133 @@ mod tests {
         assert_eq!(&chunk.data_as_string().unwrap(), "Message");
     }
 
+    #[test]
+    fn test_insert_chunk() {
+        let mut png = testing_png();
+        png.insert_chunk(1, chunk_from_strings("TeSt", "Message").unwrap());
+        let chunk = &png.chunks()[1];
+        assert_eq!(png.chunks().len(), 4);
+        assert_eq!(&chunk.chunk_type().to_string(), "TeSt");
+        assert_eq!(&chunk.data_as_string().unwrap(), "Message");
+    }
+
+    #[test]
+    fn test_replace_chunk() {
+        let mut png = testing_png();
+        let old_chunk = png.replace_chunk(1, chunk_from_strings("miDl", "Replaced").unwrap());
+        let chunk = &png.chunks()[1];
+        assert_eq!(png.chunks().len(), 3);
+        assert_eq!(&old_chunk.data_as_string().unwrap(), "I am another chunk");
+        assert_eq!(&chunk.data_as_string().unwrap(), "Replaced");
+        assert_eq!(chunk.length(), 8);
+    }
+
+    #[test]
+    fn test_append_chunk_to_file() {
+        let path = std::env::temp_dir().join(format!("pngme-append-{}.png", std::process::id()));
+        let mut png = testing_png();
+        png.append_chunk(Png::end_chunk(Signature::Png));
+        std::fs::write(&path, png.as_bytes()).unwrap();
+
+        let chunk = chunk_from_strings("ruSt", "Message").unwrap();
+        let bytes_written = Png::append_chunk_to_file(&path, &chunk).unwrap();
+
+        let actual = Png::from_file(&path).unwrap();
+        let chunk_types: Vec<String> = actual
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+
+        assert_eq!(bytes_written, Some(chunk.as_bytes().len() as u64 + 12));
+        assert_eq!(chunk_types, vec!["FrSt", "miDl", "LASt", "ruSt", "IEND"]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_append_chunk_to_mng_file() {
+        let path = std::env::temp_dir().join(format!("pngme-append-{}.mng", std::process::id()));
+        let mut chunks = testing_chunks();
+        chunks.push(Png::end_chunk(Signature::Mng));
+        std::fs::write(
+            &path,
+            Png::with_signature(Signature::Mng, chunks).as_bytes(),
+        )
+        .unwrap();
+
+        let chunk = chunk_from_strings("ruSt", "Message").unwrap();
+        assert!(Png::append_chunk_to_file(&path, &chunk).unwrap().is_some());
+
+        let actual = Png::from_file(&path).unwrap();
+        let chunk_types: Vec<String> = actual
+            .chunks()
+            .iter()
+            .map(|chunk| chunk.chunk_type().to_string())
+            .collect();
+
+        assert_eq!(actual.signature(), Signature::Mng);
+        assert_eq!(chunk_types, vec!["FrSt", "miDl", "LASt", "ruSt", "MEND"]);
+
+        std::fs::remove_file(path).unwrap();
+    }
+
+    #[test]
+    fn test_container_payloads() {
+        let mut chunks = testing_chunks();
+        chunks.insert(0, chunk_from_strings("IHDR", "").unwrap());
+        chunks.push(Png::end_chunk(Signature::Png));
+        let mut png = Png::from_chunks(chunks);
+
+        let options = PayloadOptions {
+            position: ChunkPosition::Start,
+            comment: false,
+        };
+        png.insert_payload(chunk_from_strings("ruSt", "first").unwrap(), &options)
+            .unwrap();
+        png.insert_payload(
+            chunk_from_strings("ruSt", "last").unwrap(),
+            &PayloadOptions::default(),
+        )
+        .unwrap();
+        assert!(png
+            .replace_payload(&chunk_from_strings("ruSt", "replaced").unwrap())
+            .unwrap());
+
+        let selector = Selector::from_str("ruSt").unwrap();
+        let messages: Vec<String> = png
+            .extract_payloads(&selector)
+            .iter()
+            .map(|chunk| chunk.data_as_string().unwrap())
+            .collect();
+        assert_eq!(messages, vec!["replaced", "last"]);
+        assert_eq!(png.segments()[1].1.chunk_type().to_string(), "ruSt");
+        assert!(png
+            .segments()
+            .iter()
+            .all(|(_, chunk)| matches!(chunk, Cow::Borrowed(_))));
+
+        assert!(png
+            .remove_payloads(&Selector::from_str("IEND").unwrap())
+            .is_err());
+        assert_eq!(png.remove_payloads(&selector).unwrap().len(), 2);
+        assert_eq!(png.to_bytes(), png.as_bytes());
+    }
+
+    #[test]
+    fn test_append_chunk_to_file_without_iend() {
+        let path = std::env::temp_dir().join(format!("pngme-no-iend-{}.png", std::process::id()));
+        std::fs::write(&path, testing_png().as_bytes()).unwrap();
+
+        let chunk = chunk_from_strings("ruSt", "Message").unwrap();
+        let bytes_written = Png::append_chunk_to_file(&path, &chunk).unwrap();
+
+        assert!(bytes_written.is_none());
+        assert_eq!(std::fs::read(&path).unwrap(), testing_png().as_bytes());
+
+        std::fs::remove_file(path).unwrap();
+    }
+
     #[test]
     fn test_remove_first_chunk() {
         let mut png = testing_png();
@@ -299,6 +926,252 @@ mod tests {
         assert!(chunk.is_none());
     }
 
+    #[test]
+    fn test_remove_chunk() {
+        let mut png = testing_png();
+        let chunk = png.remove_chunk(1);
+        assert_eq!(&chunk.chunk_type().to_string(), "miDl");
+        assert_eq!(png.chunks().len(), 2);
+        assert_eq!(&png.chunks()[1].chunk_type().to_string(), "LASt");
+    }
+
+    #[test]
+    fn test_retain() {
+        let mut png = testing_png();
+        png.retain(|chunk| chunk.chunk_type().is_critical());
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("miDl").is_none());
+    }
+
+    #[test]
+    fn test_chunks_mut() {
+        let mut png = testing_png();
+        png.chunks_mut()[1].set_data("Edited".bytes().collect());
+        let chunk = png.chunk_by_type("miDl").unwrap();
+        assert_eq!(&chunk.data_as_string().unwrap(), "Edited");
+        assert_eq!(chunk.length(), 6);
+    }
+
+    #[test]
+    fn test_iter_by_type() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am yet another chunk").unwrap());
+        let messages: Vec<String> = png
+            .iter_by_type("miDl")
+            .map(|chunk| chunk.data_as_string().unwrap())
+            .collect();
+        assert_eq!(
+            messages,
+            vec!["I am another chunk", "I am yet another chunk"]
+        );
+    }
+
+    #[test]
+    fn test_remove_all() {
+        let mut png = testing_png();
+        png.append_chunk(chunk_from_strings("miDl", "I am yet another chunk").unwrap());
+        let removed = png.remove_all("miDl");
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 2);
+        assert!(png.chunk_by_type("miDl").is_none());
+    }
+
+    #[test]
+    fn test_into_chunks() {
+        let chunks = testing_png().into_chunks();
+        assert_eq!(chunks.len(), 3);
+    }
+
+    #[test]
+    fn test_select() {
+        let png = testing_png();
+        let selector = Selector::from_str("ancillary || #2").unwrap();
+        let selected: Vec<usize> = png.select(&selector).map(|(index, _)| index).collect();
+        assert_eq!(selected, vec![1, 2]);
+    }
+
+    #[test]
+    fn test_remove_selected() {
+        let mut png = testing_png();
+        let selector = Selector::from_str("#0 || #1").unwrap();
+        let removed = png.remove_selected(&selector);
+        assert_eq!(removed.len(), 2);
+        assert_eq!(png.chunks().len(), 1);
+        assert_eq!(&png.chunks()[0].chunk_type().to_string(), "LASt");
+    }
+
+    #[test]
+    fn test_ihdr() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let ihdr = png.ihdr().unwrap();
+
+        assert_eq!(ihdr.width(), 50);
+        assert_eq!(ihdr.height(), 50);
+        assert_eq!(ihdr.bit_depth(), 8);
+        assert_eq!(ihdr.color_type(), crate::ihdr::ColorType::Rgba);
+
+        assert!(testing_png().ihdr().is_err());
+    }
+
+    #[test]
+    fn test_image_data() {
+        let png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let image_data = png.image_data().unwrap();
+
+        assert_eq!(image_data.scanlines().len(), 50);
+        assert!(image_data
+            .scanlines()
+            .iter()
+            .all(|scanline| scanline.data().len() == 200));
+
+        let raw = image_data.unfilter();
+        assert_eq!(raw.rows().len(), 50);
+        assert_eq!(raw.pixel(0, 0).unwrap().len(), 4);
+        assert!(raw.pixel(50, 0).is_none());
+    }
+
+    #[test]
+    fn test_set_image_data_interlaced_round_trip() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        let raw = png.image_data().unwrap().unfilter();
+
+        let mut interlaced = raw.clone();
+        interlaced.set_interlaced(true);
+        png.set_image_data(&interlaced, &EncodeOptions::default())
+            .unwrap();
+
+        assert!(png.ihdr().unwrap().is_interlaced());
+        assert_eq!(png.iter_by_type("IDAT").count(), 1);
+        assert_eq!(
+            png.chunks().last().unwrap().chunk_type().to_string(),
+            "IEND"
+        );
+
+        let decoded = png.image_data().unwrap().unfilter();
+        assert_eq!(decoded.rows(), raw.rows());
+
+        let mut progressive = decoded;
+        progressive.set_interlaced(false);
+        png.set_image_data(&progressive, &EncodeOptions::default())
+            .unwrap();
+
+        assert!(!png.ihdr().unwrap().is_interlaced());
+        assert_eq!(png.image_data().unwrap().unfilter(), raw);
+    }
+
+    #[test]
+    fn test_set_image_data_split_idat() {
+        let mut png = Png::try_from(&PNG_FILE[..]).unwrap();
+        png.insert_chunk(2, chunk_from_strings("ruSt", "message").unwrap());
+        let raw = png.image_data().unwrap().unfilter();
+
+        let options = EncodeOptions {
+            compression_level: 0,
+            filter_strategy: FilterStrategy::Fixed(FilterType::None),
+            idat_size: Some(1000),
+        };
+        png.set_image_data(&raw, &options).unwrap();
+
+        let idat_lengths: Vec<u32> = png.iter_by_type("IDAT").map(|c| c.length()).collect();
+        assert!(idat_lengths.len() > 10);
+        assert!(idat_lengths[..idat_lengths.len() - 1]
+            .iter()
+            .all(|length| *length == 1000));
+        assert_eq!(png.chunk_by_type("ruSt").unwrap().data(), b"message");
+        assert_eq!(png.image_data().unwrap().unfilter(), raw);
+
+        let options = EncodeOptions {
+            idat_size: Some(0),
+            ..EncodeOptions::default()
+        };
+        assert!(png.set_image_data(&raw, &options).is_err());
+    }
+
+    fn indexed_png() -> Png {
+        let ihdr = crate::ihdr::Ihdr::new(2, 1, 2, crate::ihdr::ColorType::Indexed, false).unwrap();
+        let idat = Chunk::new(ChunkType::from_str("IDAT").unwrap(), vec![]);
+        let iend = Chunk::new(ChunkType::from_str("IEND").unwrap(), vec![]);
+
+        Png::from_chunks(vec![ihdr.as_chunk(), idat, iend])
+    }
+
+    #[test]
+    fn test_set_palette_and_transparency() {
+        let mut png = indexed_png();
+        assert!(png.palette().unwrap().is_none());
+
+        // tRNS needs a palette for indexed images
+        assert!(png
+            .set_transparency(&Transparency::Indexed(vec![0]))
+            .is_err());
+
+        let mut palette =
+            Palette::new(vec![Color::new(0, 0, 0), Color::new(255, 255, 255)]).unwrap();
+        png.set_palette(&palette).unwrap();
+        png.set_transparency(&Transparency::Indexed(vec![0]))
+            .unwrap();
+
+        let chunk_types: Vec<String> = png
+            .chunks()
+            .iter()
+            .map(|c| c.chunk_type().to_string())
+            .collect();
+        assert_eq!(chunk_types, vec!["IHDR", "PLTE", "tRNS", "IDAT", "IEND"]);
+
+        palette.set_color(1, Color::new(255, 0, 0)).unwrap();
+        png.set_palette(&palette).unwrap();
+        assert_eq!(png.palette().unwrap().unwrap(), palette);
+        assert_eq!(png.chunks().len(), 5);
+
+        // The tRNS chunk would have more entries than the palette
+        png.set_transparency(&Transparency::Indexed(vec![0, 0]))
+            .unwrap();
+        let palette = Palette::new(vec![Color::new(0, 0, 0)]).unwrap();
+        assert!(png.set_palette(&palette).is_err());
+
+        // A 2-bit image can only index 4 entries
+        let palette = Palette::new(vec![Color::new(0, 0, 0); 5]).unwrap();
+        assert!(png.set_palette(&palette).is_err());
+    }
+
+    #[test]
+    fn test_set_transparency_all_opaque() {
+        let mut png = indexed_png();
+        let palette = Palette::new(vec![Color::new(0, 0, 0), Color::new(255, 255, 255)]).unwrap();
+        png.set_palette(&palette).unwrap();
+
+        let mut transparency = Transparency::Indexed(vec![0, 128]);
+        png.set_transparency(&transparency).unwrap();
+        assert!(png.chunk_by_type("tRNS").is_some());
+
+        transparency.set_alpha(0, 255).unwrap();
+        transparency.set_alpha(1, 255).unwrap();
+        assert_eq!(transparency, Transparency::Indexed(vec![]));
+
+        png.set_transparency(&transparency).unwrap();
+        assert!(png.chunk_by_type("tRNS").is_none());
+        assert_eq!(png.transparency().unwrap(), None);
+
+        // Still valid without a tRNS chunk
+        png.set_transparency(&transparency).unwrap();
+        assert_eq!(png.chunks().len(), 4);
+    }
+
+    #[test]
+    fn test_frame_boundary() {
+        let png = Png::from_chunks(
+            [
+                "IHDR", "acTL", "fcTL", "IDAT", "IDAT", "fcTL", "fdAT", "tEXt", "IEND",
+            ]
+            .iter()
+            .map(|chunk_type| chunk_from_strings(chunk_type, "").unwrap())
+            .collect(),
+        );
+
+        let boundaries: Vec<usize> = (0..=9).map(|i| png.frame_boundary(i)).collect();
+        assert_eq!(boundaries, vec![0, 1, 2, 2, 2, 5, 5, 7, 8, 9]);
+    }
+
     #[test]
     fn test_png_from_image_file() {
         let png = Png::try_from(&PNG_FILE[..]);
@@ -0,0 +1,201 @@
+use anyhow::{ensure, Context, Error, Result};
+
+use crate::{chunk_ref::ChunkRef, signature::Signature};
+
+/// A read-only view of a PNG (or MNG or JNG) that borrows from a byte buffer, like the contents
+/// of a file read into memory or a memory-mapped file.
+///
+/// Chunks are parsed lazily while iterating with [PngRef::chunks], without copying their data.
+#[derive(Debug, Clone, Copy)]
+pub struct PngRef<'a> {
+    signature: Signature,
+    header: &'a [u8; 8],
+    chunk_bytes: &'a [u8],
+}
+
+impl<'a> PngRef<'a> {
+    pub fn header(&self) -> &'a [u8; 8] {
+        self.header
+    }
+
+    pub fn signature(&self) -> Signature {
+        self.signature
+    }
+
+    /// Iterates over the chunks, parsing them as they are reached. Iteration stops after the
+    /// first chunk that fails to be parsed.
+    pub fn chunks(&self) -> ChunkRefs<'a> {
+        ChunkRefs {
+            bytes: self.chunk_bytes,
+            offset: self.header.len(),
+        }
+    }
+
+    /// Returns the first chunk of a chunk type
+    pub fn chunk_by_type(&self, chunk_type: &str) -> Result<Option<ChunkRef<'a>>> {
+        for chunk in self.chunks() {
+            let chunk = chunk?;
+            if chunk.chunk_type().bytes() == chunk_type.as_bytes() {
+                return Ok(Some(chunk));
+            }
+        }
+
+        Ok(None)
+    }
+
+    /// Checks the CRC of every chunk
+    pub fn verify_crcs(&self) -> Result<()> {
+        for chunk in self.chunks() {
+            chunk?.verify_crc()?;
+        }
+
+        Ok(())
+    }
+}
+
+impl<'a> TryFrom<&'a [u8]> for PngRef<'a> {
+    type Error = Error;
+
+    fn try_from(bytes: &'a [u8]) -> Result<Self, Self::Error> {
+        ensure!(bytes.len() >= 8, "Unable to read header bytes");
+
+        let (header, chunk_bytes) = bytes.split_at(8);
+        // Since we make sure [bytes]'s length is at least 8, the conversion should not fail
+        let header: &[u8; 8] = header.try_into()?;
+
+        let signature = Signature::from_bytes(header).context("Invalid PNG file")?;
+
+        Ok(Self {
+            signature,
+            header,
+            chunk_bytes,
+        })
+    }
+}
+
+/// Iterator over the chunks of a [PngRef]
+#[derive(Debug, Clone)]
+pub struct ChunkRefs<'a> {
+    bytes: &'a [u8],
+    offset: usize,
+}
+
+impl ChunkRefs<'_> {
+    /// Offset, from the start of the PNG, of the next chunk to be parsed
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+}
+
+impl<'a> Iterator for ChunkRefs<'a> {
+    type Item = Result<ChunkRef<'a>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Like when reading a `Png`, there are no more chunks if there aren't enough bytes left
+        // for the length of another chunk
+        if self.bytes.len() < 4 {
+            return None;
+        }
+
+        match ChunkRef::parse(self.bytes) {
+            Ok(chunk) => {
+                self.bytes = &self.bytes[chunk.total_length()..];
+                self.offset += chunk.total_length();
+                Some(Ok(chunk))
+            }
+            Err(e) => {
+                self.bytes = &[];
+                Some(Err(e))
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk::Chunk;
+    use crate::chunk_type::ChunkType;
+    use crate::png::Png;
+    use std::str::FromStr;
+
+    fn testing_png_bytes() -> Vec<u8> {
+        let chunks = vec![
+            chunk_from_strings("FrSt", "I am the first chunk"),
+            chunk_from_strings("miDl", "I am another chunk"),
+            chunk_from_strings("LASt", "I am the last chunk"),
+        ];
+
+        Png::from_chunks(chunks).as_bytes()
+    }
+
+    fn chunk_from_strings(chunk_type: &str, data: &str) -> Chunk {
+        let chunk_type = ChunkType::from_str(chunk_type).unwrap();
+        Chunk::new(chunk_type, data.bytes().collect())
+    }
+
+    #[test]
+    fn test_png_ref_chunks() {
+        let bytes = testing_png_bytes();
+        let png = PngRef::try_from(&bytes[..]).unwrap();
+
+        let chunk_types: Vec<String> = png
+            .chunks()
+            .map(|chunk| chunk.unwrap().chunk_type().to_string())
+            .collect();
+
+        assert_eq!(chunk_types, vec!["FrSt", "miDl", "LASt"]);
+        assert!(png.verify_crcs().is_ok());
+    }
+
+    #[test]
+    fn test_png_ref_chunk_by_type() {
+        let bytes = testing_png_bytes();
+        let png = PngRef::try_from(&bytes[..]).unwrap();
+
+        let chunk = png.chunk_by_type("miDl").unwrap().unwrap();
+        assert_eq!(chunk.data(), "I am another chunk".as_bytes());
+        assert!(png.chunk_by_type("abCd").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_png_ref_offsets() {
+        let bytes = testing_png_bytes();
+        let png = PngRef::try_from(&bytes[..]).unwrap();
+
+        let mut chunks = png.chunks();
+        assert_eq!(chunks.offset(), 8);
+        let first = chunks.next().unwrap().unwrap();
+        assert_eq!(chunks.offset(), 8 + first.total_length());
+    }
+
+    #[test]
+    fn test_png_ref_invalid_header() {
+        let mut bytes = testing_png_bytes();
+        bytes[0] = 13;
+
+        assert!(PngRef::try_from(&bytes[..]).is_err());
+        assert!(PngRef::try_from(&bytes[..4]).is_err());
+    }
+
+    #[test]
+    fn test_png_ref_invalid_crc_is_lazy() {
+        let mut bytes = testing_png_bytes();
+        let last = bytes.len() - 1;
+        bytes[last] ^= 1;
+
+        let png = PngRef::try_from(&bytes[..]).unwrap();
+        assert_eq!(png.chunks().count(), 3);
+        assert!(png.verify_crcs().is_err());
+    }
+
+    #[test]
+    fn test_png_ref_truncated_chunk() {
+        let bytes = testing_png_bytes();
+        let png = PngRef::try_from(&bytes[..bytes.len() - 1]).unwrap();
+
+        let chunks: Vec<_> = png.chunks().collect();
+        assert_eq!(chunks.len(), 3);
+        assert!(chunks[2].is_err());
+    }
+}
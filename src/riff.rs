@@ -0,0 +1,416 @@
+//! Reads and writes RIFF files, like WebP images, WAV audio and AVI videos, to hide messages in
+//! custom chunks.
+//!
+//! RIFF files start with `RIFF`, the size of the rest of the file and a form type (`WEBP`, `WAVE`,
+//! `AVI `), followed by chunks made of a FourCC, a little-endian size and data, padded to an even
+//! length. Chunks whose FourCC is made of 4 letters are exposed as [Chunk]s, so messages are
+//! stored in chunks with the chunk type as FourCC and selected like PNG chunks.
+
+use std::{borrow::Cow, fs, path::Path, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::{
+    chunk::Chunk,
+    chunk_type::ChunkType,
+    container::{Container, PayloadOptions},
+    selector::Selector,
+};
+
+/// Length of the FourCC and size of a chunk
+const CHUNK_HEADER_LENGTH: usize = 8;
+
+fn read_u32_le(bytes: &[u8], offset: usize) -> u32 {
+    u32::from_le_bytes([
+        bytes[offset],
+        bytes[offset + 1],
+        bytes[offset + 2],
+        bytes[offset + 3],
+    ])
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiffChunk {
+    pub fourcc: [u8; 4],
+    pub data: Vec<u8>,
+}
+
+impl RiffChunk {
+    /// Returns the chunk as a [Chunk] if its FourCC is a valid chunk type
+    pub fn as_chunk(&self) -> Option<Chunk> {
+        let fourcc = std::str::from_utf8(&self.fourcc).ok()?;
+        let chunk_type = ChunkType::from_str(fourcc).ok()?;
+
+        Some(Chunk::new(chunk_type, self.data.clone()))
+    }
+
+    /// Length of the chunk in the file, including the pad byte after data of odd length
+    pub fn total_length(&self) -> usize {
+        CHUNK_HEADER_LENGTH + self.data.len() + self.data.len() % 2
+    }
+
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(self.total_length());
+        bytes.extend(self.fourcc);
+        bytes.extend((self.data.len() as u32).to_le_bytes());
+        bytes.extend(&self.data);
+        if self.data.len() % 2 == 1 {
+            bytes.push(0);
+        }
+        bytes
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct Riff {
+    form_type: [u8; 4],
+    chunks: Vec<RiffChunk>,
+
+    /// Bytes after the size given in the RIFF header, kept so they're written back unchanged
+    trailing: Vec<u8>,
+}
+
+impl Riff {
+    pub const SIGNATURE: [u8; 4] = *b"RIFF";
+
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
+        let bytes = fs::read(path).context("Unable to read file")?;
+        Self::try_from(&bytes[..])
+    }
+
+    /// The form type, like `WEBP`, `WAVE` or `AVI `
+    pub fn form_type(&self) -> &[u8; 4] {
+        &self.form_type
+    }
+
+    pub fn riff_chunks(&self) -> &[RiffChunk] {
+        &self.chunks
+    }
+
+    /// Returns the chunks whose FourCC is a valid chunk type as [Chunk]s, along with their index
+    /// in the chunk list
+    pub fn chunks(&self) -> Vec<(usize, Chunk)> {
+        self.chunks
+            .iter()
+            .enumerate()
+            .filter_map(|(index, chunk)| chunk.as_chunk().map(|chunk| (index, chunk)))
+            .collect()
+    }
+
+    pub fn select(&self, selector: &Selector) -> Vec<(usize, Chunk)> {
+        self.chunks()
+            .into_iter()
+            .filter(|(index, chunk)| selector.matches(*index, chunk))
+            .collect()
+    }
+
+    /// Checks if the form type requires chunks with this FourCC, like the image data of WebP
+    /// images or the format and samples of WAV files, so they should never be removed
+    pub fn is_required(&self, fourcc: &[u8; 4]) -> bool {
+        match &self.form_type {
+            b"WEBP" => matches!(
+                fourcc,
+                b"VP8 " | b"VP8L" | b"VP8X" | b"ALPH" | b"ANIM" | b"ANMF"
+            ),
+            b"WAVE" => matches!(fourcc, b"fmt " | b"data"),
+            b"AVI " => matches!(fourcc, b"LIST" | b"idx1"),
+            _ => false,
+        }
+    }
+
+    /// Adds a chunk after all the others, where readers expecting the image or audio data
+    /// first still find it
+    pub fn append_chunk(&mut self, chunk: &Chunk) -> Result<()> {
+        let chunk = RiffChunk {
+            fourcc: chunk.chunk_type().bytes(),
+            data: chunk.data().clone(),
+        };
+        ensure!(
+            self.riff_size() + chunk.total_length() <= u32::MAX as usize,
+            "The RIFF file would be larger than 4 GiB"
+        );
+
+        self.chunks.push(chunk);
+
+        Ok(())
+    }
+
+    /// Replaces the data of the first chunk with the chunk type of `chunk` as FourCC, keeping
+    /// its position. Returns false if there's no such chunk.
+    pub fn replace_chunk(&mut self, chunk: &Chunk) -> bool {
+        let fourcc = chunk.chunk_type().bytes();
+        match self.chunks.iter_mut().find(|c| c.fourcc == fourcc) {
+            Some(riff_chunk) => {
+                riff_chunk.data = chunk.data().clone();
+                true
+            }
+            None => false,
+        }
+    }
+
+    /// Removes the chunks matching the selector, returning them. Fails without removing any
+    /// chunk if a chunk required by the form type is selected.
+    pub fn remove_selected(&mut self, selector: &Selector) -> Result<Vec<Chunk>> {
+        let selected = self.select(selector);
+
+        if let Some((_, chunk)) = selected
+            .iter()
+            .find(|(_, chunk)| self.is_required(&chunk.chunk_type().bytes()))
+        {
+            bail!(
+                "The selector \"{}\" matches the {} chunk, which {} files require",
+                selector,
+                chunk.chunk_type(),
+                String::from_utf8_lossy(&self.form_type).trim_end()
+            );
+        }
+
+        // Removing from the end keeps the indices of the other selected chunks valid
+        for (index, _) in selected.iter().rev() {
+            self.chunks.remove(*index);
+        }
+
+        Ok(selected.into_iter().map(|(_, chunk)| chunk).collect())
+    }
+
+    /// Size stored in the RIFF header: the form type and all chunks
+    fn riff_size(&self) -> usize {
+        self.form_type.len() + self.chunks.iter().map(|c| c.total_length()).sum::<usize>()
+    }
+
+    /// Serializes the file, with the RIFF size updated to match the chunks
+    pub fn as_bytes(&self) -> Vec<u8> {
+        let mut bytes = Vec::with_capacity(CHUNK_HEADER_LENGTH + self.riff_size());
+        bytes.extend(Self::SIGNATURE);
+        bytes.extend((self.riff_size() as u32).to_le_bytes());
+        bytes.extend(self.form_type);
+
+        for chunk in self.chunks.iter() {
+            bytes.extend(chunk.as_bytes());
+        }
+
+        bytes.extend(&self.trailing);
+        bytes
+    }
+}
+
+impl Container for Riff {
+    fn open(path: &Path) -> Result<Self> {
+        Self::from_file(path)
+    }
+
+    fn segments(&self) -> Vec<(usize, Cow<'_, Chunk>)> {
+        self.chunks()
+            .into_iter()
+            .map(|(index, chunk)| (index, Cow::Owned(chunk)))
+            .collect()
+    }
+
+    /// Appends a chunk after all other chunks, the options are ignored
+    fn insert_payload(&mut self, chunk: Chunk, _options: &PayloadOptions) -> Result<()> {
+        self.append_chunk(&chunk)
+    }
+
+    fn replace_payload(&mut self, chunk: &Chunk) -> Result<bool> {
+        Ok(self.replace_chunk(chunk))
+    }
+
+    fn remove_payloads(&mut self, selector: &Selector) -> Result<Vec<Chunk>> {
+        self.remove_selected(selector)
+    }
+
+    fn to_bytes(&self) -> Vec<u8> {
+        self.as_bytes()
+    }
+}
+
+impl TryFrom<&[u8]> for Riff {
+    type Error = Error;
+
+    fn try_from(bytes: &[u8]) -> Result<Self, Self::Error> {
+        ensure!(
+            bytes.len() >= 12 && bytes.starts_with(&Self::SIGNATURE),
+            "Invalid RIFF file, the file does not start with a RIFF header"
+        );
+
+        let riff_size = read_u32_le(bytes, 4) as usize;
+        ensure!(
+            riff_size >= 4 && CHUNK_HEADER_LENGTH + riff_size <= bytes.len(),
+            "Invalid RIFF file, the RIFF size {} doesn't match the file size",
+            riff_size
+        );
+        let end = CHUNK_HEADER_LENGTH + riff_size;
+
+        let mut form_type = [0u8; 4];
+        form_type.copy_from_slice(&bytes[8..12]);
+
+        let mut chunks = Vec::new();
+        let mut offset = 12;
+
+        while offset < end {
+            ensure!(
+                offset + CHUNK_HEADER_LENGTH <= end,
+                "Invalid RIFF file, the chunk at offset {} is truncated",
+                offset
+            );
+
+            let mut fourcc = [0u8; 4];
+            fourcc.copy_from_slice(&bytes[offset..offset + 4]);
+            let size = read_u32_le(bytes, offset + 4) as usize;
+
+            let data_start = offset + CHUNK_HEADER_LENGTH;
+            ensure!(
+                size <= end - data_start,
+                "Invalid RIFF file, the {} chunk at offset {} is larger than the file",
+                String::from_utf8_lossy(&fourcc),
+                offset
+            );
+
+            chunks.push(RiffChunk {
+                fourcc,
+                data: bytes[data_start..data_start + size].to_vec(),
+            });
+
+            // Some writers leave out the pad byte of the last chunk
+            offset = (data_start + size + size % 2).min(end);
+        }
+
+        Ok(Self {
+            form_type,
+            chunks,
+            trailing: bytes[end..].to_vec(),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn riff_bytes(form_type: &[u8; 4], chunks: &[(&[u8; 4], &[u8])]) -> Vec<u8> {
+        let mut body = form_type.to_vec();
+        for (fourcc, data) in chunks {
+            body.extend(*fourcc);
+            body.extend((data.len() as u32).to_le_bytes());
+            body.extend(*data);
+            if data.len() % 2 == 1 {
+                body.push(0);
+            }
+        }
+
+        let mut bytes = b"RIFF".to_vec();
+        bytes.extend((body.len() as u32).to_le_bytes());
+        bytes.extend(body);
+        bytes
+    }
+
+    fn testing_webp_bytes() -> Vec<u8> {
+        riff_bytes(
+            b"WEBP",
+            &[(b"VP8L", b"\x2f\x00\x00\x00\x10\x07"), (b"EXIF", b"odd")],
+        )
+    }
+
+    fn message_chunk(chunk_type: &str, message: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            message.as_bytes().to_vec(),
+        )
+    }
+
+    #[test]
+    fn test_parse_and_write() {
+        let bytes = testing_webp_bytes();
+        let riff = Riff::try_from(&bytes[..]).unwrap();
+
+        assert_eq!(riff.form_type(), b"WEBP");
+        assert_eq!(riff.riff_chunks().len(), 2);
+        assert_eq!(riff.riff_chunks()[1].data, b"odd");
+        assert_eq!(riff.riff_chunks()[1].total_length(), 12);
+        assert_eq!(riff.as_bytes(), bytes);
+    }
+
+    #[test]
+    fn test_append_fixes_size_and_padding() {
+        let mut riff = Riff::try_from(&testing_webp_bytes()[..]).unwrap();
+        riff.append_chunk(&message_chunk("ruSt", "Hi!")).unwrap();
+
+        let bytes = riff.as_bytes();
+        assert_eq!(read_u32_le(&bytes, 4) as usize, bytes.len() - 8);
+        assert_eq!(bytes.len() % 2, 0);
+        assert_eq!(&bytes[bytes.len() - 12..], b"ruSt\x03\x00\x00\x00Hi!\x00");
+
+        let riff = Riff::try_from(&bytes[..]).unwrap();
+        let selector = Selector::from_str("ruSt").unwrap();
+        let selected = riff.select(&selector);
+        assert_eq!(selected.len(), 1);
+        assert_eq!(selected[0].0, 2);
+        assert_eq!(selected[0].1.data_as_string().unwrap(), "Hi!");
+    }
+
+    #[test]
+    fn test_replace_and_remove() {
+        let mut riff = Riff::try_from(&testing_webp_bytes()[..]).unwrap();
+        riff.append_chunk(&message_chunk("ruSt", "first")).unwrap();
+
+        assert!(riff.replace_chunk(&message_chunk("ruSt", "second")));
+        assert!(!riff.replace_chunk(&message_chunk("abCd", "missing")));
+
+        let selector = Selector::from_str("ruSt").unwrap();
+        let removed = riff.remove_selected(&selector).unwrap();
+        assert_eq!(removed[0].data_as_string().unwrap(), "second");
+        assert_eq!(riff.as_bytes(), testing_webp_bytes());
+    }
+
+    #[test]
+    fn test_webp_required_chunks() {
+        let bytes = riff_bytes(
+            b"WEBP",
+            &[(b"VP8X", b"flags"), (b"ALPH", b"alpha"), (b"VP8 ", b"data")],
+        );
+        let mut riff = Riff::try_from(&bytes[..]).unwrap();
+
+        // VP8X and "VP8 " aren't valid chunk types so they're never selected, but ALPH is
+        let selector = Selector::from_str("*").unwrap();
+        assert_eq!(riff.select(&selector).len(), 1);
+        assert!(riff.remove_selected(&selector).is_err());
+        assert_eq!(riff.riff_chunks().len(), 3);
+    }
+
+    #[test]
+    fn test_wave_required_chunks() {
+        let bytes = riff_bytes(b"WAVE", &[(b"fmt ", b"format"), (b"data", b"samples")]);
+        let mut riff = Riff::try_from(&bytes[..]).unwrap();
+
+        // Only "data" is a valid chunk type, "fmt " isn't
+        assert_eq!(riff.chunks().len(), 1);
+        let selector = Selector::from_str("data").unwrap();
+        assert!(riff.remove_selected(&selector).is_err());
+    }
+
+    #[test]
+    fn test_missing_last_pad_byte_and_trailing_bytes() {
+        let mut bytes = riff_bytes(b"WEBP", &[(b"VP8L", b"abc")]);
+        // Drop the pad byte and fix the RIFF size
+        bytes.pop();
+        let size = (bytes.len() - 8) as u32;
+        bytes[4..8].copy_from_slice(&size.to_le_bytes());
+        bytes.extend(b"junk");
+
+        let riff = Riff::try_from(&bytes[..]).unwrap();
+        assert_eq!(riff.riff_chunks()[0].data, b"abc");
+        assert!(riff.as_bytes().ends_with(b"abc\x00junk"));
+    }
+
+    #[test]
+    fn test_invalid_riff() {
+        let bytes = testing_webp_bytes();
+
+        assert!(Riff::try_from(&bytes[..10]).is_err());
+        assert!(Riff::try_from(&bytes[..bytes.len() - 1]).is_err());
+
+        let mut bad_chunk_size = bytes.clone();
+        bad_chunk_size[16] = 0xff;
+        assert!(Riff::try_from(&bad_chunk_size[..]).is_err());
+    }
+}
@@ -0,0 +1,509 @@
+use std::{fmt::Display, str::FromStr};
+
+use anyhow::{bail, ensure, Context, Error, Result};
+
+use crate::chunk::Chunk;
+
+/// Flags encoded in the case of the letters of a chunk type
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ChunkFlag {
+    Critical,
+    Ancillary,
+    Public,
+    Private,
+    SafeToCopy,
+    UnsafeToCopy,
+}
+
+impl ChunkFlag {
+    const KEYWORDS: [(&'static str, ChunkFlag); 6] = [
+        ("critical", ChunkFlag::Critical),
+        ("ancillary", ChunkFlag::Ancillary),
+        ("public", ChunkFlag::Public),
+        ("private", ChunkFlag::Private),
+        ("safe-to-copy", ChunkFlag::SafeToCopy),
+        ("unsafe-to-copy", ChunkFlag::UnsafeToCopy),
+    ];
+
+    fn from_keyword(keyword: &str) -> Option<Self> {
+        Self::KEYWORDS
+            .iter()
+            .find(|(k, _)| *k == keyword)
+            .map(|(_, flag)| *flag)
+    }
+
+    fn keyword(&self) -> &'static str {
+        Self::KEYWORDS
+            .iter()
+            .find(|(_, flag)| flag == self)
+            .map(|(k, _)| *k)
+            .unwrap_or_default()
+    }
+
+    fn matches(&self, chunk: &Chunk) -> bool {
+        let chunk_type = chunk.chunk_type();
+        match self {
+            ChunkFlag::Critical => chunk_type.is_critical(),
+            ChunkFlag::Ancillary => !chunk_type.is_critical(),
+            ChunkFlag::Public => chunk_type.is_public(),
+            ChunkFlag::Private => !chunk_type.is_public(),
+            ChunkFlag::SafeToCopy => chunk_type.is_safe_to_copy(),
+            ChunkFlag::UnsafeToCopy => !chunk_type.is_safe_to_copy(),
+        }
+    }
+}
+
+/// Selects chunks of an image.
+///
+/// Selectors are parsed from expressions combining the following with `!`, `&&`, `||` and
+/// parentheses:
+///
+/// - A chunk type, or a glob where `?` matches any letter and `*` any number of letters.
+///   Ex.: `ruSt`, `ru??`, `*`
+/// - A chunk index or range of indices, starting at 0. Ex.: `#3`, `#2..5`, `#2..=4`, `#2..`
+/// - A chunk type flag: `critical`, `ancillary`, `public`, `private`, `safe-to-copy` or
+///   `unsafe-to-copy`
+/// - Data containing some text, quoted if it has spaces or special characters. Inside quotes,
+///   `\"` and `\\` stand for a quote and a backslash. Ex.: `data:secret`, `data:"Hi there!"`
+///
+/// Ex.: `private && ancillary`, `ru?? || (#0..3 && !critical)`
+#[derive(Debug, Clone, PartialEq)]
+pub enum Selector {
+    Type(String),
+    Index { start: usize, end: Option<usize> },
+    Flag(ChunkFlag),
+    Data(Vec<u8>),
+    Not(Box<Selector>),
+    And(Box<Selector>, Box<Selector>),
+    Or(Box<Selector>, Box<Selector>),
+}
+
+impl Selector {
+    /// Checks if the chunk at `index` in the image's chunk list is selected
+    pub fn matches(&self, index: usize, chunk: &Chunk) -> bool {
+        match self {
+            Selector::Type(pattern) => {
+                Self::glob_matches(pattern.as_bytes(), &chunk.chunk_type().bytes())
+            }
+            Selector::Index { start, end } => index >= *start && end.is_none_or(|e| index < e),
+            Selector::Flag(flag) => flag.matches(chunk),
+            Selector::Data(needle) => {
+                needle.is_empty()
+                    || chunk
+                        .data()
+                        .windows(needle.len())
+                        .any(|window| window == &needle[..])
+            }
+            Selector::Not(selector) => !selector.matches(index, chunk),
+            Selector::And(a, b) => a.matches(index, chunk) && b.matches(index, chunk),
+            Selector::Or(a, b) => a.matches(index, chunk) || b.matches(index, chunk),
+        }
+    }
+
+    fn glob_matches(pattern: &[u8], bytes: &[u8]) -> bool {
+        match (pattern.first(), bytes.first()) {
+            (None, None) => true,
+            (Some(b'*'), _) => {
+                Self::glob_matches(&pattern[1..], bytes)
+                    || (!bytes.is_empty() && Self::glob_matches(pattern, &bytes[1..]))
+            }
+            (Some(b'?'), Some(_)) => Self::glob_matches(&pattern[1..], &bytes[1..]),
+            (Some(p), Some(b)) if p == b => Self::glob_matches(&pattern[1..], &bytes[1..]),
+            _ => false,
+        }
+    }
+
+    fn parse_word(word: &str) -> Result<Self> {
+        if let Some(range) = word.strip_prefix('#') {
+            return Self::parse_index(range);
+        }
+
+        if let Some(data) = word.strip_prefix("data:") {
+            return Ok(Selector::Data(data.as_bytes().to_vec()));
+        }
+
+        if let Some(flag) = ChunkFlag::from_keyword(word) {
+            return Ok(Selector::Flag(flag));
+        }
+
+        if let Some(c) = word
+            .chars()
+            .find(|c| !(c.is_ascii_alphabetic() || *c == '?' || *c == '*'))
+        {
+            bail!(
+                "Invalid selector \"{}\", the character '{}' is not allowed in chunk types",
+                word,
+                c
+            );
+        }
+
+        ensure!(
+            word.contains('*') || word.len() == 4,
+            "Invalid selector \"{}\", chunk types should have 4 characters",
+            word
+        );
+
+        Ok(Selector::Type(word.to_string()))
+    }
+
+    fn parse_index(range: &str) -> Result<Self> {
+        let parse = |s: &str| -> Result<usize> {
+            s.parse()
+                .context(format!("Invalid chunk index \"{}\" in selector", s))
+        };
+
+        // The end of the range is exclusive, so inclusive ends are moved past the last index
+        let after = |s: &str| -> Result<usize> {
+            parse(s)?
+                .checked_add(1)
+                .context(format!("Chunk index \"{}\" in selector is too large", s))
+        };
+
+        let (start, end) = match range.split_once("..") {
+            None => (parse(range)?, Some(after(range)?)),
+            Some((start, end)) => {
+                let start = if start.is_empty() { 0 } else { parse(start)? };
+                let end = match end.strip_prefix('=') {
+                    Some(end) => Some(after(end)?),
+                    None if end.is_empty() => None,
+                    None => Some(parse(end)?),
+                };
+                (start, end)
+            }
+        };
+
+        Ok(Selector::Index { start, end })
+    }
+
+    fn precedence(&self) -> u8 {
+        match self {
+            Selector::Or(..) => 0,
+            Selector::And(..) => 1,
+            _ => 2,
+        }
+    }
+
+    fn fmt_operand(&self, f: &mut std::fmt::Formatter<'_>, precedence: u8) -> std::fmt::Result {
+        if self.precedence() < precedence {
+            write!(f, "({})", self)
+        } else {
+            write!(f, "{}", self)
+        }
+    }
+}
+
+#[derive(Debug, PartialEq)]
+enum Token {
+    Not,
+    And,
+    Or,
+    Open,
+    Close,
+    Word(String),
+}
+
+fn tokenize(s: &str) -> Result<Vec<Token>> {
+    let mut tokens = Vec::new();
+    let mut chars = s.chars().peekable();
+
+    while let Some(c) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {}
+            '!' => tokens.push(Token::Not),
+            '(' => tokens.push(Token::Open),
+            ')' => tokens.push(Token::Close),
+            '&' | '|' => {
+                ensure!(
+                    chars.next() == Some(c),
+                    "Invalid selector \"{}\", expected \"{}{}\"",
+                    s,
+                    c,
+                    c
+                );
+                tokens.push(if c == '&' { Token::And } else { Token::Or });
+            }
+            _ => {
+                let mut word = String::from(c);
+                while let Some(&c) = chars.peek() {
+                    if c.is_whitespace() || "!()&|".contains(c) {
+                        break;
+                    }
+                    chars.next();
+
+                    if c != '"' {
+                        word.push(c);
+                        continue;
+                    }
+
+                    // Quoted text is taken as is, up to the closing quote. A backslash escapes
+                    // the next character, like a quote or another backslash
+                    loop {
+                        match chars.next() {
+                            Some('"') => break,
+                            Some('\\') => match chars.next() {
+                                Some(c) => word.push(c),
+                                None => bail!("Invalid selector \"{}\", missing closing quote", s),
+                            },
+                            Some(c) => word.push(c),
+                            None => bail!("Invalid selector \"{}\", missing closing quote", s),
+                        }
+                    }
+                }
+                tokens.push(Token::Word(word));
+            }
+        }
+    }
+
+    Ok(tokens)
+}
+
+/// Recursive descent parser over the selector tokens, from lowest to highest precedence:
+/// `||`, `&&`, then `!`, parentheses and words
+struct Parser<'a> {
+    input: &'a str,
+    tokens: Vec<Token>,
+    position: usize,
+}
+
+impl Parser<'_> {
+    fn next_is(&self, token: &Token) -> bool {
+        self.tokens.get(self.position) == Some(token)
+    }
+
+    fn parse_or(&mut self) -> Result<Selector> {
+        let mut selector = self.parse_and()?;
+        while self.next_is(&Token::Or) {
+            self.position += 1;
+            selector = Selector::Or(Box::new(selector), Box::new(self.parse_and()?));
+        }
+        Ok(selector)
+    }
+
+    fn parse_and(&mut self) -> Result<Selector> {
+        let mut selector = self.parse_unary()?;
+        while self.next_is(&Token::And) {
+            self.position += 1;
+            selector = Selector::And(Box::new(selector), Box::new(self.parse_unary()?));
+        }
+        Ok(selector)
+    }
+
+    fn parse_unary(&mut self) -> Result<Selector> {
+        let token = self.tokens.get(self.position).context(format!(
+            "Invalid selector \"{}\", it ends unexpectedly",
+            self.input
+        ))?;
+        self.position += 1;
+
+        match token {
+            Token::Not => Ok(Selector::Not(Box::new(self.parse_unary()?))),
+            Token::Open => {
+                let selector = self.parse_or()?;
+                ensure!(
+                    self.next_is(&Token::Close),
+                    "Invalid selector \"{}\", missing closing parenthesis",
+                    self.input
+                );
+                self.position += 1;
+                Ok(selector)
+            }
+            Token::Word(word) => Selector::parse_word(word),
+            _ => bail!(
+                "Invalid selector \"{}\", unexpected operator or parenthesis",
+                self.input
+            ),
+        }
+    }
+}
+
+impl FromStr for Selector {
+    type Err = Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        let mut parser = Parser {
+            input: s,
+            tokens: tokenize(s)?,
+            position: 0,
+        };
+
+        let selector = parser.parse_or()?;
+
+        ensure!(
+            parser.position == parser.tokens.len(),
+            "Invalid selector \"{}\", unexpected text after the end of the expression",
+            s
+        );
+
+        Ok(selector)
+    }
+}
+
+impl Display for Selector {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Selector::Type(pattern) => write!(f, "{}", pattern),
+            Selector::Index { start, end } => match end {
+                Some(end) if start.checked_add(1) == Some(*end) => write!(f, "#{}", start),
+                Some(end) => write!(f, "#{}..{}", start, end),
+                None => write!(f, "#{}..", start),
+            },
+            Selector::Flag(flag) => write!(f, "{}", flag.keyword()),
+            Selector::Data(data) => {
+                let data = String::from_utf8_lossy(data)
+                    .replace('\\', "\\\\")
+                    .replace('"', "\\\"");
+                write!(f, "data:\"{}\"", data)
+            }
+            Selector::Not(selector) => {
+                write!(f, "!")?;
+                selector.fmt_operand(f, 2)
+            }
+            Selector::And(a, b) => {
+                a.fmt_operand(f, 1)?;
+                write!(f, " && ")?;
+                b.fmt_operand(f, 2)
+            }
+            Selector::Or(a, b) => {
+                a.fmt_operand(f, 0)?;
+                write!(f, " || ")?;
+                b.fmt_operand(f, 1)
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::chunk_type::ChunkType;
+
+    fn chunk(chunk_type: &str, data: &str) -> Chunk {
+        Chunk::new(
+            ChunkType::from_str(chunk_type).unwrap(),
+            data.bytes().collect(),
+        )
+    }
+
+    #[test]
+    fn test_type_selector() {
+        let selector = Selector::from_str("ruSt").unwrap();
+        assert!(selector.matches(0, &chunk("ruSt", "")));
+        assert!(!selector.matches(0, &chunk("ruSa", "")));
+    }
+
+    #[test]
+    fn test_type_glob_selector() {
+        let selector = Selector::from_str("ru??").unwrap();
+        assert!(selector.matches(0, &chunk("ruSt", "")));
+        assert!(!selector.matches(0, &chunk("RuSt", "")));
+
+        let selector = Selector::from_str("*t").unwrap();
+        assert!(selector.matches(0, &chunk("ruSt", "")));
+        assert!(!selector.matches(0, &chunk("ruSa", "")));
+    }
+
+    #[test]
+    fn test_invalid_type_selector() {
+        assert!(Selector::from_str("ru?").is_err());
+        assert!(Selector::from_str("ru1t").is_err());
+    }
+
+    #[test]
+    fn test_index_selector() {
+        let c = chunk("ruSt", "");
+
+        let selector = Selector::from_str("#2").unwrap();
+        assert!(!selector.matches(1, &c));
+        assert!(selector.matches(2, &c));
+        assert!(!selector.matches(3, &c));
+
+        let selector = Selector::from_str("#1..3").unwrap();
+        assert!(selector.matches(1, &c));
+        assert!(selector.matches(2, &c));
+        assert!(!selector.matches(3, &c));
+
+        let selector = Selector::from_str("#1..=3").unwrap();
+        assert!(selector.matches(3, &c));
+        assert!(!selector.matches(4, &c));
+
+        let selector = Selector::from_str("#2..").unwrap();
+        assert!(!selector.matches(1, &c));
+        assert!(selector.matches(100, &c));
+    }
+
+    #[test]
+    fn test_index_selector_overflow() {
+        let max = usize::MAX;
+
+        assert!(Selector::from_str(&format!("#{}", max)).is_err());
+        assert!(Selector::from_str(&format!("#0..={}", max)).is_err());
+        assert!(Selector::from_str("#18446744073709551616").is_err());
+
+        let selector = Selector::from_str(&format!("#{}..{}", max, max)).unwrap();
+        assert_eq!(selector.to_string(), format!("#{}..{}", max, max));
+    }
+
+    #[test]
+    fn test_flag_selector() {
+        let selector = Selector::from_str("private && ancillary").unwrap();
+        assert!(selector.matches(0, &chunk("ruSt", "")));
+        assert!(!selector.matches(0, &chunk("RuSt", "")));
+        assert!(!selector.matches(0, &chunk("rUSt", "")));
+    }
+
+    #[test]
+    fn test_data_selector() {
+        let c = chunk("ruSt", "Hi there!");
+        assert!(Selector::from_str("data:there").unwrap().matches(0, &c));
+        assert!(Selector::from_str("data:\"Hi there!\"")
+            .unwrap()
+            .matches(0, &c));
+        assert!(!Selector::from_str("data:bye").unwrap().matches(0, &c));
+    }
+
+    #[test]
+    fn test_data_selector_escapes() {
+        let c = chunk("ruSt", r#"say "hi" \o/"#);
+        let selector = Selector::from_str(r#"data:"\"hi\" \\o/""#).unwrap();
+
+        assert_eq!(selector, Selector::Data(br#""hi" \o/"#.to_vec()));
+        assert!(selector.matches(0, &c));
+        assert_eq!(Selector::from_str(&selector.to_string()).unwrap(), selector);
+        assert!(Selector::from_str(r#"data:"open\""#).is_err());
+    }
+
+    #[test]
+    fn test_operator_precedence() {
+        let selector = Selector::from_str("ruSt || RuSt && !critical").unwrap();
+        assert!(selector.matches(0, &chunk("ruSt", "")));
+        assert!(!selector.matches(0, &chunk("RuSt", "")));
+
+        let selector = Selector::from_str("(ruSt || RuSt) && !critical").unwrap();
+        assert!(selector.matches(0, &chunk("ruSt", "")));
+        assert!(!selector.matches(0, &chunk("RuSt", "")));
+    }
+
+    #[test]
+    fn test_invalid_expressions() {
+        assert!(Selector::from_str("").is_err());
+        assert!(Selector::from_str("ruSt &&").is_err());
+        assert!(Selector::from_str("(ruSt").is_err());
+        assert!(Selector::from_str("ruSt RuSt").is_err());
+        assert!(Selector::from_str("ruSt & RuSt").is_err());
+        assert!(Selector::from_str("data:\"unclosed").is_err());
+    }
+
+    #[test]
+    fn test_selector_string() {
+        for s in [
+            "ru??",
+            "#1..3",
+            "private && !(critical || #0)",
+            "(ruSt || RuSt) && #2..",
+            r#"data:"a \"quoted\" \\ text""#,
+        ] {
+            let selector = Selector::from_str(s).unwrap();
+            assert_eq!(selector.to_string(), s);
+        }
+    }
+}
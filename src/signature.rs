@@ -0,0 +1,130 @@
+use std::fmt::Display;
+
+use crate::chunk_type::ChunkType;
+
+/// The formats sharing the chunk layout of PNG (length, chunk type, data and CRC), told apart by
+/// the 8 bytes at the start of the file
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Signature {
+    /// Portable Network Graphics, including animated PNG (APNG)
+    Png,
+
+    /// Multiple-image Network Graphics, for animations
+    Mng,
+
+    /// JPEG Network Graphics, JPEG images with PNG-style chunks and an optional alpha channel
+    Jng,
+}
+
+impl Signature {
+    pub const ALL: [Signature; 3] = [Signature::Png, Signature::Mng, Signature::Jng];
+
+    const PNG_BYTES: [u8; 8] = [137, 80, 78, 71, 13, 10, 26, 10];
+    const MNG_BYTES: [u8; 8] = [138, 77, 78, 71, 13, 10, 26, 10];
+    const JNG_BYTES: [u8; 8] = [139, 74, 78, 71, 13, 10, 26, 10];
+
+    pub const fn bytes(&self) -> &'static [u8; 8] {
+        match self {
+            Signature::Png => &Self::PNG_BYTES,
+            Signature::Mng => &Self::MNG_BYTES,
+            Signature::Jng => &Self::JNG_BYTES,
+        }
+    }
+
+    /// Detects the format from the first 8 bytes of a file
+    pub fn from_bytes(bytes: &[u8; 8]) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|signature| signature.bytes() == bytes)
+    }
+
+    /// Chunk type of the first chunk, describing the image or animation
+    pub fn header_chunk_type(&self) -> &'static [u8; 4] {
+        match self {
+            Signature::Png => b"IHDR",
+            Signature::Mng => b"MHDR",
+            Signature::Jng => b"JHDR",
+        }
+    }
+
+    /// Chunk type of the last chunk
+    pub fn end_chunk_type(&self) -> &'static [u8; 4] {
+        match self {
+            Signature::Png | Signature::Jng => b"IEND",
+            Signature::Mng => b"MEND",
+        }
+    }
+
+    /// Checks if chunks of this type hold image data, which shouldn't be split by other chunks.
+    /// MNG files embed PNG and JNG images, so they can hold the image data of both.
+    pub fn is_image_data(&self, chunk_type: &ChunkType) -> bool {
+        let chunk_type = chunk_type.bytes();
+        match self {
+            Signature::Png => matches!(&chunk_type, b"IDAT" | b"fdAT"),
+            Signature::Mng | Signature::Jng => {
+                matches!(&chunk_type, b"IDAT" | b"JDAT" | b"JDAA" | b"JSEP")
+            }
+        }
+    }
+
+    /// Checks if the format requires chunks of this type, so they should never be removed: the
+    /// header and end chunks, and the image data of standalone images
+    pub fn is_required(&self, chunk_type: &ChunkType) -> bool {
+        let bytes = chunk_type.bytes();
+        if &bytes == self.header_chunk_type() || &bytes == self.end_chunk_type() {
+            return true;
+        }
+
+        match self {
+            Signature::Png => &bytes == b"IDAT",
+            Signature::Mng => false,
+            Signature::Jng => &bytes == b"JDAT",
+        }
+    }
+}
+
+impl Display for Signature {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let name = match self {
+            Signature::Png => "PNG",
+            Signature::Mng => "MNG",
+            Signature::Jng => "JNG",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::str::FromStr;
+
+    #[test]
+    fn test_from_bytes() {
+        for signature in Signature::ALL {
+            assert_eq!(Signature::from_bytes(signature.bytes()), Some(signature));
+        }
+
+        assert_eq!(
+            Signature::from_bytes(b"\x8aMNG\r\n\x1a\n"),
+            Some(Signature::Mng)
+        );
+        assert_eq!(Signature::from_bytes(b"\x89PNG\r\n\x1a\x00"), None);
+    }
+
+    #[test]
+    fn test_chunk_rules() {
+        let chunk_type = |s| ChunkType::from_str(s).unwrap();
+
+        assert!(Signature::Mng.is_required(&chunk_type("MHDR")));
+        assert!(Signature::Mng.is_required(&chunk_type("MEND")));
+        assert!(!Signature::Mng.is_required(&chunk_type("IEND")));
+        assert!(Signature::Jng.is_required(&chunk_type("JDAT")));
+        assert!(!Signature::Png.is_required(&chunk_type("JDAT")));
+        assert!(!Signature::Png.is_required(&chunk_type("ruSt")));
+
+        assert!(Signature::Jng.is_image_data(&chunk_type("JDAA")));
+        assert!(Signature::Png.is_image_data(&chunk_type("fdAT")));
+        assert!(!Signature::Jng.is_image_data(&chunk_type("fdAT")));
+    }
+}